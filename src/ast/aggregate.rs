@@ -0,0 +1,163 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+use Bindings;
+use ast::{Aggregate, ArithOp, Clause, Expr, Literal, Term, Value};
+use ast::arith::{as_float, compare};
+use errors::Result;
+
+impl Clause {
+    /// Returns whether the clause has any aggregates in its head.
+    pub fn is_aggregate(&self) -> bool {
+        !self.2.is_empty()
+    }
+
+    /// Replaces the arguments of a goal at the positions of the clause's
+    /// aggregates with the aggregated variables, so that the goal can be used
+    /// to instantiate the clause. The aggregated values can only be matched
+    /// against the goal once they have been computed, by `aggregate`.
+    pub fn group_goal(&self, goal: &Literal) -> Literal {
+        let mut goal = goal.clone();
+        for &(i, _) in &self.2 {
+            goal.1[i] = self.head().1[i].clone();
+        }
+        goal
+    }
+
+    /// Computes the head tuples of an aggregate clause from the solutions to
+    /// its body. The solutions are grouped by the values of the head's
+    /// non-aggregated arguments, and each aggregate combines the values of
+    /// its variable over the distinct solutions in a group.
+    pub fn aggregate<I>(&self, solutions: I) -> Result<Vec<Vec<Value>>>
+    where
+        I: IntoIterator<Item = Bindings>,
+    {
+        let solutions = solutions.into_iter().collect::<BTreeSet<_>>();
+        let mut groups = BTreeMap::new();
+        for bindings in solutions {
+            let args = match self.head().substitute(&bindings).as_ground() {
+                Some(args) => args,
+                None => continue,
+            };
+            let (mut key, mut values) = (Vec::new(), Vec::new());
+            let mut aggregates = self.2.iter().peekable();
+            for (i, arg) in args.into_iter().enumerate() {
+                if aggregates.peek().map(|&&(j, _)| j) == Some(i) {
+                    aggregates.next();
+                    values.push(arg);
+                } else {
+                    key.push(arg);
+                }
+            }
+            let group = groups
+                .entry(key)
+                .or_insert_with(|| vec![Vec::new(); self.2.len()]);
+            for (column, value) in group.iter_mut().zip(values) {
+                column.push(value);
+            }
+        }
+
+        let mut tuples = Vec::new();
+        for (key, columns) in groups {
+            let mut key = key.into_iter();
+            let mut aggregates = self.2.iter().zip(columns).peekable();
+            let mut tuple = Vec::new();
+            for i in 0..self.head().1.len() {
+                if aggregates.peek().map(|&(&(j, _), _)| j) == Some(i) {
+                    let (&(_, aggregate), values) = aggregates.next().unwrap();
+                    tuple.push(aggregate.eval(&values)?);
+                } else {
+                    tuple.push(key.next().unwrap());
+                }
+            }
+            tuples.push(tuple);
+        }
+        Ok(tuples)
+    }
+}
+
+impl Aggregate {
+    /// Combines a non-empty list of values. Every aggregate other than
+    /// `count` requires the values to be numbers.
+    pub fn eval(self, values: &[Value]) -> Result<Value> {
+        let number =
+            |v: &Value| Expr::Term(Term::Value(v.clone(), None)).eval();
+        let sum = || -> Result<Value> {
+            let mut sum = number(&values[0])?;
+            for value in &values[1..] {
+                let l = Box::new(Expr::Term(Term::Value(sum, None)));
+                let r = Box::new(Expr::Term(Term::Value(value.clone(), None)));
+                sum = Expr::Op(l, ArithOp::Add, r).eval()?;
+            }
+            Ok(sum)
+        };
+        let extreme = |wanted: Ordering| -> Result<Value> {
+            let mut best = number(&values[0])?;
+            for value in &values[1..] {
+                let value = number(value)?;
+                if compare(&value, &best) == Some(wanted) {
+                    best = value;
+                }
+            }
+            Ok(best)
+        };
+
+        match self {
+            Aggregate::Count => Ok(Value::Int(values.len() as i64)),
+            Aggregate::Sum => sum(),
+            Aggregate::Min => extreme(Ordering::Less),
+            Aggregate::Max => extreme(Ordering::Greater),
+            Aggregate::Avg => {
+                Ok(Value::float(as_float(&sum()?) / values.len() as f64))
+            }
+        }
+    }
+}
+
+#[test]
+fn aggregate() {
+    use ast::Statement;
+
+    let clause = |s: &str| match s.parse().unwrap() {
+        Statement::Assertion(c) => c,
+        _ => unreachable!(),
+    };
+    let solutions = |pairs: &[(&str, Value)]| {
+        pairs
+            .iter()
+            .map(|&(x, ref y)| {
+                let (x, y) = (Value::new(x).unwrap(), y.clone());
+                let mut bindings = Bindings::new();
+                bindings.insert(::Variable::new("X").unwrap(), x);
+                bindings.insert(::Variable::new("Y").unwrap(), y);
+                bindings
+            })
+            .collect::<Vec<_>>()
+    };
+    let values = vec![
+        ("a", Value::Int(3)),
+        ("a", Value::Float(1.5)),
+        ("a", Value::Int(3)),
+        ("b", Value::Int(-2)),
+    ];
+
+    let c = clause(concat!(
+        "p(X, count<Y>, sum<Y>, min<Y>, max<Y>, avg<Y>) :-\n",
+        "    q(X, Y).",
+    ));
+    let tuples = c.aggregate(solutions(&values)).unwrap();
+    let show = |tuple: &Vec<Value>| {
+        tuple.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" ")
+    };
+    assert_eq!(
+        tuples.iter().map(show).collect::<Vec<_>>(),
+        vec!["a 2 4.5 1.5 3 2.25", "b 1 -2 -2 -2 -2.0"]
+    );
+
+    let c = clause("p(sum<Y>) :- q(X, Y).");
+    let err = c.aggregate(solutions(&[("a", Value::string("1"))]));
+    assert_eq!(
+        err.unwrap_err().to_string(),
+        "The value \"1\" is not a number"
+    );
+}
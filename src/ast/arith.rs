@@ -0,0 +1,189 @@
+use std::cmp::Ordering;
+
+use Bindings;
+use ast::{ArithOp, CmpOp, Expr, Term, Value};
+use errors::{ErrorKind, Result};
+
+impl Expr {
+    /// Evaluates the expression to a number. Every variable in it should have
+    /// been substituted.
+    pub fn eval(&self) -> Result<Value> {
+        match *self {
+            Expr::Term(Term::Value(ref v, _)) => match *v {
+                Value::Int(_) | Value::Float(_) => Ok(v.clone()),
+                _ => Err(ErrorKind::NotANumber(v.clone()).into()),
+            },
+            Expr::Term(Term::Var(ref v, _)) => {
+                let msg = format!("the variable {} is unbound", v);
+                Err(ErrorKind::Arithmetic(self.clone(), msg).into())
+            }
+            Expr::Op(ref l, op, ref r) => {
+                let error = |msg: &str| -> Result<Value> {
+                    Err(ErrorKind::Arithmetic(self.clone(), msg.into()).into())
+                };
+                let (l, r) = (l.eval()?, r.eval()?);
+                let divides = op == ArithOp::Div || op == ArithOp::Mod;
+                // Floats are compared numerically, so that -0.0 is zero too.
+                if divides && as_float(&r) == 0.0 {
+                    return error("division by zero");
+                }
+
+                if let (&Value::Int(l), &Value::Int(r)) = (&l, &r) {
+                    let result = match op {
+                        ArithOp::Add => l.checked_add(r),
+                        ArithOp::Sub => l.checked_sub(r),
+                        ArithOp::Mul => l.checked_mul(r),
+                        ArithOp::Div => l.checked_div(r),
+                        ArithOp::Mod => l.checked_rem_euclid(r),
+                    };
+                    return match result {
+                        Some(n) => Ok(Value::Int(n)),
+                        None => error("overflow"),
+                    };
+                }
+
+                let (l, r) = (as_float(&l), as_float(&r));
+                let result = match op {
+                    ArithOp::Add => l + r,
+                    ArithOp::Sub => l - r,
+                    ArithOp::Mul => l * r,
+                    ArithOp::Div => l / r,
+                    ArithOp::Mod => l.rem_euclid(r),
+                };
+                if result.is_finite() {
+                    Ok(Value::float(result))
+                } else {
+                    error("overflow")
+                }
+            }
+            Expr::Neg(ref e) => match e.eval()? {
+                Value::Int(n) => match n.checked_neg() {
+                    Some(n) => Ok(Value::Int(n)),
+                    None => {
+                        let msg = "overflow".into();
+                        Err(ErrorKind::Arithmetic(self.clone(), msg).into())
+                    }
+                },
+                v => Ok(Value::float(-as_float(&v))),
+            },
+        }
+    }
+
+    /// Evaluates the expression to a value. A lone value is returned as-is,
+    /// so it need not be a number.
+    fn value(&self) -> Result<Value> {
+        match *self {
+            Expr::Term(Term::Value(ref v, _)) => Ok(v.clone()),
+            _ => self.eval(),
+        }
+    }
+}
+
+/// Converts a number to a float.
+pub(super) fn as_float(value: &Value) -> f64 {
+    match *value {
+        Value::Int(n) => n as f64,
+        Value::Float(x) => x,
+        _ => unreachable!("{} is not a number", value),
+    }
+}
+
+/// Compares two numbers. Integers are compared exactly with each other, and
+/// as floats with floats.
+pub(super) fn compare(l: &Value, r: &Value) -> Option<Ordering> {
+    match (l, r) {
+        (&Value::Int(l), &Value::Int(r)) => Some(l.cmp(&r)),
+        _ => as_float(l).partial_cmp(&as_float(r)),
+    }
+}
+
+impl CmpOp {
+    /// Evaluates a comparison between two expressions, which should have had
+    /// their bound variables substituted. If the comparison holds, returns the
+    /// bindings it makes; the only comparison that makes any is an equality
+    /// with an unbound variable on one side, which binds it to the value of
+    /// the other side. Equality and inequality between two values that aren't
+    /// both numbers compare the values; every other comparison is between
+    /// numbers.
+    pub fn eval(self, l: &Expr, r: &Expr) -> Result<Option<Bindings>> {
+        if self == CmpOp::Eq {
+            for &(var, value) in &[(l, r), (r, l)] {
+                if let Expr::Term(Term::Var(ref v, _)) = *var {
+                    let mut bindings = Bindings::new();
+                    bindings.insert(v.clone(), value.value()?);
+                    return Ok(Some(bindings));
+                }
+            }
+        }
+
+        let is_number = |v: &Value| match *v {
+            Value::Int(_) | Value::Float(_) => true,
+            _ => false,
+        };
+        let (l, r) = match self {
+            CmpOp::Eq | CmpOp::Ne => (l.value()?, r.value()?),
+            _ => (l.eval()?, r.eval()?),
+        };
+        let holds = if is_number(&l) && is_number(&r) {
+            let ord = compare(&l, &r);
+            match self {
+                CmpOp::Eq => ord == Some(Ordering::Equal),
+                CmpOp::Ne => ord != Some(Ordering::Equal),
+                CmpOp::Lt => ord == Some(Ordering::Less),
+                CmpOp::Le => ord.map_or(false, |o| o != Ordering::Greater),
+                CmpOp::Gt => ord == Some(Ordering::Greater),
+                CmpOp::Ge => ord.map_or(false, |o| o != Ordering::Less),
+            }
+        } else {
+            (l == r) == (self == CmpOp::Eq)
+        };
+        Ok(if holds { Some(Bindings::new()) } else { None })
+    }
+}
+
+#[test]
+fn comparisons() {
+    use ast::{Goal, Statement};
+
+    let eval = |s: &str| -> Result<Option<Vec<String>>> {
+        let goal = match s.parse().unwrap() {
+            Statement::Assertion(c) => c.1[0].clone(),
+            _ => unreachable!(),
+        };
+        match goal {
+            Goal::Compare(ref l, op, ref r) => Ok(op.eval(l, r)?
+                .map(|b| b.values().map(|n| n.to_string()).collect())),
+            _ => unreachable!(),
+        }
+    };
+    let kind = |s: &str| eval(s).unwrap_err().kind().to_string();
+
+    assert_eq!(eval("p :- (2 * 3) + 1 = Y.").unwrap(), Some(vec!["7".into()]));
+    assert_eq!(eval("p :- Y = 7 mod -3.").unwrap(), Some(vec!["1".into()]));
+    assert_eq!(eval("p :- 1 - 3 = -2.").unwrap(), Some(vec![]));
+    assert_eq!(eval("p :- Y = 7-2*3.").unwrap(), Some(vec!["1".into()]));
+    assert_eq!(
+        eval("p :- Y = -(2.5 * 2).").unwrap(),
+        Some(vec!["-5.0".into()])
+    );
+    assert_eq!(eval("p :- 10 / 3 > 3.").unwrap(), None);
+    assert_eq!(eval("p :- 10 / 3 >= 3.").unwrap(), Some(vec![]));
+    assert_eq!(eval("p :- apple != pepper.").unwrap(), Some(vec![]));
+    assert_eq!(eval("p :- Y = apple.").unwrap(), Some(vec!["apple".into()]));
+    assert_eq!(eval("p :- Y = 'x-1'.").unwrap(), Some(vec!["x-1".into()]));
+    assert_eq!(eval("p :- 1 = 1.0.").unwrap(), Some(vec![]));
+    assert_eq!(eval("p :- 1 = \"1\".").unwrap(), None);
+    assert_eq!(eval("p :- Y = 7 / 2.0.").unwrap(), Some(vec!["3.5".into()]));
+    assert_eq!(eval("p :- Y = 0.0 * -1.").unwrap(), Some(vec!["0.0".into()]));
+    assert_eq!(eval("p :- 0.1 + 0.2 > 0.3.").unwrap(), Some(vec![]));
+    assert_eq!(kind("p :- apple < 1."), "The value apple is not a number");
+    assert_eq!(kind("p :- \"1\" < 1."), "The value \"1\" is not a number");
+    assert_eq!(
+        kind("p :- 1 / (2 - 2) = Y."),
+        "Could not evaluate 1 / (2 - 2): division by zero"
+    );
+    assert_eq!(
+        kind("p :- 1 mod -0.0 = Y."),
+        "Could not evaluate 1 mod 0.0: division by zero"
+    );
+}
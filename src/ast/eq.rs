@@ -1,8 +1,108 @@
-use ast::Clause;
+use std::collections::BTreeMap;
+
+use ast::{Clause, Expr, Goal, Literal, Term, Value, Variable};
 
 impl Clause {
     /// Returns whether two expressions are equal up to alpha-conversion.
     pub fn alpha_eq(&self, other: &Clause) -> bool {
-        unimplemented!()
+        let Clause(ref lhead, ref lbody, ref laggs, _) = *self;
+        let Clause(ref rhead, ref rbody, ref raggs, _) = *other;
+        if lbody.len() != rbody.len() || laggs != raggs {
+            return false;
+        }
+
+        let mut renaming = Renaming::default();
+        renaming.literal(lhead, rhead)
+            && lbody.iter().zip(rbody).all(|(l, r)| renaming.goal(l, r))
+    }
+
+    /// Returns whether retracting this clause removes the given fact, which
+    /// it does if this clause is a fact that can be instantiated to it.
+    pub fn retracts_fact(&self, fact: &[Value]) -> bool {
+        self.body().is_empty()
+            && self.head().try_instantiate_fact(fact).is_some()
+    }
+
+    /// Returns whether retracting this clause removes the given clause: any
+    /// clause equal to it up to alpha-conversion, and, if it is a fact, any
+    /// fact it can be instantiated to.
+    pub fn retracts(&self, clause: &Clause) -> bool {
+        self.alpha_eq(clause)
+            || clause.as_fact().map_or(false, |f| self.retracts_fact(&f))
+    }
+}
+
+/// A bijection between the variables of two clauses.
+#[derive(Default)]
+struct Renaming {
+    forward: BTreeMap<Variable, Variable>,
+    backward: BTreeMap<Variable, Variable>,
+}
+
+impl Renaming {
+    fn goal(&mut self, l: &Goal, r: &Goal) -> bool {
+        match (l, r) {
+            (&Goal::Literal(ref l), &Goal::Literal(ref r))
+            | (&Goal::Not(ref l), &Goal::Not(ref r)) => self.literal(l, r),
+            (
+                &Goal::Compare(ref ll, lop, ref lr),
+                &Goal::Compare(ref rl, rop, ref rr),
+            ) => lop == rop && self.expr(ll, rl) && self.expr(lr, rr),
+            _ => false,
+        }
+    }
+
+    fn expr(&mut self, l: &Expr, r: &Expr) -> bool {
+        match (l, r) {
+            (&Expr::Term(ref l), &Expr::Term(ref r)) => self.term(l, r),
+            (
+                &Expr::Op(ref ll, lop, ref lr),
+                &Expr::Op(ref rl, rop, ref rr),
+            ) => lop == rop && self.expr(ll, rl) && self.expr(lr, rr),
+            (&Expr::Neg(ref l), &Expr::Neg(ref r)) => self.expr(l, r),
+            _ => false,
+        }
+    }
+
+    fn literal(&mut self, l: &Literal, r: &Literal) -> bool {
+        let Literal(ref lpred, ref largs, _) = *l;
+        let Literal(ref rpred, ref rargs, _) = *r;
+        lpred == rpred
+            && largs.len() == rargs.len()
+            && largs.iter().zip(rargs).all(|(l, r)| self.term(l, r))
     }
+
+    fn term(&mut self, l: &Term, r: &Term) -> bool {
+        match (l, r) {
+            (&Term::Value(ref l, _), &Term::Value(ref r, _)) => l == r,
+            (&Term::Var(ref l, _), &Term::Var(ref r, _)) => {
+                let f =
+                    self.forward.entry(l.clone()).or_insert_with(|| r.clone());
+                let b =
+                    self.backward.entry(r.clone()).or_insert_with(|| l.clone());
+                f == r && b == l
+            }
+            _ => false,
+        }
+    }
+}
+
+#[test]
+fn alpha_eq() {
+    let parse = |s: &str| match s.parse().unwrap() {
+        ::Statement::Assertion(c) => c,
+        _ => unreachable!(),
+    };
+
+    let c = parse("path(X, Y) :- edge(X, Z), path(Z, Y).");
+    assert!(c.alpha_eq(&parse("path(A, B) :- edge(A, C), path(C, B).")));
+    assert!(!c.alpha_eq(&parse("path(A, B) :- edge(A, A), path(A, B).")));
+    assert!(!c.alpha_eq(&parse("path(A, B) :- edge(A, C), path(B, C).")));
+    assert!(!c.alpha_eq(&parse("path(X, Y) :- edge(X, Y).")));
+    assert!(!c.alpha_eq(&parse("path(A, B) :- edge(A, C), not path(C, B).")));
+    let c = parse("next(X, Y) :- num(X), Y = X + 1.");
+    assert!(c.alpha_eq(&parse("next(A, B) :- num(A), B = A + 1.")));
+    assert!(!c.alpha_eq(&parse("next(A, B) :- num(A), B = A - 1.")));
+    assert!(parse("edge(X, X).").alpha_eq(&parse("edge(Y, Y).")));
+    assert!(!parse("edge(X, X).").alpha_eq(&parse("edge(X, Y).")));
 }
@@ -1,12 +1,18 @@
 //! The basic Datalog AST.
 
+mod aggregate;
+mod arith;
 mod eq;
 mod name;
 mod pattern_match;
 mod print;
+mod safety;
+mod span;
 pub mod styles;
+mod value;
 mod variable;
 
+use std::hash::{Hash, Hasher};
 use std::path::Path;
 use std::str::FromStr;
 
@@ -15,6 +21,9 @@ use errors::{Error, Result};
 
 pub use self::name::Name;
 use self::pattern_match::pattern_match;
+pub(crate) use self::safety::schedule_goals;
+pub use self::span::Span;
+pub use self::value::{Type, Value};
 pub use self::variable::Variable;
 
 /// A complete program; really just a bunch of statements.
@@ -26,6 +35,37 @@ impl Program {
     pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<Program> {
         ::parser::parse_program_file(path)
     }
+
+    /// Parses a program from a string, giving the spans in it the given file
+    /// name, as if it had been read from that file.
+    pub fn parse_source(src: &str, file: &str) -> Result<Program> {
+        ::parser::parse_program_source(src, Some(file))
+    }
+}
+
+impl Program {
+    /// Rewrites the program with magic sets, so that a bottom-up interpreter
+    /// only derives the facts relevant to the given query. The result keeps
+    /// the program's facts, replaces its rules with the rewritten ones, and
+    /// ends with the rewritten query.
+    pub fn magic_sets(&self, query: &Literal) -> Program {
+        ::magic::rewrite_program(self, query)
+    }
+
+    /// Infers the type of each column of each predicate in the program, from
+    /// its declarations and clauses. Also returns an error for each value
+    /// that is used as two different types.
+    pub fn infer_types(&self) -> (::Types, Vec<Error>) {
+        ::schema::infer_types(self)
+    }
+
+    /// Looks for likely mistakes in the program without running it, such as
+    /// predicates that are used but never defined, or clauses that are
+    /// unsafe or have type errors. If the program has queries, rules that
+    /// none of them use are reported too.
+    pub fn lint(&self) -> Vec<::Lint> {
+        ::lint::lint(self)
+    }
 }
 
 impl FromStr for Program {
@@ -35,11 +75,13 @@ impl FromStr for Program {
     }
 }
 
-/// A statement in a program, either an assertion, retraction, or query.
+/// A statement in a program, either a declaration, assertion, retraction, or
+/// query.
 ///
 /// For example, each of the following is a statement:
 ///
 /// ```datalog
+/// .decl red(x: symbol)
 /// red(apple).
 /// fruit(apple).
 ///
@@ -55,6 +97,9 @@ impl FromStr for Program {
 /// ```
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Statement {
+    /// A declaration of the arity and column types of a predicate.
+    Declaration(Declaration),
+
     /// An assertion that a clause is true.
     Assertion(Clause),
 
@@ -62,8 +107,9 @@ pub enum Statement {
     Retraction(Clause),
 
     /// A query, which produces all possible instantiations of its variables,
-    /// or simply `true`.
-    Query(Literal),
+    /// or simply `true`. A query with several goals, separated by commas,
+    /// holds when all of them do.
+    Query(Vec<Goal>),
 }
 
 impl FromStr for Statement {
@@ -85,54 +131,97 @@ impl FromStr for Statement {
 /// spicy(X) :-
 ///     red(X),
 ///     vegetable(X)
+///
+/// bland(X) :-
+///     fruit(X),
+///     not spicy(X)
+///
+/// next(X, Y) :-
+///     num(X),
+///     X + 1 = Y
+///
+/// degree(X, count<Y>) :-
+///     edge(X, Y)
 /// ```
 ///
 /// Note the lack of trailing `.`, `~`, or `?` on clauses; adding these marks
 /// makes the clause an assertion, retraction, or query (respectively), all of
 /// which are statements.
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Clause(pub Literal, pub Vec<Literal>);
+///
+/// The third field holds the aggregates in the head, by argument position,
+/// in order. The head itself holds the aggregated variable at each of these
+/// positions, so `degree(X, count<Y>)` is stored as `degree(X, Y)` with a
+/// `Count` aggregate at position 1. The last field holds where the clause
+/// was in its source, if it was parsed from one.
+#[derive(Clone, Debug, Eq)]
+pub struct Clause(
+    pub Literal,
+    pub Vec<Goal>,
+    pub Vec<(usize, Aggregate)>,
+    pub Option<Span>,
+);
+
+impl PartialEq for Clause {
+    fn eq(&self, other: &Clause) -> bool {
+        self.0 == other.0 && self.1 == other.1 && self.2 == other.2
+    }
+}
+
+impl Hash for Clause {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+        self.1.hash(state);
+        self.2.hash(state);
+    }
+}
 
 impl Clause {
     /// If this clause is a fact, returns its arguments. Otherwise, returns
     /// `None`.
-    pub fn as_fact(&self) -> Option<Vec<Name>> {
-        let Clause(Literal(_, ref args), ref body) = *self;
+    pub fn as_fact(&self) -> Option<Vec<Value>> {
+        let Clause(ref head, ref body, ..) = *self;
         if body.len() != 0 {
             return None;
         }
-
-        let mut names = Vec::new();
-        for arg in args {
-            if let Term::Name(ref n) = *arg {
-                names.push(n);
-            } else {
-                return None;
-            }
-        }
-        Some(names.into_iter().cloned().collect())
+        head.as_ground()
     }
 
-    /// Tries to instantiate the clause against a literal. Returns the literals
-    /// that compose the body of the resulting literal.
-    pub fn try_instantiate(&self, literal: Literal) -> Option<Vec<Literal>> {
-        let Clause(head, body) = self.clone();
+    /// Tries to instantiate the clause against a literal. Returns the bindings
+    /// the literal imposes on the clause's variables, and the goals that
+    /// compose the body of the resulting clause, with those bindings applied.
+    pub fn try_instantiate(
+        &self,
+        literal: Literal,
+    ) -> Option<(Bindings, Vec<Goal>)> {
+        let Clause(head, body, ..) = self.clone();
         if head.signature() != literal.signature() {
             return None;
         }
-        let Literal(name, mut hargs) = head;
-        let Literal(_, mut largs) = literal;
+        let Literal(_, mut hargs, _) = head;
+        let Literal(_, mut largs, _) = literal;
 
         let (hb, _) = try_opt!(pattern_match(&mut hargs, &mut largs));
-        unimplemented!("{:?}", hb)
+        let body = body.into_iter().map(|g| g.substitute(&hb)).collect();
+        Some((hb, body))
     }
 
     /// Returns the head of the clause.
     pub fn head(&self) -> &Literal {
-        let Clause(ref head, _) = *self;
+        let Clause(ref head, ..) = *self;
         head
     }
 
+    /// Returns the body of the clause.
+    pub fn body(&self) -> &[Goal] {
+        let Clause(_, ref body, ..) = *self;
+        body
+    }
+
+    /// Returns where the clause was in its source, if it was parsed from one.
+    pub fn span(&self) -> Option<Span> {
+        self.3
+    }
+
     /// Returns the name and arity of the predicate this rule is for.
     ///
     /// For the clause `path(X, Y) :- path(X, Z), edge(Z, Y)`, this returns
@@ -142,60 +231,351 @@ impl Clause {
     }
 }
 
-/// A literal term, for example `foo`, `bar(X)`, or `baz(quux(X, 2), X)`.
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Literal(pub Name, pub Vec<Term>);
+/// A declaration of a predicate, for example
+/// `.decl edge(src: symbol, dst: symbol)`, which gives its arity, and the name
+/// and type of each of its columns. Clauses using a declared predicate are
+/// checked against its declaration.
+#[derive(Clone, Debug, Eq)]
+pub struct Declaration(pub Name, pub Vec<(Name, Type)>, pub Option<Span>);
+
+impl PartialEq for Declaration {
+    fn eq(&self, other: &Declaration) -> bool {
+        self.0 == other.0 && self.1 == other.1
+    }
+}
+
+impl Declaration {
+    /// Returns the name and arity of the declared predicate.
+    pub fn pred(&self) -> (Name, usize) {
+        let Declaration(ref name, ref columns, _) = *self;
+        (name.clone(), columns.len())
+    }
+}
+
+/// A goal in the body of a rule, for example `bar(X)`, `not baz(X)`, or
+/// `X * 2 < Y`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Goal {
+    /// A literal, which holds if it can be proven.
+    Literal(Literal),
+
+    /// A negated literal, which holds if the literal cannot be proven. This
+    /// may be written as either `not foo(X)` or `\+ foo(X)`.
+    Not(Literal),
+
+    /// A comparison between two expressions. An equality with a variable on
+    /// one side, such as `X + 1 = Y`, binds the variable if it is not
+    /// already bound.
+    Compare(Expr, CmpOp, Expr),
+}
+
+impl Goal {
+    /// Returns the literal inside the goal, if it is not a comparison.
+    pub fn literal(&self) -> Option<&Literal> {
+        match *self {
+            Goal::Literal(ref l) | Goal::Not(ref l) => Some(l),
+            Goal::Compare(..) => None,
+        }
+    }
+
+    /// Returns whether the goal is negated.
+    pub fn is_negated(&self) -> bool {
+        match *self {
+            Goal::Literal(_) | Goal::Compare(..) => false,
+            Goal::Not(_) => true,
+        }
+    }
+
+    /// Replaces the variables in this goal that have bindings with the values
+    /// they are bound to.
+    pub fn substitute(&self, bindings: &Bindings) -> Goal {
+        match *self {
+            Goal::Literal(ref l) => Goal::Literal(l.substitute(bindings)),
+            Goal::Not(ref l) => Goal::Not(l.substitute(bindings)),
+            Goal::Compare(ref l, op, ref r) => Goal::Compare(
+                l.substitute(bindings),
+                op,
+                r.substitute(bindings),
+            ),
+        }
+    }
+
+    /// Returns the variables in the goal, in order of appearance.
+    pub fn vars(&self) -> Vec<Variable> {
+        let mut vars = Vec::new();
+        let terms: Box<Iterator<Item = &Term>> = match *self {
+            Goal::Literal(ref l) | Goal::Not(ref l) => Box::new(l.1.iter()),
+            Goal::Compare(ref l, _, ref r) => {
+                Box::new(l.terms().into_iter().chain(r.terms()))
+            }
+        };
+        for term in terms {
+            if let Term::Var(ref v, _) = *term {
+                if !vars.contains(v) {
+                    vars.push(v.clone());
+                }
+            }
+        }
+        vars
+    }
+}
+
+/// An arithmetic expression over terms, for example `(X * 2) + 1`. Only
+/// integers and floats can be used in arithmetic; an operation on two
+/// integers gives an integer, and any other operation gives a float. Since
+/// `x-1` would otherwise be a single symbol, a symbol with a hyphen must be
+/// quoted in an expression, as in `'x-1'`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Expr {
+    /// A term.
+    Term(Term),
+
+    /// An arithmetic operator applied to two expressions.
+    Op(Box<Expr>, ArithOp, Box<Expr>),
+
+    /// The negation of an expression, `-X`.
+    Neg(Box<Expr>),
+}
+
+impl Expr {
+    /// Replaces the variables in this expression that have bindings with the
+    /// values they are bound to.
+    pub fn substitute(&self, bindings: &Bindings) -> Expr {
+        match *self {
+            Expr::Term(ref t) => Expr::Term(t.substitute(bindings)),
+            Expr::Op(ref l, op, ref r) => Expr::Op(
+                Box::new(l.substitute(bindings)),
+                op,
+                Box::new(r.substitute(bindings)),
+            ),
+            Expr::Neg(ref e) => Expr::Neg(Box::new(e.substitute(bindings))),
+        }
+    }
+
+    /// Returns the terms in the expression, from left to right.
+    pub fn terms(&self) -> Vec<&Term> {
+        match *self {
+            Expr::Term(ref t) => vec![t],
+            Expr::Op(ref l, _, ref r) => {
+                let mut terms = l.terms();
+                terms.extend(r.terms());
+                terms
+            }
+            Expr::Neg(ref e) => e.terms(),
+        }
+    }
+}
+
+/// An aggregate function, which combines the values a variable takes in the
+/// solutions to a rule's body.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Aggregate {
+    /// The number of solutions, `count`.
+    Count,
+
+    /// The sum of the values, `sum`.
+    Sum,
+
+    /// The least value, `min`.
+    Min,
+
+    /// The greatest value, `max`.
+    Max,
+
+    /// The mean of the values, as a float, `avg`.
+    Avg,
+}
+
+/// An arithmetic operator.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ArithOp {
+    /// Addition, `+`.
+    Add,
+
+    /// Subtraction, `-`.
+    Sub,
+
+    /// Multiplication, `*`.
+    Mul,
+
+    /// Division, `/`. The quotient of two integers is an integer, rounded
+    /// towards zero, so `7 / 2` is `3`; if either is a float, so is the
+    /// quotient, so `7.0 / 2` is `3.5`.
+    Div,
+
+    /// The remainder of division, which is never negative, `mod`.
+    Mod,
+}
+
+/// A comparison operator.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CmpOp {
+    /// Equality, `=`. Numbers are compared by value, so `1 = 1.0` holds,
+    /// although `1` and `1.0` are distinct constants; see `Value`.
+    Eq,
+
+    /// Inequality, `!=`.
+    Ne,
+
+    /// Less than, `<`.
+    Lt,
+
+    /// Less than or equal to, `<=`.
+    Le,
+
+    /// Greater than, `>`.
+    Gt,
+
+    /// Greater than or equal to, `>=`.
+    Ge,
+}
+
+/// A literal term, for example `foo`, `bar(X)`, or `baz(quux(X, 2), X)`,
+/// along with where it was in its source, if it was parsed from one.
+#[derive(Clone, Debug, Eq)]
+pub struct Literal(pub Name, pub Vec<Term>, pub Option<Span>);
+
+impl PartialEq for Literal {
+    fn eq(&self, other: &Literal) -> bool {
+        self.0 == other.0 && self.1 == other.1
+    }
+}
+
+impl Hash for Literal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+        self.1.hash(state);
+    }
+}
 
 impl Literal {
     /// Returns the name and arity of this literal.
     pub fn signature(&self) -> (Name, usize) {
-        let Literal(ref pred, ref args) = *self;
+        let Literal(ref pred, ref args, _) = *self;
         (pred.clone(), args.len())
     }
 
     /// Tries to instantiate this literal to the argument tuple of a fact
     /// describing this literal. Panics if `arg_tuple.len()` is not equal to
     /// the arity of this literal.
-    pub fn try_instantiate_fact(&self, arg_tuple: &[Name]) -> Option<Bindings> {
-        let Literal(_, ref args) = *self;
+    pub fn try_instantiate_fact(
+        &self,
+        arg_tuple: &[Value],
+    ) -> Option<Bindings> {
+        let Literal(_, ref args, _) = *self;
         assert_eq!(args.len(), arg_tuple.len());
         let mut bindings = Bindings::new();
         for i in 0..args.len() {
             let r = &arg_tuple[i];
             match args[i] {
-                Term::Name(ref l) => if l != r {
+                Term::Value(ref l, _) => if l != r {
                     return None;
                 },
-                Term::Var(ref l) => {
+                Term::Var(ref l, _) => if let Some(prev) = bindings.get(l) {
+                    if prev != r {
+                        return None;
+                    }
+                } else {
                     bindings.insert(l.clone(), r.clone());
-                }
+                },
             }
         }
         Some(bindings)
     }
+
+    /// If every argument of this literal is a value, returns the arguments.
+    /// Otherwise, returns `None`.
+    pub fn as_ground(&self) -> Option<Vec<Value>> {
+        let Literal(_, ref args, _) = *self;
+        args.iter()
+            .map(|arg| match *arg {
+                Term::Value(ref v, _) => Some(v.clone()),
+                Term::Var(..) => None,
+            })
+            .collect()
+    }
+
+    /// Replaces the variables in this literal that have bindings with the
+    /// values they are bound to.
+    pub fn substitute(&self, bindings: &Bindings) -> Literal {
+        let Literal(ref pred, ref args, span) = *self;
+        let args = args.iter().map(|arg| arg.substitute(bindings)).collect();
+        Literal(pred.clone(), args, span)
+    }
+
+    /// Returns where the literal was in its source, if it was parsed from
+    /// one.
+    pub fn span(&self) -> Option<Span> {
+        self.2
+    }
 }
 
-/// A term, for example `foo` or `Bar`.
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// A term, for example `foo`, `42`, or `Bar`, along with where it was in its
+/// source, if it was parsed from one.
+#[derive(Clone, Debug, Eq)]
 pub enum Term {
-    /// A name.
-    Name(Name),
+    /// A constant.
+    Value(Value, Option<Span>),
 
     /// A variable.
-    Var(Variable),
+    Var(Variable, Option<Span>),
+}
+
+impl PartialEq for Term {
+    fn eq(&self, other: &Term) -> bool {
+        match (self, other) {
+            (&Term::Value(ref l, _), &Term::Value(ref r, _)) => l == r,
+            (&Term::Var(ref l, _), &Term::Var(ref r, _)) => l == r,
+            _ => false,
+        }
+    }
+}
+
+impl Hash for Term {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match *self {
+            Term::Value(ref value, _) => {
+                0.hash(state);
+                value.hash(state);
+            }
+            Term::Var(ref var, _) => {
+                1.hash(state);
+                var.hash(state);
+            }
+        }
+    }
 }
 
 impl Term {
     /// Creates a new Term from the valid string, trying to parse as both a
-    /// `Name` and a `Variable`.
+    /// `Value` and a `Variable`.
     pub fn new<S: AsRef<str>>(s: S) -> Option<Term> {
         let s = s.as_ref();
-        if let Some(name) = Name::new(s) {
-            Some(Term::Name(name))
+        if let Some(value) = Value::new(s) {
+            Some(Term::Value(value, None))
         } else if let Some(var) = Variable::new(s) {
-            Some(Term::Var(var))
+            Some(Term::Var(var, None))
         } else {
             None
         }
     }
+
+    /// If this term is a variable with a binding, returns the value it is
+    /// bound to. Otherwise, returns the term unchanged.
+    pub fn substitute(&self, bindings: &Bindings) -> Term {
+        match *self {
+            Term::Var(ref v, span) => match bindings.get(v) {
+                Some(value) => Term::Value(value.clone(), span),
+                None => self.clone(),
+            },
+            Term::Value(..) => self.clone(),
+        }
+    }
+
+    /// Returns where the term was in its source, if it was parsed from one.
+    pub fn span(&self) -> Option<Span> {
+        match *self {
+            Term::Value(_, span) | Term::Var(_, span) => span,
+        }
+    }
 }
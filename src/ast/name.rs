@@ -5,6 +5,8 @@ use regex::Regex;
 
 use symbol::Symbol;
 
+use super::value::write_quoted;
+
 /// A name, for example `foo`, `42`, or `"qwerty\nasdf\n\u03bb"`.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Name(Symbol);
@@ -27,6 +29,20 @@ impl Name {
             false
         }
     }
+
+    /// Returns whether the name is an identifier, and so is written without
+    /// quotes.
+    pub fn is_ident(&self) -> bool {
+        fn is_ident_char(ch: char) -> bool {
+            ('a' <= ch && ch <= 'z') || ('A' <= ch && ch <= 'Z')
+                || ('0' <= ch && ch <= '9') || ch == '_' || ch == '-'
+        }
+
+        let first = self.0.chars().next().unwrap();
+        let is_ident_start =
+            ('a' <= first && first <= 'z') || ('0' <= first && first <= '9');
+        is_ident_start && self.0.chars().all(is_ident_char)
+    }
 }
 
 impl AsRef<str> for Name {
@@ -37,15 +53,12 @@ impl AsRef<str> for Name {
 
 impl Display for Name {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
-        fn is_ident_char(ch: char) -> bool {
-            ('a' <= ch && ch <= 'z') || ('A' <= ch && ch <= 'Z')
-                || ('0' <= ch && ch <= '9') || ch == '_'
-        }
-
-        if self.0.chars().all(is_ident_char) {
+        // A name is written as an identifier if it would parse as one, and
+        // otherwise in string syntax.
+        if self.is_ident() {
             fmt.write_str(&self.0)
         } else {
-            unimplemented!("Print name {:?} in string syntax", self.0)
+            write_quoted(fmt, &self.0)
         }
     }
 }
@@ -66,3 +79,13 @@ proptest! {
         }
     }
 }
+
+#[test]
+fn display() {
+    let show = |s: &str| Name::new(s).unwrap().to_string();
+    assert_eq!(show("foo-bar_2"), "foo-bar_2");
+    assert_eq!(show("42"), "42");
+    assert_eq!(show("hello world"), r#""hello world""#);
+    assert_eq!(show("fooBar"), "fooBar");
+    assert_eq!(show("\u{3bb}\n\u{7}"), "\"\u{3bb}\\n\\u0007\"");
+}
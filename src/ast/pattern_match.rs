@@ -1,5 +1,5 @@
 use Bindings;
-use ast::{Name, Term, Variable};
+use ast::{Term, Value, Variable};
 
 pub fn pattern_match(
     l: &mut [Term],
@@ -12,41 +12,41 @@ pub fn pattern_match(
     let mut rb = Bindings::new();
     for i in 0..len {
         match (l[i].clone(), r[i].clone()) {
-            (Term::Name(lv), Term::Name(rv)) => if lv != rv {
+            (Term::Value(lv, _), Term::Value(rv, _)) => if lv != rv {
                 return None;
             },
-            (Term::Var(lv), Term::Name(rv)) => {
+            (Term::Var(lv, _), Term::Value(rv, _)) => {
                 if lb.contains_key(&lv) {
                     return None;
                 }
                 lb.insert(lv.clone(), rv.clone());
                 apply_binding(l, lv, rv);
             }
-            (Term::Name(lv), Term::Var(rv)) => {
+            (Term::Value(lv, _), Term::Var(rv, _)) => {
                 if rb.contains_key(&rv) {
                     return None;
                 }
                 rb.insert(rv.clone(), lv.clone());
                 apply_binding(r, rv, lv);
             }
-            (Term::Var(lv), Term::Var(rv)) => {}
+            (Term::Var(..), Term::Var(..)) => {}
         }
     }
     Some((lb, rb))
 }
 
-fn apply_binding(terms: &mut [Term], var: Variable, name: Name) {
-    let var = Term::Var(var);
+fn apply_binding(terms: &mut [Term], var: Variable, value: Value) {
+    let var = Term::Var(var, None);
     for term in terms.iter_mut() {
         if *term == var {
-            *term = Term::Name(name.clone());
+            *term = Term::Value(value.clone(), term.span());
         }
     }
 }
 
 #[test]
 fn literal() {
-    use ast::{Name, Variable};
+    use ast::{Value, Variable};
 
     let one = Term::new("1").unwrap();
     let x = Term::new("X").unwrap();
@@ -61,14 +61,14 @@ fn literal() {
     assert_eq!(
         lb,
         vec![
-            (Variable::new("X").unwrap(), Name::new("1").unwrap()),
-            (Variable::new("Y").unwrap(), Name::new("1").unwrap()),
+            (Variable::new("X").unwrap(), Value::Int(1)),
+            (Variable::new("Y").unwrap(), Value::Int(1)),
         ].into_iter()
             .collect()
     );
     assert_eq!(
         rb,
-        vec![(Variable::new("X").unwrap(), Name::new("1").unwrap())]
+        vec![(Variable::new("X").unwrap(), Value::Int(1))]
             .into_iter()
             .collect()
     );
@@ -1,6 +1,7 @@
 use sparkly::{Doc, Sparkly};
 
-use ast::{Clause, Literal, Name, Program, Statement, Term, Variable};
+use ast::{Aggregate, ArithOp, Clause, CmpOp, Declaration, Expr, Goal,
+          Literal, Name, Program, Statement, Term, Type, Value, Variable};
 use styles::{NAME, PUNCTUATION, VARIABLE};
 
 impl_Display_for_Sparkly!(Program);
@@ -14,42 +15,180 @@ impl_Display_for_Sparkly!(Statement);
 impl Sparkly for Statement {
     fn to_doc(&self) -> Doc {
         match *self {
+            Statement::Declaration(ref d) => d.to_doc(),
             Statement::Assertion(ref c) => {
                 c.to_doc().append(Doc::text(".", PUNCTUATION))
             }
             Statement::Retraction(ref c) => {
                 c.to_doc().append(Doc::text("~", PUNCTUATION))
             }
-            Statement::Query(ref q) => {
-                q.to_doc().append(Doc::text("?", PUNCTUATION))
-            }
+            Statement::Query(ref q) => Doc::text(",", PUNCTUATION)
+                .append(Doc::space())
+                .join(q.iter().map(Sparkly::to_doc))
+                .group()
+                .append(Doc::text("?", PUNCTUATION)),
         }
     }
 }
 
+impl_Display_for_Sparkly!(Declaration);
+impl Sparkly for Declaration {
+    fn to_doc(&self) -> Doc {
+        let Declaration(ref pred, ref columns, _) = *self;
+        let columns = columns.iter().map(|&(ref name, ty)| {
+            name.to_doc()
+                .append(Doc::text(":", PUNCTUATION))
+                .append(Doc::nbsp())
+                .append(ty.to_doc())
+        });
+        let columns = Doc::text(",", PUNCTUATION)
+            .append(Doc::space())
+            .join(columns);
+        Doc::from(".decl")
+            .append(Doc::nbsp())
+            .append(pred.to_doc())
+            .append(columns.bracket("(", ")"))
+    }
+}
+
 impl_Display_for_Sparkly!(Clause);
 impl Sparkly for Clause {
     fn to_doc(&self) -> Doc {
-        let Clause(ref head, ref body) = *self;
-        if body.len() == 0 {
+        let Clause(ref head, ref body, ref aggregates, _) = *self;
+        let head = if aggregates.is_empty() {
             head.to_doc()
+        } else {
+            let Literal(ref pred, ref args, _) = *head;
+            let args = args.iter().enumerate().map(|(i, arg)| {
+                match aggregates.iter().find(|&&(j, _)| i == j) {
+                    Some(&(_, aggregate)) => aggregate
+                        .to_doc()
+                        .append(Doc::from("<"))
+                        .append(arg.to_doc())
+                        .append(Doc::from(">")),
+                    None => arg.to_doc(),
+                }
+            });
+            let args = Doc::from(",").append(Doc::space()).join(args);
+            pred.to_doc().append(args.bracket("(", ")"))
+        };
+        if body.len() == 0 {
+            head
         } else {
             let body = Doc::text(",", PUNCTUATION)
                 .join(body.iter().map(|l| Doc::space().append(l.to_doc())))
                 .nest(4)
                 .group();
-            head.to_doc()
-                .append(Doc::nbsp())
+            head.append(Doc::nbsp())
                 .append(Doc::text(":-", PUNCTUATION))
                 .append(body)
         }
     }
 }
 
+impl_Display_for_Sparkly!(Goal);
+impl Sparkly for Goal {
+    fn to_doc(&self) -> Doc {
+        match *self {
+            Goal::Literal(ref l) => l.to_doc(),
+            Goal::Not(ref l) => {
+                Doc::from("not").append(Doc::nbsp()).append(l.to_doc())
+            }
+            Goal::Compare(ref l, ref op, ref r) => l.to_doc()
+                .append(Doc::nbsp())
+                .append(op.to_doc())
+                .append(Doc::nbsp())
+                .append(r.to_doc()),
+        }
+    }
+}
+
+impl_Display_for_Sparkly!(Expr);
+impl Sparkly for Expr {
+    fn to_doc(&self) -> Doc {
+        // Nested operations are always parenthesized, rather than relying on
+        // precedence.
+        fn operand(expr: &Expr) -> Doc {
+            match *expr {
+                Expr::Term(ref t) => term(t),
+                Expr::Op(..) => expr.to_doc().bracket("(", ")"),
+                Expr::Neg(..) => expr.to_doc(),
+            }
+        }
+
+        // A symbol with a hyphen is quoted, since the parser rejects it
+        // unquoted in an expression.
+        fn term(term: &Term) -> Doc {
+            if let Term::Value(ref v @ Value::Symbol(_), _) = *term {
+                let s = v.to_string();
+                if s.contains('-') && !s.starts_with('\'') {
+                    return Doc::from(format!("'{}'", s)).style(NAME);
+                }
+            }
+            term.to_doc()
+        }
+
+        match *self {
+            Expr::Term(ref t) => term(t),
+            Expr::Op(ref l, ref op, ref r) => operand(l)
+                .append(Doc::nbsp())
+                .append(op.to_doc())
+                .append(Doc::nbsp())
+                .append(operand(r)),
+            // Only a variable is negated without parentheses, since `-1` would
+            // be read as a negative number rather than a negation.
+            Expr::Neg(ref e) => Doc::from("-").append(match **e {
+                Expr::Term(Term::Var(..)) => e.to_doc(),
+                _ => e.to_doc().bracket("(", ")"),
+            }),
+        }
+    }
+}
+
+impl_Display_for_Sparkly!(Aggregate);
+impl Sparkly for Aggregate {
+    fn to_doc(&self) -> Doc {
+        Doc::from(match *self {
+            Aggregate::Count => "count",
+            Aggregate::Sum => "sum",
+            Aggregate::Min => "min",
+            Aggregate::Max => "max",
+            Aggregate::Avg => "avg",
+        })
+    }
+}
+
+impl_Display_for_Sparkly!(ArithOp);
+impl Sparkly for ArithOp {
+    fn to_doc(&self) -> Doc {
+        Doc::from(match *self {
+            ArithOp::Add => "+",
+            ArithOp::Sub => "-",
+            ArithOp::Mul => "*",
+            ArithOp::Div => "/",
+            ArithOp::Mod => "mod",
+        })
+    }
+}
+
+impl_Display_for_Sparkly!(CmpOp);
+impl Sparkly for CmpOp {
+    fn to_doc(&self) -> Doc {
+        Doc::from(match *self {
+            CmpOp::Eq => "=",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        })
+    }
+}
+
 impl_Display_for_Sparkly!(Literal);
 impl Sparkly for Literal {
     fn to_doc(&self) -> Doc {
-        let Literal(ref pred, ref args) = *self;
+        let Literal(ref pred, ref args, _) = *self;
         let args = Doc::from(",").append(Doc::space()).join(args);
         pred.to_doc().append(args.bracket("(", ")"))
     }
@@ -59,8 +198,8 @@ impl_Display_for_Sparkly!(Term);
 impl Sparkly for Term {
     fn to_doc(&self) -> Doc {
         match *self {
-            Term::Name(ref n) => n.to_doc(),
-            Term::Var(ref v) => v.to_doc(),
+            Term::Value(ref v, _) => v.to_doc(),
+            Term::Var(ref v, _) => v.to_doc(),
         }
     }
 }
@@ -71,6 +210,18 @@ impl Sparkly for Name {
     }
 }
 
+impl Sparkly for Value {
+    fn to_doc(&self) -> Doc {
+        Doc::from(self.to_string()).style(NAME)
+    }
+}
+
+impl Sparkly for Type {
+    fn to_doc(&self) -> Doc {
+        Doc::from(self.name())
+    }
+}
+
 impl Sparkly for Variable {
     fn to_doc(&self) -> Doc {
         Doc::from(self.to_string()).style(VARIABLE)
@@ -0,0 +1,150 @@
+use std::collections::HashSet;
+
+use ast::{Clause, CmpOp, Expr, Goal, Term, Variable};
+use errors::{ErrorKind, Result};
+
+impl Clause {
+    /// Reorders the body of the clause, so that each negated literal and
+    /// comparison comes after the goals that bind its variables. Goals are
+    /// otherwise kept in order; any that can never have their variables bound
+    /// are moved to the end.
+    pub fn schedule(&self) -> Clause {
+        let Clause(ref head, ref body, ref aggregates, span) = *self;
+        Clause(head.clone(), schedule_goals(body), aggregates.clone(), span)
+    }
+
+    /// Checks that the clause is safe (or range-restricted): every variable
+    /// in its head, in a negated literal, or in a comparison must also appear
+    /// in a positive literal in its body, or be bound by an equality whose
+    /// other side only has such variables. Otherwise, the clause could hold
+    /// for infinitely many names, such as the fact `edge(X, X)`.
+    pub fn check_safety(&self) -> Result<()> {
+        let unsafe_var = |var: Variable| {
+            Err(ErrorKind::UnsafeVariable(var, self.clone()).into())
+        };
+
+        let mut bound = HashSet::new();
+        for goal in self.schedule().body() {
+            if !ready(goal, &bound) {
+                // An equality could bind a lone variable on one side, so the
+                // variables on the other side are the real problem.
+                let assigned = match *goal {
+                    Goal::Compare(ref l, CmpOp::Eq, ref r) => {
+                        lone_var(l).or_else(|| lone_var(r))
+                    }
+                    _ => None,
+                };
+                let mut vars = goal.vars();
+                vars.retain(|v| !bound.contains(v));
+                let var = vars.iter()
+                    .find(|&v| Some(v) != assigned)
+                    .unwrap_or(&vars[0]);
+                return unsafe_var(var.clone());
+            }
+            if let Goal::Not(_) = *goal {
+            } else {
+                bound.extend(goal.vars());
+            }
+        }
+
+        for arg in &self.head().1 {
+            if let Term::Var(ref v, _) = *arg {
+                if !bound.contains(v) {
+                    return unsafe_var(v.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reorders a list of goals in the same way as the body of a clause.
+pub fn schedule_goals(goals: &[Goal]) -> Vec<Goal> {
+    let mut bound = HashSet::new();
+    let mut todo = goals.to_vec();
+    let mut scheduled = Vec::new();
+    while let Some(i) = todo.iter().position(|g| ready(g, &bound)) {
+        let goal = todo.remove(i);
+        if let Goal::Not(_) = goal {
+        } else {
+            bound.extend(goal.vars());
+        }
+        scheduled.push(goal);
+    }
+    scheduled.extend(todo);
+    scheduled
+}
+
+/// Returns whether a goal can be evaluated once the given variables are
+/// bound.
+fn ready(goal: &Goal, bound: &HashSet<Variable>) -> bool {
+    match *goal {
+        Goal::Literal(_) => true,
+        Goal::Not(_) => goal.vars().iter().all(|v| bound.contains(v)),
+        Goal::Compare(ref l, op, ref r) => {
+            let is_bound = |e: &Expr| {
+                e.terms().into_iter().all(|t| match *t {
+                    Term::Var(ref v, _) => bound.contains(v),
+                    Term::Value(..) => true,
+                })
+            };
+            let assigns = |var: &Expr, value: &Expr| {
+                op == CmpOp::Eq && lone_var(var).is_some() && is_bound(value)
+            };
+            (is_bound(l) && is_bound(r)) || assigns(l, r) || assigns(r, l)
+        }
+    }
+}
+
+/// If the expression is a single variable, returns it.
+fn lone_var(expr: &Expr) -> Option<&Variable> {
+    match *expr {
+        Expr::Term(Term::Var(ref v, _)) => Some(v),
+        _ => None,
+    }
+}
+
+#[test]
+fn safety() {
+    let check = |s: &str| match s.parse().unwrap() {
+        ::Statement::Assertion(c) => match c.check_safety() {
+            Ok(()) => None,
+            Err(err) => match *err.kind() {
+                ErrorKind::UnsafeVariable(ref v, _) => Some(v.clone()),
+                ref err => panic!("unexpected error {}", err),
+            },
+        },
+        _ => unreachable!(),
+    };
+    let var = |s: &str| Some(Variable::new(s).unwrap());
+
+    assert_eq!(check("edge(a, b)."), None);
+    assert_eq!(check("path(X, Y) :- edge(X, Z), path(Z, Y)."), None);
+    assert_eq!(check("bland(X) :- red(X), not spicy(X)."), None);
+    assert_eq!(check("bland(X) :- not spicy(X), red(X)."), None);
+    assert_eq!(check("next(X, Y) :- Y = X + 1, num(X)."), None);
+    assert_eq!(check("big(X) :- num(X), X > 10."), None);
+    assert_eq!(check("edge(X, X)."), var("X"));
+    assert_eq!(check("path(X, Y) :- edge(X, Z)."), var("Y"));
+    assert_eq!(check("bland(X) :- red(X), not spicy(X, Y)."), var("Y"));
+    assert_eq!(check("bland(X) :- not spicy(X)."), var("X"));
+    assert_eq!(check("next(X, Y) :- num(X), Y > X + 1."), var("Y"));
+    assert_eq!(check("next(X, Y) :- num(Z), Y = X + 1."), var("X"));
+}
+
+#[test]
+fn schedule() {
+    let parse = |s: &str| match s.parse().unwrap() {
+        ::Statement::Assertion(c) => c,
+        _ => unreachable!(),
+    };
+
+    assert_eq!(
+        parse("p(X, Y) :- Y = X * 2, not q(Y), r(X).").schedule(),
+        parse("p(X, Y) :- r(X), Y = X * 2, not q(Y).")
+    );
+    assert_eq!(
+        parse("p(X) :- r(X), not q(X), s(X).").schedule(),
+        parse("p(X) :- r(X), not q(X), s(X).")
+    );
+}
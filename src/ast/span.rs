@@ -0,0 +1,32 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use symbol::Symbol;
+
+/// Where part of a program was in its source. Spans are only kept for
+/// reporting errors, so parts of a program are compared without them.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Span {
+    /// The file the program was parsed from, if it was parsed from one.
+    pub file: Option<Symbol>,
+
+    /// The byte offset of the start of the part.
+    pub start: usize,
+
+    /// The byte offset of the end of the part.
+    pub end: usize,
+
+    /// The line the part starts on, counting from 1.
+    pub line: usize,
+
+    /// The column the part starts at, in characters, counting from 1.
+    pub col: usize,
+}
+
+impl Display for Span {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        if let Some(file) = self.file {
+            write!(fmt, "{}:", file)?;
+        }
+        write!(fmt, "{}:{}", self.line, self.col)
+    }
+}
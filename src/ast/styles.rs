@@ -23,6 +23,12 @@ pub const ERROR: Style = Style {
     ..PLAIN
 };
 
+/// The style associated with a warning.
+pub const WARNING: Style = Style {
+    foreground: Some(Colour::Yellow),
+    ..PLAIN
+};
+
 /// The style associated with a name.
 pub const NAME: Style = Style {
     foreground: Some(Colour::Blue),
@@ -35,6 +41,12 @@ pub const PUNCTUATION: Style = Style {
     ..PLAIN
 };
 
+/// The style associated with a successful answer.
+pub const SUCCESS: Style = Style {
+    foreground: Some(Colour::Green),
+    ..PLAIN
+};
+
 /// The style associated with a variable.
 pub const VARIABLE: Style = Style {
     foreground: Some(Colour::Green),
@@ -0,0 +1,277 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter, Result as FmtResult, Write};
+use std::hash::{Hash, Hasher};
+
+use symbol::Symbol;
+
+use ast::Name;
+
+/// A constant, for example `foo`, `42`, `-1.5`, `true`, or `"foo"`.
+///
+/// Constants of different types are never equal, so `42` and `"42"` are
+/// distinct. That goes for integers and floats too, so the fact `p(1)` does
+/// not match `p(1.0)`, even though the comparison `1 = 1.0` holds, since
+/// comparisons are between numbers rather than constants. Floats are
+/// compared by their bit patterns, so that values can be stored in sets and
+/// maps; `Value::float` turns `-0.0` into `0.0`, so that zero has only one.
+#[derive(Clone, Debug)]
+pub enum Value {
+    /// A boolean, `true` or `false`.
+    Bool(bool),
+
+    /// An integer, for example `42` or `-1`.
+    Int(i64),
+
+    /// A floating-point number, for example `1.5` or `-2.0e10`.
+    Float(f64),
+
+    /// A string, for example `"qwerty\nasdf"`.
+    Str(Symbol),
+
+    /// A symbol, for example `foo`.
+    Symbol(Name),
+}
+
+/// The type of a constant, as written in a declaration.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Type {
+    /// The type of `true` and `false`.
+    Bool,
+
+    /// The type of integers.
+    Int,
+
+    /// The type of floating-point numbers.
+    Float,
+
+    /// The type of strings.
+    Str,
+
+    /// The type of symbols.
+    Symbol,
+}
+
+impl Type {
+    /// Returns the name of the type, which is how it is written.
+    pub fn name(self) -> &'static str {
+        match self {
+            Type::Bool => "bool",
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Str => "string",
+            Type::Symbol => "symbol",
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.write_str(self.name())
+    }
+}
+
+/// Returns whether a string that parses as a float is written as one, with a
+/// fractional part or an exponent, rather than being, say, `inf`.
+fn is_float(s: &str) -> bool {
+    s.contains(|ch| ch == '.' || ch == 'e' || ch == 'E')
+}
+
+impl Value {
+    /// Interprets a string as the constant it would be if written, unquoted,
+    /// in a program. Returns `None` if the string isn't a valid name.
+    pub fn new(s: &str) -> Option<Value> {
+        match s {
+            "true" => return Some(Value::Bool(true)),
+            "false" => return Some(Value::Bool(false)),
+            _ => {}
+        }
+        if let Ok(n) = s.parse() {
+            Some(Value::Int(n))
+        } else if let (true, Ok(x)) = (is_float(s), s.parse()) {
+            Some(Value::float(x))
+        } else {
+            Name::new(s).map(Value::Symbol)
+        }
+    }
+
+    /// Creates a float constant, replacing `-0.0` with `0.0`.
+    pub fn float(x: f64) -> Value {
+        // Adding zero leaves every float but `-0.0` as it is.
+        Value::Float(x + 0.0)
+    }
+
+    /// Creates a string constant.
+    pub fn string(s: &str) -> Value {
+        Value::Str(s.into())
+    }
+
+    /// Returns the type of the constant.
+    pub fn ty(&self) -> Type {
+        match *self {
+            Value::Bool(_) => Type::Bool,
+            Value::Int(_) => Type::Int,
+            Value::Float(_) => Type::Float,
+            Value::Str(_) => Type::Str,
+            Value::Symbol(_) => Type::Symbol,
+        }
+    }
+
+    /// Returns the name of the type of the constant.
+    pub fn type_name(&self) -> &'static str {
+        self.ty().name()
+    }
+
+    /// Returns the order of the variants, which is used to order constants of
+    /// different types.
+    fn rank(&self) -> u8 {
+        match *self {
+            Value::Bool(_) => 0,
+            Value::Int(_) => 1,
+            Value::Float(_) => 2,
+            Value::Str(_) => 3,
+            Value::Symbol(_) => 4,
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Value {
+        Value::Int(n)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Value {
+        Value::float(x)
+    }
+}
+
+impl From<Name> for Value {
+    fn from(name: Name) -> Value {
+        Value::Symbol(name)
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (&Value::Bool(l), &Value::Bool(r)) => l.cmp(&r),
+            (&Value::Int(l), &Value::Int(r)) => l.cmp(&r),
+            (&Value::Float(l), &Value::Float(r)) => l.total_cmp(&r),
+            (&Value::Str(ref l), &Value::Str(ref r)) => l.as_str().cmp(r),
+            (&Value::Symbol(ref l), &Value::Symbol(ref r)) => {
+                l.as_ref().cmp(r.as_ref())
+            }
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match *self {
+            Value::Bool(b) => b.hash(state),
+            Value::Int(n) => n.hash(state),
+            Value::Float(x) => x.to_bits().hash(state),
+            Value::Str(ref s) => s.hash(state),
+            Value::Symbol(ref n) => n.hash(state),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match *self {
+            Value::Bool(b) => write!(fmt, "{}", b),
+            Value::Int(n) => write!(fmt, "{}", n),
+            Value::Float(x) => {
+                // The decimal point is what distinguishes a float from an
+                // integer, so it can't be left out, even with an exponent.
+                let s = format!("{:?}", x);
+                if !x.is_finite() || s.contains('.') {
+                    fmt.write_str(&s)
+                } else if let Some(i) = s.find('e') {
+                    write!(fmt, "{}.0{}", &s[..i], &s[i..])
+                } else {
+                    write!(fmt, "{}.0", s)
+                }
+            }
+            Value::Str(ref s) => write_quoted(fmt, s),
+            Value::Symbol(ref n) => {
+                // A symbol is written as an identifier unless it would be read
+                // as some other value, like `42` or `true`, and otherwise in
+                // single quotes.
+                let first = n.as_ref().chars().next().unwrap();
+                let keyword = n.as_ref() == "true" || n.as_ref() == "false";
+                if n.is_ident() && 'a' <= first && first <= 'z' && !keyword {
+                    fmt.write_str(n.as_ref())
+                } else {
+                    write_delimited(fmt, n.as_ref(), '\'')
+                }
+            }
+        }
+    }
+}
+
+/// Writes a string in the quoted syntax used by the parser.
+pub fn write_quoted<W: Write>(w: &mut W, s: &str) -> FmtResult {
+    write_delimited(w, s, '"')
+}
+
+/// Writes a string between the given quotes, escaping them within it.
+fn write_delimited<W: Write>(w: &mut W, s: &str, quote: char) -> FmtResult {
+    w.write_char(quote)?;
+    for ch in s.chars() {
+        match ch {
+            '\n' => w.write_str("\\n")?,
+            '\r' => w.write_str("\\r")?,
+            '\t' => w.write_str("\\t")?,
+            '\\' => w.write_str("\\\\")?,
+            ch if ch == quote => write!(w, "\\{}", ch)?,
+            ch if ch.is_control() => write!(w, "\\u{:04x}", ch as u32)?,
+            ch => w.write_char(ch)?,
+        }
+    }
+    w.write_char(quote)
+}
+
+#[test]
+fn display() {
+    let show = |v: Value| v.to_string();
+    assert_eq!(show(Value::Int(-42)), "-42");
+    assert_eq!(show(Value::Float(1.0)), "1.0");
+    assert_eq!(show(Value::Float(-2.5e-20)), "-2.5e-20");
+    assert_eq!(show(Value::Float(1e100)), "1.0e100");
+    assert_eq!(show(Value::Bool(true)), "true");
+    assert_eq!(show(Value::string("say \"hi\"\n")), r#""say \"hi\"\n""#);
+    assert_eq!(show(Value::new("foo").unwrap()), "foo");
+    assert_eq!(show(Value::new("a-1").unwrap()), "a-1");
+    assert_eq!(show(Value::new("4x4").unwrap()), "'4x4'");
+    assert_eq!(show(Value::new("hello world").unwrap()), "'hello world'");
+    assert_eq!(show(Name::new("42").unwrap().into()), "'42'");
+    assert_eq!(show(Name::new("true").unwrap().into()), "'true'");
+    assert_eq!(show(Name::new("it's").unwrap().into()), r"'it\'s'");
+    assert_eq!(show(Value::new("1e3").unwrap()), "1000.0");
+    assert_eq!(show(Value::new("-0.0").unwrap()), "0.0");
+}
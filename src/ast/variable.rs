@@ -28,6 +28,20 @@ impl Variable {
             false
         }
     }
+
+    /// Returns the `n`th of the fresh variables that each anonymous variable
+    /// `_` is renamed to when it is parsed. These can't be written in source,
+    /// so they never clash with the variables of a clause, and are printed as
+    /// `_`.
+    pub fn anonymous(n: usize) -> Variable {
+        Variable(format!("_#{}", n).as_str().into())
+    }
+
+    /// Returns whether the variable is one of the fresh variables made by
+    /// `Variable::anonymous`. Anonymous variables are left out of answers.
+    pub fn is_anonymous(&self) -> bool {
+        self.0.starts_with("_#")
+    }
 }
 
 impl AsRef<str> for Variable {
@@ -38,7 +52,11 @@ impl AsRef<str> for Variable {
 
 impl Display for Variable {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
-        fmt.write_str(&self.0)
+        if self.is_anonymous() {
+            fmt.write_str("_")
+        } else {
+            fmt.write_str(&self.0)
+        }
     }
 }
 
@@ -0,0 +1,341 @@
+//! Loading relations from, and saving them to, delimited text files such as
+//! CSV and TSV.
+//!
+//! Each row of a file is a tuple, with one field per argument. A field in
+//! double quotes is a string, and one in single quotes is a symbol, such as
+//! `'hello world'` or `'42'`; like a double quote in a string, a single quote
+//! in a symbol is doubled. An unquoted field is read like a constant in a
+//! program, so `42` is an integer and `foo` a symbol, except that a field
+//! which isn't a valid constant or identifier, such as `Foo` or `foo bar`, is
+//! also read as a string. Values are written so that they are read back the
+//! same way.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use std::result::Result as StdResult;
+
+use {Clause, ErrorKind, Interpeter, Literal, Name, Result, Term, Value,
+     Variable};
+
+/// The rows of a delimited file, with the line each starts on. Each field
+/// has the quote it was written in, if any.
+type Rows = Vec<(usize, Vec<(String, Option<char>)>)>;
+
+/// Loads every row of a delimited file as a fact about the predicate with
+/// the given name, returning the number of rows loaded. Every row must have
+/// one field per argument of the predicate, whose arity is the one the
+/// interpreter knows from its declaration or clauses, or else the number of
+/// fields in the first row. Every row is read before any is asserted, so a
+/// malformed file loads nothing.
+pub fn load_csv<I, P>(
+    interpreter: &mut I,
+    name: &Name,
+    path: P,
+    delimiter: char,
+) -> Result<usize>
+where
+    I: Interpeter,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let mut src = String::new();
+    File::open(path)?.read_to_string(&mut src)?;
+    let error = |line, msg| {
+        ErrorKind::Csv(path.display().to_string(), line, msg).into()
+    };
+
+    let rows = parse_rows(&src, delimiter).map_err(|(l, m)| error(l, m))?;
+    let arity = match interpreter.arity(name) {
+        Some(arity) => arity,
+        None => rows.first().map_or(0, |&(_, ref row)| row.len()),
+    };
+    let mut facts = Vec::new();
+    for (line, row) in rows {
+        if row.len() != arity {
+            let msg = format!(
+                "expected {} fields for {}/{}, found {}",
+                arity,
+                name,
+                arity,
+                row.len()
+            );
+            return Err(error(line, msg));
+        }
+
+        let mut args = Vec::new();
+        for (field, quote) in row {
+            let value = match (quote, Value::new(&field)) {
+                (Some('\''), _) => match Name::new(&field) {
+                    Some(name) => Value::Symbol(name),
+                    None => {
+                        let msg = format!("'{}' is not a valid symbol", field);
+                        return Err(error(line, msg));
+                    }
+                },
+                (None, Some(Value::Symbol(ref name))) if !name.is_ident() => {
+                    Value::string(&field)
+                }
+                (None, Some(value)) => value,
+                _ => Value::string(&field),
+            };
+            args.push(Term::Value(value, None));
+        }
+        let head = Literal(name.clone(), args, None);
+        facts.push(Clause(head, vec![], vec![], None));
+    }
+
+    let count = facts.len();
+    for fact in facts {
+        interpreter.run_assertion(fact)?;
+    }
+    Ok(count)
+}
+
+/// Saves every fact about the given predicate to a delimited file, one row
+/// per fact, in sorted order. Returns the number of rows saved.
+pub fn save_csv<I, P>(
+    interpreter: &I,
+    pred: &(Name, usize),
+    path: P,
+    delimiter: char,
+) -> Result<usize>
+where
+    I: Interpeter,
+    P: AsRef<Path>,
+{
+    let vars = (0..pred.1)
+        .map(|i| Variable::new(&format!("X{}", i)).unwrap())
+        .collect::<Vec<_>>();
+    let args = vars.iter().map(|v| Term::Var(v.clone(), None)).collect();
+    let query = Literal(pred.0.clone(), args, None);
+    let mut rows = Vec::new();
+    for bindings in interpreter.run_query(query) {
+        let bindings = bindings?;
+        let row = vars.iter().map(|v| bindings[v].clone());
+        rows.push(row.collect::<Vec<_>>());
+    }
+    rows.sort();
+    rows.dedup();
+
+    let mut out = String::new();
+    for row in &rows {
+        for (i, value) in row.iter().enumerate() {
+            if i > 0 {
+                out.push(delimiter);
+            }
+            write_field(&mut out, value, delimiter);
+        }
+        out.push('\n');
+    }
+    File::create(path)?.write_all(out.as_bytes())?;
+    Ok(rows.len())
+}
+
+/// Appends a value to a row, quoting it if it is a string, or if it is a
+/// symbol that would not otherwise be read back as the same symbol in a
+/// single field.
+fn write_field(out: &mut String, value: &Value, delimiter: char) {
+    let (text, quote) = match *value {
+        Value::Str(ref s) => (s.as_str().to_owned(), Some('"')),
+        Value::Symbol(ref n) => {
+            let text = n.as_ref().to_owned();
+            let bare = n.is_ident() && !text.contains(delimiter)
+                && Value::new(&text).as_ref() == Some(value);
+            (text, if bare { None } else { Some('\'') })
+        }
+        ref value => (value.to_string(), None),
+    };
+    if let Some(quote) = quote {
+        let doubled = format!("{}{}", quote, quote);
+        out.push(quote);
+        out.push_str(&text.replace(quote, &doubled));
+        out.push(quote);
+    } else {
+        out.push_str(&text);
+    }
+}
+
+/// Splits delimited text into rows of fields, each with the quote it was
+/// written in, and the line each row starts on. A quoted field may contain
+/// the delimiter, newlines, and doubled quotes. Blank lines are skipped. On
+/// error, returns the line number and a message.
+fn parse_rows(src: &str, delimiter: char) -> StdResult<Rows, (usize, String)> {
+    let mut rows = Vec::new();
+    let mut chars = src.chars().peekable();
+    let mut line = 1;
+    while chars.peek().is_some() {
+        let start = line;
+        let mut row = Vec::new();
+        loop {
+            let mut field = String::new();
+            let quote = match chars.peek() {
+                Some(&'"') => Some('"'),
+                Some(&'\'') => Some('\''),
+                _ => None,
+            };
+            if let Some(quote) = quote {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some(c) if c == quote && chars.peek() == Some(&c) => {
+                            chars.next();
+                            field.push(c);
+                        }
+                        Some(c) if c == quote => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c);
+                        }
+                        None => {
+                            let msg = "unterminated quoted field".to_string();
+                            return Err((start, msg));
+                        }
+                    }
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if c == delimiter || c == '\n' || c == '\r' {
+                        break;
+                    }
+                    field.push(c);
+                    chars.next();
+                }
+            }
+            row.push((field, quote));
+
+            match chars.next() {
+                Some(c) if c == delimiter => {}
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                    line += 1;
+                    break;
+                }
+                Some('\n') => {
+                    line += 1;
+                    break;
+                }
+                None => break,
+                Some(c) => {
+                    let msg = if quote.is_some() {
+                        format!("expected {:?} after quoted field", delimiter)
+                    } else {
+                        format!("unexpected {:?} in unquoted field", c)
+                    };
+                    return Err((line, msg));
+                }
+            }
+        }
+        if row != [(String::new(), None)] {
+            rows.push((start, row));
+        }
+    }
+    Ok(rows)
+}
+
+#[test]
+fn round_trip() {
+    use std::env::temp_dir;
+    use std::fs::{read_to_string, remove_file, write};
+    use std::process;
+
+    use {NaiveInterpreter, Statement};
+
+    let src = concat!(
+        "a,1,2.5,true,\"a, \"\"quoted\"\" string\"\r\n",
+        "\n",
+        "b,-3,1.5e3,false,\"two\nlines\"\n",
+        "c,\"42\",Capital,,x y\n",
+        "d,'hello world','42','true','it''s, ok'\n",
+    );
+    let input = temp_dir().join(format!("datalog-in-{}.csv", process::id()));
+    let output = temp_dir().join(format!("datalog-out-{}.csv", process::id()));
+    write(&input, src).unwrap();
+
+    let mut interpreter = NaiveInterpreter::new();
+    let pred = (Name::new("row").unwrap(), 5);
+    let loaded = load_csv(&mut interpreter, &pred.0, &input, ',').unwrap();
+    assert_eq!(loaded, 4);
+    let query = |s: &str| match s.parse().unwrap() {
+        Statement::Query(q) => q,
+        _ => unreachable!(),
+    };
+    let count = |interpreter: &NaiveInterpreter, s: &str| {
+        interpreter.run_goals(query(s)).count()
+    };
+    assert_eq!(count(&interpreter, "row(a, 1, 2.5, true, S)?"), 1);
+    assert_eq!(count(&interpreter, "row(c, \"42\", \"Capital\", \"\", Y)?"), 1);
+    assert_eq!(count(&interpreter, "row(c, 42, X, Y, Z)?"), 0);
+    assert_eq!(count(&interpreter, "row(X, Y, Z, W, \"x y\")?"), 1);
+    assert_eq!(count(&interpreter, "row(X, Y, Z, W, \"two\\nlines\")?"), 1);
+    let symbols = "row(d, 'hello world', '42', 'true', 'it\\'s, ok')?";
+    assert_eq!(count(&interpreter, symbols), 1);
+
+    assert_eq!(save_csv(&interpreter, &pred, &output, ',').unwrap(), 4);
+    assert_eq!(
+        read_to_string(&output).unwrap(),
+        concat!(
+            "a,1,2.5,true,\"a, \"\"quoted\"\" string\"\n",
+            "b,-3,1500.0,false,\"two\nlines\"\n",
+            "c,\"42\",\"Capital\",\"\",\"x y\"\n",
+            "d,'hello world','42','true','it''s, ok'\n",
+        )
+    );
+
+    let mut copy = NaiveInterpreter::new();
+    assert_eq!(load_csv(&mut copy, &pred.0, &output, ',').unwrap(), 4);
+    assert_eq!(count(&copy, "row(b, -3, 1500.0, false, X)?"), 1);
+    assert_eq!(count(&copy, "row(c, \"42\", \"Capital\", \"\", Y)?"), 1);
+    assert_eq!(count(&copy, "row(X, Y, Z, W, \"x y\")?"), 1);
+    assert_eq!(count(&copy, symbols), 1);
+
+    write(&input, "a\tb\n\nc\n").unwrap();
+    let edge = Name::new("edge").unwrap();
+    let err = load_csv(&mut copy, &edge, &input, '\t').unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Error in {} on line 3: expected 2 fields for edge/2, found 1",
+            input.display()
+        )
+    );
+    assert_eq!(count(&copy, "edge(X, Y)?"), 0);
+
+    write(&input, "a\tb\nc\t''\n").unwrap();
+    let err = load_csv(&mut copy, &edge, &input, '\t').unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Error in {} on line 2: '' is not a valid symbol",
+            input.display()
+        )
+    );
+    assert_eq!(count(&copy, "edge(X, Y)?"), 0);
+
+    let decl = ".decl edge(src: symbol)".parse().unwrap();
+    copy.run_stmt(decl).unwrap().count();
+    write(&input, "a\tb\n").unwrap();
+    let err = load_csv(&mut copy, &edge, &input, '\t').unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Error in {} on line 1: expected 1 fields for edge/1, found 2",
+            input.display()
+        )
+    );
+
+    write(&input, "a\rb\tc\n").unwrap();
+    let err = load_csv(&mut copy, &edge, &input, '\t').unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Error in {} on line 1: unexpected '\\r' in unquoted field",
+            input.display()
+        )
+    );
+    remove_file(&input).unwrap();
+    remove_file(&output).unwrap();
+}
@@ -0,0 +1,389 @@
+//! Persistent storage for the facts and rules of a program.
+//!
+//! A database is a directory holding a snapshot of its declarations and
+//! clauses, written as a Datalog program, and a write-ahead log of the
+//! declarations, assertions and retractions made since the snapshot was
+//! taken. Both are numbered with a generation,
+//! so `snapshot-3.dl` is followed by `log-3`; taking a new snapshot starts a
+//! new generation, and only then are the old files removed.
+//!
+//! Each log record is a single statement, preceded by a header line with its
+//! length in bytes and a checksum:
+//!
+//! ```text
+//! 19 eb61d956
+//! edge(a, b) :- c(a).
+//! ```
+//!
+//! A crash while a record is being appended leaves a truncated or garbled
+//! record at the end of the log. When the database is opened, the log is
+//! replayed up to the first such record, and truncated there.
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use {Bindings, Clause, Declaration, Interpeter, Literal, Name, Program,
+     Result, ResultExt, Statement};
+use sparkly::Sparkly;
+
+/// An interpreter whose clauses are stored on disk. A declaration, assertion
+/// or retraction is written to the log once the interpreter has accepted it,
+/// and is durable once it returns.
+#[derive(Debug)]
+pub struct Database<I> {
+    interpreter: I,
+    declarations: Vec<Declaration>,
+    clauses: Vec<Clause>,
+    stored: HashSet<Clause>,
+    dir: PathBuf,
+    generation: u64,
+    log: File,
+}
+
+impl<I: Interpeter> Database<I> {
+    /// Opens the database in the given directory, creating it if it doesn't
+    /// exist, and loads its clauses into the interpreter.
+    pub fn open<P>(dir: P, interpreter: I) -> Result<Database<I>>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut generation = 0;
+        for entry in fs::read_dir(&dir)? {
+            let name = entry?.file_name();
+            let name = name.to_string_lossy();
+            if let Some(n) = parse_generation(&name, "snapshot-", ".dl") {
+                generation = generation.max(n);
+            }
+        }
+
+        let snapshot = dir.join(format!("snapshot-{}.dl", generation));
+        let log_path = dir.join(format!("log-{}", generation));
+
+        let mut buf = Vec::new();
+        if log_path.exists() {
+            File::open(&log_path)?.read_to_end(&mut buf)?;
+        }
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)?;
+
+        let mut db = Database {
+            interpreter,
+            declarations: Vec::new(),
+            clauses: Vec::new(),
+            stored: HashSet::new(),
+            dir,
+            generation,
+            log,
+        };
+        if snapshot.exists() {
+            for stmt in Program::parse_file(&snapshot)?.0 {
+                db.apply(stmt)?;
+            }
+        }
+        let mut offset = 0;
+        while let Some((stmt, len)) = read_record(&buf[offset..]) {
+            let stmt = stmt.parse().chain_err(|| {
+                format!("Invalid record in {}", log_path.display())
+            })?;
+            db.apply(stmt)?;
+            offset += len;
+        }
+        if offset < buf.len() {
+            db.log.set_len(offset as u64)?;
+            db.log.sync_all()?;
+        }
+        db.remove_old_files()?;
+        Ok(db)
+    }
+
+    /// Returns the interpreter holding the database's clauses.
+    pub fn interpreter(&self) -> &I {
+        &self.interpreter
+    }
+
+    /// Writes every declaration and clause to a new snapshot, and starts a
+    /// new, empty log.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        let generation = self.generation + 1;
+        let snapshot = self.dir.join(format!("snapshot-{}.dl", generation));
+        let tmp = self.dir.join(format!("snapshot-{}.dl.tmp", generation));
+        {
+            let mut file = File::create(&tmp)?;
+            let declarations = self.declarations
+                .iter()
+                .cloned()
+                .map(Statement::Declaration);
+            let clauses = self.clauses
+                .iter()
+                .cloned()
+                .map(Statement::Assertion);
+            for stmt in declarations.chain(clauses) {
+                writeln!(file, "{}", stmt.to_doc().display_opts(80, false))?;
+            }
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &snapshot)?;
+        sync_dir(&self.dir)?;
+
+        self.log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(format!("log-{}", generation)))?;
+        self.generation = generation;
+        self.remove_old_files()
+    }
+
+    /// Runs a declaration, assertion or retraction against the interpreter,
+    /// and keeps track of the declarations and clauses that remain, each
+    /// only once.
+    fn apply(&mut self, stmt: Statement) -> Result<()> {
+        match stmt {
+            Statement::Declaration(decl) => {
+                self.interpreter.run_declaration(decl.clone())?;
+                if !self.declarations.contains(&decl) {
+                    self.declarations.push(decl);
+                }
+            }
+            Statement::Assertion(clause) => {
+                self.interpreter.run_assertion(clause.clone())?;
+                if self.stored.insert(clause.clone()) {
+                    self.clauses.push(clause);
+                }
+            }
+            Statement::Retraction(clause) => {
+                self.interpreter.run_retraction(clause.clone())?;
+                let clause = clause.schedule();
+                let stored = &mut self.stored;
+                self.clauses.retain(|c| {
+                    let retracted = clause.retracts(&c.clone().schedule());
+                    if retracted {
+                        stored.remove(c);
+                    }
+                    !retracted
+                });
+            }
+            Statement::Query(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Runs a declaration, assertion or retraction, then appends it to the
+    /// log.
+    fn apply_and_log(&mut self, stmt: Statement) -> Result<()> {
+        let text = stmt.to_doc().display_opts(80, false).to_string();
+        self.apply(stmt)?;
+        let sum = checksum(&text);
+        let record = format!("{} {:08x}\n{}\n", text.len(), sum, text);
+        self.log.write_all(record.as_bytes())?;
+        self.log.sync_data()?;
+        Ok(())
+    }
+
+    /// Removes the snapshots and logs of earlier generations, and any
+    /// snapshot left unfinished by a crash.
+    fn remove_old_files(&self) -> Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let old = parse_generation(&name, "snapshot-", ".dl")
+                .or_else(|| parse_generation(&name, "log-", ""))
+                .map_or(false, |n| n < self.generation);
+            if old || name.ends_with(".dl.tmp") {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<I: Interpeter> Interpeter for Database<I> {
+    fn run_declaration(&mut self, decl: Declaration) -> Result<()> {
+        self.apply_and_log(Statement::Declaration(decl))
+    }
+
+    fn run_assertion(&mut self, clause: Clause) -> Result<()> {
+        self.apply_and_log(Statement::Assertion(clause))
+    }
+
+    fn run_retraction(&mut self, clause: Clause) -> Result<()> {
+        self.apply_and_log(Statement::Retraction(clause))
+    }
+
+    fn run_query<'a>(
+        &'a self,
+        query: Literal,
+    ) -> Box<'a + Iterator<Item = Result<Bindings>>> {
+        self.interpreter.run_query(query)
+    }
+
+    fn arity(&self, name: &Name) -> Option<usize> {
+        self.interpreter.arity(name)
+    }
+}
+
+/// Reads the record at the start of the buffer, returning the statement in
+/// it and the length of the record. Returns `None` if the record is
+/// incomplete or corrupt.
+fn read_record(buf: &[u8]) -> Option<(&str, usize)> {
+    let header_len = buf.iter().position(|&b| b == b'\n')?;
+    let header = ::std::str::from_utf8(&buf[..header_len]).ok()?;
+    let mut fields = header.split(' ');
+    let len = fields.next()?.parse::<usize>().ok()?;
+    let sum = u32::from_str_radix(fields.next()?, 16).ok()?;
+
+    let start = header_len + 1;
+    let end = start.checked_add(len)?;
+    if buf.len() <= end || buf[end] != b'\n' {
+        return None;
+    }
+    let text = ::std::str::from_utf8(&buf[start..end]).ok()?;
+    if checksum(text) == sum {
+        Some((text, end + 1))
+    } else {
+        None
+    }
+}
+
+/// Computes the 32-bit FNV-1a hash of a string.
+fn checksum(s: &str) -> u32 {
+    s.bytes().fold(0x811c_9dc5, |hash, b| {
+        (hash ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Parses the generation out of a file name with the given prefix and
+/// suffix.
+fn parse_generation(name: &str, prefix: &str, suffix: &str) -> Option<u64> {
+    if name.starts_with(prefix) && name.ends_with(suffix) {
+        name[prefix.len()..name.len() - suffix.len()].parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Flushes a directory's entries to disk, so that a rename in it is durable.
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+fn test_dir(name: &str) -> PathBuf {
+    let dir = ::std::env::temp_dir()
+        .join(format!("datalog-{}-{}", name, ::std::process::id()));
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    dir
+}
+
+#[cfg(test)]
+fn answers<I: Interpeter>(db: &Database<I>, query: &str) -> Vec<String> {
+    let query = match query.parse().unwrap() {
+        Statement::Query(q) => q,
+        _ => unreachable!(),
+    };
+    let mut answers = db.run_goals(query)
+        .map(|b| {
+            let b = b.unwrap();
+            b.values().map(|v| v.to_string()).collect::<Vec<_>>().join(" ")
+        })
+        .collect::<Vec<_>>();
+    answers.sort();
+    answers
+}
+
+#[test]
+fn recovery() {
+    use NaiveInterpreter;
+
+    let dir = test_dir("recovery");
+    let program: Program = concat!(
+        "edge(a, b). edge(b, c). edge(c, d).\n",
+        "path(X, Y) :- edge(X, Y).\n",
+        "path(X, Y) :- edge(X, Z), path(Z, Y).\n",
+        "edge(b, c)~\n",
+    ).parse()
+        .unwrap();
+    {
+        let mut db = Database::open(&dir, NaiveInterpreter::new()).unwrap();
+        db.load_program(program).unwrap();
+        assert_eq!(answers(&db, "path(a, X)?"), ["b"]);
+    }
+
+    let mut db = Database::open(&dir, NaiveInterpreter::new()).unwrap();
+    assert_eq!(answers(&db, "path(a, X)?"), ["b"]);
+    db.run_stmt("edge(a, b).".parse().unwrap()).unwrap().count();
+    db.checkpoint().unwrap();
+    let snapshot = fs::read_to_string(dir.join("snapshot-1.dl")).unwrap();
+    assert_eq!(snapshot.matches("edge(a, b).").count(), 1);
+    db.run_stmt("edge(b, d).".parse().unwrap()).unwrap().count();
+    drop(db);
+
+    let files = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<::std::collections::BTreeSet<_>>();
+    assert_eq!(
+        files.into_iter().collect::<Vec<_>>(),
+        ["log-1", "snapshot-1.dl"]
+    );
+    let mut db = Database::open(&dir, NaiveInterpreter::new()).unwrap();
+    assert_eq!(answers(&db, "path(a, X)?"), ["b", "d"]);
+    assert!(db.run_stmt("edge(e, f)~".parse().unwrap()).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn truncated_log() {
+    use NaiveInterpreter;
+
+    let dir = test_dir("truncated-log");
+    {
+        let mut db = Database::open(&dir, NaiveInterpreter::new()).unwrap();
+        for stmt in &["edge(a, b).", "edge(b, c).", "edge(c, d)."] {
+            db.run_stmt(stmt.parse().unwrap()).unwrap().count();
+        }
+    }
+
+    // Cut the last record off in the middle, as if the process had crashed
+    // while appending it.
+    let log = dir.join("log-0");
+    let len = fs::metadata(&log).unwrap().len();
+    OpenOptions::new()
+        .write(true)
+        .open(&log)
+        .unwrap()
+        .set_len(len - 5)
+        .unwrap();
+
+    let mut db = Database::open(&dir, NaiveInterpreter::new()).unwrap();
+    assert_eq!(answers(&db, "edge(X, Y)?"), ["a b", "b c"]);
+    db.run_stmt("edge(d, e).".parse().unwrap()).unwrap().count();
+    drop(db);
+
+    let db = Database::open(&dir, NaiveInterpreter::new()).unwrap();
+    assert_eq!(answers(&db, "edge(X, Y)?"), ["a b", "b c", "d e"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn corrupt_record() {
+    let text = "edge(a, b).";
+    let record = format!("{} {:08x}\n{}\n", text.len(), checksum(text), text);
+    assert_eq!(read_record(record.as_bytes()), Some((text, record.len())));
+
+    let garbled = record.replace("a, b", "a, c");
+    assert_eq!(read_record(garbled.as_bytes()), None);
+    let short = &record.as_bytes()[..record.len() - 1];
+    assert_eq!(read_record(short), None);
+    assert_eq!(read_record(b"12"), None);
+}
@@ -4,19 +4,119 @@ use std::io::Error as IoError;
 
 use sparkly::Sparkly;
 
-use ast::Clause;
+use ast::{Clause, Declaration, Expr, Name, Span, Type, Value, Variable};
+use stratify::Dependency;
 
 error_chain! {
     errors {
         NoSuchClause(clause: Clause) {
             description("A non-existent clause was found")
-            display("The clause {} does not exist", clause.to_doc().display())
+            display("The clause {}{} does not exist",
+                    clause.to_doc().display(), at(clause.3))
         }
-        Parse(msg: String) {
-            description(&msg)
+        UnsafeVariable(var: Variable, clause: Clause) {
+            description("A clause is unsafe")
+            display("The clause {}{} is unsafe, since the variable {} does \
+                     not appear in a positive literal in its body",
+                    clause.to_doc().display(), at(clause.3), var)
+        }
+        Unstratifiable(cycle: Vec<(Name, usize)>, kinds: Vec<Dependency>) {
+            description("The program cannot be stratified")
+            display("The program cannot be stratified, since it has a cycle \
+                     through {}: {}", through(kinds[0]),
+                    print_cycle(cycle, kinds))
+        }
+        Redeclared(old: Declaration, new: Declaration) {
+            description("A predicate was declared twice")
+            display("The declaration {}{} conflicts with the earlier \
+                     declaration {}{}", new, at(new.2), old, at(old.2))
+        }
+        ArityMismatch(decl: Declaration, clause: Clause) {
+            description("A clause uses a predicate with the wrong arity")
+            display("The clause {}{} uses {} with the wrong number of \
+                     arguments; it is declared as {}",
+                    clause.to_doc().display(), at(clause.3), decl.0, decl)
+        }
+        TypeMismatch(decl: Declaration, clause: Clause, column: usize,
+                     found: Type) {
+            description("A clause puts a value of the wrong type in a column")
+            display("The clause {}{} puts {} in the {} column of {}, which \
+                     is declared as {}", clause.to_doc().display(),
+                    at(clause.3), a(found), (decl.1)[*column].0, decl.0, decl)
+        }
+        TypeConflict(clause: Clause, what: String, expected: Type,
+                     found: Type) {
+            description("A clause uses a value as two different types")
+            display("The clause {}{} uses {} as {}, where {} is expected",
+                    clause.to_doc().display(), at(clause.3), what, a(found),
+                    a(expected))
+        }
+        NotNumeric(clause: Clause, what: String, found: Type) {
+            description("A clause uses a value that is not a number in \
+                         arithmetic")
+            display("The clause {}{} uses {} as a number, but it is {}",
+                    clause.to_doc().display(), at(clause.3), what, a(found))
+        }
+        NotANumber(value: Value) {
+            description("A value used in arithmetic is not a number")
+            display("The value {} is not a number", value)
+        }
+        Arithmetic(expr: Expr, msg: String) {
+            description("An arithmetic expression could not be evaluated")
+            display("Could not evaluate {}: {}", expr.to_doc().display(), msg)
+        }
+        Parse(line: usize, col: usize, msg: String, excerpt: String) {
+            description("A parse error occurred")
+            display("Parse error at {}:{}: {}\n{}", line, col, msg, excerpt)
+        }
+        Csv(path: String, line: usize, msg: String) {
+            description("A delimited file could not be read")
+            display("Error in {} on line {}: {}", path, line, msg)
         }
     }
     foreign_links {
         Io(IoError);
     }
 }
+
+/// Describes where a clause or declaration was in its source, for example
+/// " at 3:1", or nothing if it was not parsed from one.
+fn at(span: Option<Span>) -> String {
+    match span {
+        Some(span) => format!(" at {}", span),
+        None => String::new(),
+    }
+}
+
+/// Names a type with an indefinite article, for example "an int".
+fn a(ty: &Type) -> String {
+    match *ty {
+        Type::Int => format!("an {}", ty),
+        _ => format!("a {}", ty),
+    }
+}
+
+/// Names what a negative dependency goes through.
+fn through(kind: Dependency) -> &'static str {
+    match kind {
+        Dependency::Aggregated(_) => "an aggregate",
+        _ => "negation",
+    }
+}
+
+/// Prints a cycle of predicates, marking each step by how the predicate
+/// before it depends on it.
+fn print_cycle(cycle: &[(Name, usize)], kinds: &[Dependency]) -> String {
+    let mut out = String::new();
+    for (i, &(ref name, arity)) in cycle.iter().enumerate() {
+        let pred = format!("{}/{}", name, arity);
+        let step = match i.checked_sub(1).map(|i| kinds[i]) {
+            None => pred,
+            Some(Dependency::Positive) => format!(" -> {}", pred),
+            Some(Dependency::Negated) => format!(" -> not {}", pred),
+            Some(Dependency::Aggregated(op)) => format!(" -> {}<{}>", op, pred),
+        };
+        out.push_str(&step);
+    }
+    out
+}
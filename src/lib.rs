@@ -23,27 +23,47 @@ extern crate proptest;
 extern crate regex;
 
 mod ast;
+mod csv;
+mod db;
 mod errors;
+mod lint;
+mod magic;
 mod naive;
 pub(crate) mod parser;
+mod schema;
+mod seminaive;
+mod stratify;
+mod tabled;
 mod util;
 mod wam;
 
-use std::collections::BTreeMap;
-use std::iter::empty;
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter::{empty, once};
 
-use sparkly::Sparkly;
-
-pub use ast::{styles, Clause, Literal, Name, Program, Statement, Term,
-              Variable};
+pub use ast::{styles, Aggregate, ArithOp, Clause, CmpOp, Declaration, Expr,
+              Goal, Literal, Name, Program, Span, Statement, Term, Type,
+              Value, Variable};
+pub use csv::{load_csv, save_csv};
+pub use db::Database;
 pub use errors::{Error, ErrorKind, Result, ResultExt};
+pub use lint::Lint;
 pub use naive::NaiveInterpreter;
+pub use schema::Types;
+pub use seminaive::SemiNaiveInterpreter;
+pub use stratify::Dependency;
+pub use tabled::TabledInterpreter;
+pub use wam::WamInterpreter;
 
 /// Bindings from variable names to values.
-pub type Bindings = BTreeMap<Variable, Name>;
+pub type Bindings = BTreeMap<Variable, Value>;
 
 /// A Datalog interpreter.
 pub trait Interpeter {
+    /// Declares a predicate, so that later assertions using it are checked
+    /// against the declaration. The facts and rules already stored that use
+    /// it must agree with the declaration too.
+    fn run_declaration(&mut self, decl: Declaration) -> Result<()>;
+
     /// Adds an assertion to the fact set.
     fn run_assertion(&mut self, clause: Clause) -> Result<()>;
 
@@ -51,18 +71,73 @@ pub trait Interpeter {
     fn run_retraction(&mut self, clause: Clause) -> Result<()>;
 
     /// Runs a query against the fact set. Returns an iterator over variable
-    /// bindings that make it true.
+    /// bindings that make it true. If solving the query fails, for example
+    /// due to arithmetic on a name that is not a number, the error is yielded
+    /// as an item.
     fn run_query<'a>(
         &'a self,
         query: Literal,
-    ) -> Box<'a + Iterator<Item = Bindings>>;
+    ) -> Box<'a + Iterator<Item = Result<Bindings>>>;
+
+    /// Returns the arity of the predicate with the given name: that of its
+    /// declaration, or else that of the facts and rules stored for it, if
+    /// they all have the same one.
+    fn arity(&self, name: &Name) -> Option<usize>;
+
+    /// Runs a query with any number of goals, joining the answers to each
+    /// from left to right. As in the body of a rule, negated goals and
+    /// comparisons are put off until the goals that bind their variables.
+    /// Anonymous variables are left out of the bindings.
+    fn run_goals<'a>(
+        &'a self,
+        goals: Vec<Goal>,
+    ) -> Box<'a + Iterator<Item = Result<Bindings>>> {
+        let answers = join(self, ast::schedule_goals(&goals), Bindings::new());
+        Box::new(answers.map(|bindings| {
+            bindings.map(|bindings| {
+                bindings
+                    .into_iter()
+                    .filter(|&(ref var, _)| !var.is_anonymous())
+                    .collect()
+            })
+        }))
+    }
+
+    /// Returns whether a query has any answers. Unlike checking whether
+    /// `run_goals` yields anything, this tells a ground query that succeeds,
+    /// with no bindings, apart from one that fails, without an error being
+    /// mistaken for either.
+    fn holds(&self, goals: Vec<Goal>) -> Result<bool> {
+        match self.run_goals(goals).next() {
+            Some(Ok(_)) => Ok(true),
+            Some(Err(err)) => Err(err),
+            None => Ok(false),
+        }
+    }
+
+    /// Runs a query like `run_goals`, but yields each distinct set of
+    /// bindings only once, even if it can be derived in more than one way.
+    fn run_query_distinct<'a>(
+        &'a self,
+        goals: Vec<Goal>,
+    ) -> Box<'a + Iterator<Item = Result<Bindings>>> {
+        let mut seen = BTreeSet::new();
+        Box::new(self.run_goals(goals).filter(move |b| match *b {
+            Ok(ref b) => seen.insert(b.clone()),
+            Err(_) => true,
+        }))
+    }
 
     /// Runs a statement.
     fn run_stmt<'a>(
         &'a mut self,
         stmt: Statement,
-    ) -> Result<Box<'a + Iterator<Item = Bindings>>> {
+    ) -> Result<Box<'a + Iterator<Item = Result<Bindings>>>> {
         match stmt {
+            Statement::Declaration(decl) => {
+                self.run_declaration(decl)?;
+                Ok(Box::new(empty()))
+            }
             Statement::Assertion(clause) => {
                 self.run_assertion(clause)?;
                 Ok(Box::new(empty()))
@@ -71,18 +146,43 @@ pub trait Interpeter {
                 self.run_retraction(clause)?;
                 Ok(Box::new(empty()))
             }
-            Statement::Query(query) => Ok(self.run_query(query)),
+            Statement::Query(goals) => Ok(self.run_goals(goals)),
         }
     }
 
-    /// Loads a program into the interpreter.
-    fn load_program(&mut self, program: Program) -> Result<()> {
+    /// Loads a program into the interpreter, running its statements in
+    /// order. Returns each query in the program, with its distinct answers
+    /// as of when it was run.
+    fn load_program(
+        &mut self,
+        program: Program,
+    ) -> Result<Vec<(Vec<Goal>, Vec<Bindings>)>> {
+        let mut answers = Vec::new();
+        self.load_program_with(program, &mut |goals, bindings| {
+            answers.push((goals, bindings));
+            Ok(())
+        })?;
+        Ok(answers)
+    }
+
+    /// Loads a program like `load_program`, but passes each query and its
+    /// answers to the given function as soon as it has been run, so they
+    /// are not lost if a later statement fails. Stops at the first error,
+    /// whether from a statement or from the function.
+    fn load_program_with(
+        &mut self,
+        program: Program,
+        on_query: &mut FnMut(Vec<Goal>, Vec<Bindings>) -> Result<()>,
+    ) -> Result<()> {
         for stmt in program.0 {
             match stmt {
+                Statement::Declaration(decl) => self.run_declaration(decl)?,
                 Statement::Assertion(clause) => self.run_assertion(clause)?,
                 Statement::Retraction(clause) => self.run_retraction(clause)?,
-                Statement::Query(q) => {
-                    unimplemented!("run query on load {}", q.to_doc().display())
+                Statement::Query(goals) => {
+                    let bindings = self.run_query_distinct(goals.clone())
+                        .collect::<Result<_>>()?;
+                    on_query(goals, bindings)?;
                 }
             }
         }
@@ -90,12 +190,63 @@ pub trait Interpeter {
     }
 }
 
+/// Solves each goal in turn, extending the given bindings with the answers to
+/// it. A negated goal succeeds, without binding anything, if the literal
+/// inside it has no answers; a comparison is evaluated directly.
+fn join<'a, I: Interpeter + ?Sized>(
+    interpreter: &'a I,
+    mut goals: Vec<Goal>,
+    bindings: Bindings,
+) -> Box<'a + Iterator<Item = Result<Bindings>>> {
+    if goals.is_empty() {
+        return Box::new(once(Ok(bindings)));
+    }
+
+    match goals.remove(0).substitute(&bindings) {
+        Goal::Literal(goal) => {
+            Box::new(interpreter.run_query(goal).flat_map(move |new| {
+                match new {
+                    Ok(new) => {
+                        let mut bindings = bindings.clone();
+                        bindings.extend(new);
+                        join(interpreter, goals.clone(), bindings)
+                    }
+                    Err(err) => Box::new(once(Err(err))),
+                }
+            }))
+        }
+        Goal::Not(goal) => match interpreter.run_query(goal).next() {
+            None => join(interpreter, goals, bindings),
+            Some(Ok(_)) => Box::new(empty()),
+            Some(Err(err)) => Box::new(once(Err(err))),
+        },
+        Goal::Compare(l, op, r) => match op.eval(&l, &r) {
+            Ok(Some(new)) => {
+                let mut bindings = bindings;
+                bindings.extend(new);
+                join(interpreter, goals, bindings)
+            }
+            Ok(None) => Box::new(empty()),
+            Err(err) => Box::new(once(Err(err))),
+        },
+    }
+}
+
 /// An enumeration over the interpreters defined in this crate. Using this type
 /// lowers dynamic dispatch overhead somewhat.
 #[derive(Debug)]
 pub enum DynamicInterpreter {
     /// A naive interpreter.
     Naive(NaiveInterpreter),
+
+    /// A semi-naive bottom-up interpreter.
+    SemiNaive(SemiNaiveInterpreter),
+
+    /// A tabled top-down interpreter.
+    Tabled(TabledInterpreter),
+
+    /// An interpreter based on the Warren Abstract Machine.
+    Wam(WamInterpreter),
 }
 
 impl From<NaiveInterpreter> for DynamicInterpreter {
@@ -104,40 +255,254 @@ impl From<NaiveInterpreter> for DynamicInterpreter {
     }
 }
 
+impl From<SemiNaiveInterpreter> for DynamicInterpreter {
+    fn from(i: SemiNaiveInterpreter) -> DynamicInterpreter {
+        DynamicInterpreter::SemiNaive(i)
+    }
+}
+
+impl From<TabledInterpreter> for DynamicInterpreter {
+    fn from(i: TabledInterpreter) -> DynamicInterpreter {
+        DynamicInterpreter::Tabled(i)
+    }
+}
+
+impl From<WamInterpreter> for DynamicInterpreter {
+    fn from(i: WamInterpreter) -> DynamicInterpreter {
+        DynamicInterpreter::Wam(i)
+    }
+}
+
 impl Interpeter for DynamicInterpreter {
+    fn run_declaration(&mut self, decl: Declaration) -> Result<()> {
+        match *self {
+            DynamicInterpreter::Naive(ref mut i) => i.run_declaration(decl),
+            DynamicInterpreter::SemiNaive(ref mut i) => {
+                i.run_declaration(decl)
+            }
+            DynamicInterpreter::Tabled(ref mut i) => i.run_declaration(decl),
+            DynamicInterpreter::Wam(ref mut i) => i.run_declaration(decl),
+        }
+    }
+
     fn run_assertion(&mut self, clause: Clause) -> Result<()> {
         match *self {
             DynamicInterpreter::Naive(ref mut i) => i.run_assertion(clause),
+            DynamicInterpreter::SemiNaive(ref mut i) => i.run_assertion(clause),
+            DynamicInterpreter::Tabled(ref mut i) => i.run_assertion(clause),
+            DynamicInterpreter::Wam(ref mut i) => i.run_assertion(clause),
         }
     }
 
     fn run_retraction(&mut self, clause: Clause) -> Result<()> {
         match *self {
             DynamicInterpreter::Naive(ref mut i) => i.run_retraction(clause),
+            DynamicInterpreter::SemiNaive(ref mut i) => {
+                i.run_retraction(clause)
+            }
+            DynamicInterpreter::Tabled(ref mut i) => i.run_retraction(clause),
+            DynamicInterpreter::Wam(ref mut i) => i.run_retraction(clause),
         }
     }
 
     fn run_query<'a>(
         &'a self,
         query: Literal,
-    ) -> Box<'a + Iterator<Item = Bindings>> {
+    ) -> Box<'a + Iterator<Item = Result<Bindings>>> {
         match *self {
             DynamicInterpreter::Naive(ref i) => i.run_query(query),
+            DynamicInterpreter::SemiNaive(ref i) => i.run_query(query),
+            DynamicInterpreter::Tabled(ref i) => i.run_query(query),
+            DynamicInterpreter::Wam(ref i) => i.run_query(query),
+        }
+    }
+
+    fn arity(&self, name: &Name) -> Option<usize> {
+        match *self {
+            DynamicInterpreter::Naive(ref i) => i.arity(name),
+            DynamicInterpreter::SemiNaive(ref i) => i.arity(name),
+            DynamicInterpreter::Tabled(ref i) => i.arity(name),
+            DynamicInterpreter::Wam(ref i) => i.arity(name),
         }
     }
 
     fn run_stmt<'a>(
         &'a mut self,
         stmt: Statement,
-    ) -> Result<Box<'a + Iterator<Item = Bindings>>> {
+    ) -> Result<Box<'a + Iterator<Item = Result<Bindings>>>> {
         match *self {
             DynamicInterpreter::Naive(ref mut i) => i.run_stmt(stmt),
+            DynamicInterpreter::SemiNaive(ref mut i) => i.run_stmt(stmt),
+            DynamicInterpreter::Tabled(ref mut i) => i.run_stmt(stmt),
+            DynamicInterpreter::Wam(ref mut i) => i.run_stmt(stmt),
         }
     }
 
-    fn load_program(&mut self, program: Program) -> Result<()> {
+    fn load_program(
+        &mut self,
+        program: Program,
+    ) -> Result<Vec<(Vec<Goal>, Vec<Bindings>)>> {
         match *self {
             DynamicInterpreter::Naive(ref mut i) => i.load_program(program),
+            DynamicInterpreter::SemiNaive(ref mut i) => i.load_program(program),
+            DynamicInterpreter::Tabled(ref mut i) => i.load_program(program),
+            DynamicInterpreter::Wam(ref mut i) => i.load_program(program),
+        }
+    }
+
+    fn load_program_with(
+        &mut self,
+        program: Program,
+        on_query: &mut FnMut(Vec<Goal>, Vec<Bindings>) -> Result<()>,
+    ) -> Result<()> {
+        match *self {
+            DynamicInterpreter::Naive(ref mut i) => {
+                i.load_program_with(program, on_query)
+            }
+            DynamicInterpreter::SemiNaive(ref mut i) => {
+                i.load_program_with(program, on_query)
+            }
+            DynamicInterpreter::Tabled(ref mut i) => {
+                i.load_program_with(program, on_query)
+            }
+            DynamicInterpreter::Wam(ref mut i) => {
+                i.load_program_with(program, on_query)
+            }
         }
     }
 }
+
+#[test]
+fn conjunctions() {
+    let program: Program = concat!(
+        "edge(a, b). edge(b, c). edge(b, d). edge(c, d).\n",
+        "red(c).\n",
+        "middle(X) :- edge(X, _), edge(_, X).\n",
+    ).parse()
+        .unwrap();
+    let interpreters: Vec<DynamicInterpreter> = vec![
+        NaiveInterpreter::new().into(),
+        SemiNaiveInterpreter::new().into(),
+        TabledInterpreter::new().into(),
+        WamInterpreter::new().into(),
+    ];
+    for mut interpreter in interpreters {
+        interpreter.load_program(program.clone()).unwrap();
+        let mut query = |s: &str| -> BTreeSet<String> {
+            interpreter
+                .run_stmt(s.parse().unwrap())
+                .unwrap()
+                .map(|b| {
+                    let b = b.unwrap();
+                    let mut vars = b.iter()
+                        .map(|(v, n)| format!("{}={}", v, n))
+                        .collect::<Vec<_>>();
+                    vars.sort();
+                    vars.join(" ")
+                })
+                .collect()
+        };
+        let set = |answers: &[&str]| -> BTreeSet<String> {
+            answers.iter().map(|s| s.to_string()).collect()
+        };
+
+        assert_eq!(
+            query("edge(a, X), edge(X, Y)?"),
+            set(&["X=b Y=c", "X=b Y=d"])
+        );
+        assert_eq!(
+            query("edge(X, Y), not red(Y), X != a?"),
+            set(&["X=b Y=d", "X=c Y=d"])
+        );
+        assert_eq!(query("X != a, edge(X, d), red(X)?"), set(&["X=c"]));
+        assert_eq!(query("edge(a, b), edge(b, c)?"), set(&[""]));
+        assert_eq!(query("edge(a, b), red(a)?"), set(&[]));
+        assert_eq!(query("edge(X, _), edge(_, X)?"), set(&["X=b", "X=c"]));
+        assert_eq!(query("middle(X)?"), set(&["X=b", "X=c"]));
+        assert_eq!(query("edge(_, _)?"), set(&[""]));
+        assert_eq!(query("edge(_X, d)?"), set(&["_X=b", "_X=c"]));
+    }
+}
+
+#[test]
+fn declarations_after_assertions() {
+    let program: Program = concat!(
+        "edge(a, b). edge(b, c).\n",
+        "path(X, Y) :- edge(X, Y).\n",
+    ).parse()
+        .unwrap();
+    let interpreters: Vec<DynamicInterpreter> = vec![
+        NaiveInterpreter::new().into(),
+        SemiNaiveInterpreter::new().into(),
+        TabledInterpreter::new().into(),
+        WamInterpreter::new().into(),
+    ];
+    for mut interpreter in interpreters {
+        interpreter.load_program(program.clone()).unwrap();
+        let mut declare = |s: &str| {
+            interpreter.run_stmt(s.parse().unwrap()).map(|i| i.count())
+        };
+
+        match *declare(".decl edge(src: symbol)").unwrap_err().kind() {
+            ErrorKind::ArityMismatch(..) => {}
+            ref err => panic!("unexpected error {}", err),
+        }
+        match *declare(".decl edge(src: symbol, dst: int)")
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::TypeMismatch(_, _, 1, _) => {}
+            ref err => panic!("unexpected error {}", err),
+        }
+        declare(".decl edge(src: symbol, dst: symbol)").unwrap();
+
+        // The rule for path uses edge, so its columns are symbols too.
+        match *declare(".decl path(src: int, dst: symbol)")
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::TypeMismatch(_, _, 0, _) => {}
+            ref err => panic!("unexpected error {}", err),
+        }
+        declare(".decl path(src: symbol, dst: symbol)").unwrap();
+    }
+}
+
+#[test]
+fn queries_on_load() {
+    let program: Program = concat!(
+        "red(apple).\n",
+        "spicy(X) :- red(X), vegetable(X).\n",
+        "spicy(X)?\n",
+        "vegetable(apple).\n",
+        "spicy(X)?\n",
+        "vegetable(apple)~\n",
+        "spicy(apple)?\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = NaiveInterpreter::new();
+    let answers = interpreter.load_program(program).unwrap();
+    let counts = answers
+        .iter()
+        .map(|&(ref query, ref bindings)| (query.len(), bindings.len()))
+        .collect::<Vec<_>>();
+    assert_eq!(counts, vec![(1, 0), (1, 1), (1, 0)]);
+
+    let apple = Value::new("apple").unwrap();
+    assert_eq!(answers[1].1[0][&Variable::new("X").unwrap()], apple);
+
+    // The answers to queries before an error are still reported.
+    let program: Program = "red(X)? green(apple)~ red(X)?".parse().unwrap();
+    let mut counts = Vec::new();
+    let err = interpreter
+        .load_program_with(program, &mut |_, bindings| {
+            counts.push(bindings.len());
+            Ok(())
+        })
+        .unwrap_err();
+    match *err.kind() {
+        ErrorKind::NoSuchClause(_) => {}
+        ref err => panic!("unexpected error {}", err),
+    }
+    assert_eq!(counts, vec![1]);
+}
@@ -0,0 +1,332 @@
+//! Finding likely mistakes in a program without running it.
+
+use std::collections::{HashMap, HashSet};
+
+use {Clause, Declaration, Error, ErrorKind, Goal, Literal, Name, Program,
+     Span, Statement, Term};
+use schema::infer;
+use stratify::stratify;
+
+/// A problem found in a program by `Program::lint`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Lint {
+    /// Where the problem is in the source, if the program was parsed from
+    /// one.
+    pub span: Option<Span>,
+
+    /// Whether the problem would stop the program from running, rather than
+    /// just being a likely mistake.
+    pub is_error: bool,
+
+    /// A description of the problem.
+    pub msg: String,
+}
+
+impl Lint {
+    fn error(span: Option<Span>, msg: String) -> Lint {
+        Lint {
+            span,
+            is_error: true,
+            msg,
+        }
+    }
+
+    fn warning(span: Option<Span>, msg: String) -> Lint {
+        Lint {
+            span,
+            is_error: false,
+            msg,
+        }
+    }
+}
+
+/// Looks for problems in a program, returning them in the order they appear
+/// in its source. Rules that no query depends on are only reported if the
+/// program has queries, since otherwise it is presumably queried elsewhere.
+pub fn lint(program: &Program) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    let clauses = program
+        .0
+        .iter()
+        .filter_map(|stmt| match *stmt {
+            Statement::Assertion(ref clause) => Some(clause),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    for &clause in &clauses {
+        if let Err(err) = clause.check_safety() {
+            lints.push(Lint::error(clause.span(), message(&err)));
+        } else {
+            for term in singletons(clause) {
+                let msg = format!(
+                    "The variable {} appears only once in its clause; if \
+                     that is intended, it can be written as _",
+                    term
+                );
+                lints.push(Lint::warning(term.span(), msg));
+            }
+        }
+    }
+    for (span, err) in infer(program).1 {
+        lints.push(Lint::error(span, message(&err)));
+    }
+
+    // A cycle through negation or an aggregate is reported at the rule that
+    // depends on the next predicate in the cycle that way.
+    if let Err(err) = stratify(clauses.iter().cloned()) {
+        if let ErrorKind::Unstratifiable(ref cycle, _) = *err.kind() {
+            let rule = clauses.iter().find(|clause| {
+                clause.pred() == cycle[0] && clause.body().iter().any(|goal| {
+                    let negative = goal.is_negated() || !clause.2.is_empty();
+                    let pred = literal(goal).map(Literal::signature);
+                    negative && pred.as_ref() == Some(&cycle[1])
+                })
+            });
+            let span = rule.and_then(|clause| clause.span());
+            lints.push(Lint::error(span, message(&err)));
+        }
+    }
+
+    // A predicate is defined by its declaration, or any clause for it.
+    let mut defined = clauses
+        .iter()
+        .map(|clause| clause.pred())
+        .collect::<HashSet<_>>();
+    let mut declared = HashMap::new();
+    for stmt in &program.0 {
+        if let Statement::Declaration(ref decl) = *stmt {
+            defined.insert(decl.pred());
+            declared.entry(decl.0.clone()).or_insert(decl.1.len());
+        }
+    }
+    for &clause in &clauses {
+        let mut reported = HashSet::new();
+        for literal in clause.body().iter().filter_map(literal) {
+            let pred = literal.signature();
+            if !defined.contains(&pred) && reported.insert(pred.clone()) {
+                let msg = format!(
+                    "The predicate {}/{} is used, but has no clauses or \
+                     declaration",
+                    pred.0, pred.1
+                );
+                lints.push(Lint::warning(literal.span(), msg));
+            }
+        }
+    }
+
+    // Each name should have one arity, which is the declared one if there
+    // is a declaration, and otherwise the first one used.
+    let mut arities = declared.clone();
+    for stmt in &program.0 {
+        let mut reported = HashSet::new();
+        for (pred, span) in signatures(stmt) {
+            let arity = *arities.entry(pred.0.clone()).or_insert(pred.1);
+            if arity == pred.1 || !reported.insert(pred.clone()) {
+                continue;
+            }
+            let msg = format!(
+                "The predicate {} is used with {} arguments, but elsewhere \
+                 with {}",
+                pred.0, pred.1, arity
+            );
+            if declared.contains_key(&pred.0) {
+                lints.push(Lint::error(span, msg));
+            } else {
+                lints.push(Lint::warning(span, msg));
+            }
+        }
+    }
+
+    // If the program has queries, every rule should contribute to one.
+    let mut used = HashSet::new();
+    let mut todo = program
+        .0
+        .iter()
+        .filter_map(|stmt| match *stmt {
+            Statement::Query(ref goals) => Some(goals),
+            _ => None,
+        })
+        .flat_map(|goals| goals.iter().filter_map(literal))
+        .map(Literal::signature)
+        .collect::<Vec<_>>();
+    let has_queries = !todo.is_empty();
+    while let Some(pred) = todo.pop() {
+        if !used.insert(pred.clone()) {
+            continue;
+        }
+        for &clause in &clauses {
+            if clause.pred() == pred {
+                let body = clause.body().iter().filter_map(literal);
+                todo.extend(body.map(Literal::signature));
+            }
+        }
+    }
+    for &clause in &clauses {
+        if has_queries && !clause.body().is_empty()
+            && !used.contains(&clause.pred())
+        {
+            let msg = format!(
+                "This rule for {}/{} is not used by any of the program's \
+                 queries",
+                clause.head().0,
+                clause.head().1.len()
+            );
+            lints.push(Lint::warning(clause.span(), msg));
+        }
+    }
+
+    lints.sort_by_key(|lint| lint.span);
+    lints
+}
+
+/// Returns the literal in a goal, whether or not it is negated.
+fn literal(goal: &Goal) -> Option<&Literal> {
+    match *goal {
+        Goal::Literal(ref l) | Goal::Not(ref l) => Some(l),
+        Goal::Compare(..) => None,
+    }
+}
+
+/// Returns the name and arity of each predicate a statement uses, along with
+/// where it is used.
+fn signatures(stmt: &Statement) -> Vec<((Name, usize), Option<Span>)> {
+    let literals = match *stmt {
+        Statement::Declaration(ref decl) => return vec![(decl.pred(), decl.2)],
+        Statement::Assertion(ref clause)
+        | Statement::Retraction(ref clause) => {
+            let body = clause.body().iter().filter_map(literal);
+            Some(clause.head()).into_iter().chain(body).collect()
+        }
+        Statement::Query(ref goals) => {
+            goals.iter().filter_map(literal).collect::<Vec<_>>()
+        }
+    };
+    literals
+        .into_iter()
+        .map(|literal| (literal.signature(), literal.span()))
+        .collect()
+}
+
+/// Returns the message for an error, leaving out the location of the clause
+/// or declaration it is about, since a lint gives its location separately.
+fn message(err: &Error) -> String {
+    let clause =
+        |c: &Clause| Clause(c.0.clone(), c.1.clone(), c.2.clone(), None);
+    let kind = match *err.kind() {
+        ErrorKind::UnsafeVariable(ref var, ref c) => {
+            ErrorKind::UnsafeVariable(var.clone(), clause(c))
+        }
+        ErrorKind::Redeclared(ref old, ref new) => {
+            let new = Declaration(new.0.clone(), new.1.clone(), None);
+            ErrorKind::Redeclared(old.clone(), new)
+        }
+        ErrorKind::ArityMismatch(ref decl, ref c) => {
+            ErrorKind::ArityMismatch(decl.clone(), clause(c))
+        }
+        ErrorKind::TypeMismatch(ref decl, ref c, column, found) => {
+            ErrorKind::TypeMismatch(decl.clone(), clause(c), column, found)
+        }
+        ErrorKind::TypeConflict(ref c, ref what, expected, found) => {
+            ErrorKind::TypeConflict(clause(c), what.clone(), expected, found)
+        }
+        ErrorKind::NotNumeric(ref c, ref what, found) => {
+            ErrorKind::NotNumeric(clause(c), what.clone(), found)
+        }
+        _ => return err.to_string(),
+    };
+    kind.to_string()
+}
+
+/// Returns the variables that appear only once in a clause, other than
+/// anonymous ones, in order.
+fn singletons(clause: &Clause) -> Vec<&Term> {
+    let mut terms = clause.head().1.iter().collect::<Vec<_>>();
+    for goal in clause.body() {
+        match *goal {
+            Goal::Literal(ref l) | Goal::Not(ref l) => terms.extend(&l.1),
+            Goal::Compare(ref l, _, ref r) => {
+                terms.extend(l.terms());
+                terms.extend(r.terms());
+            }
+        }
+    }
+
+    let mut counts = HashMap::new();
+    let mut vars = Vec::new();
+    for term in terms {
+        if let Term::Var(ref var, _) = *term {
+            if !var.is_anonymous() {
+                *counts.entry(var).or_insert(0) += 1;
+                vars.push((var, term));
+            }
+        }
+    }
+    vars.into_iter()
+        .filter(|&(var, _)| counts[var] == 1)
+        .map(|(_, term)| term)
+        .collect()
+}
+
+#[test]
+fn problems() {
+    let program: Program = concat!(
+        ".decl edge(src: symbol, dst: symbol)\n",
+        "edge(a, b). edge(b, c).\n",
+        "path(X, Y) :- edge(X, Y).\n",
+        "path(X, Y) :- path(X, Z), edge(Z, Y).\n",
+        "loop(X) :- path(X, X), node(X).\n",
+        "far(X, Y) :- path(X, Y), not edge(X, Y, Z).\n",
+        "lonely(X) :- edge(X, Y).\n",
+        "weird(X, Y) :- edge(X, _).\n",
+        "bad(N) :- path(N, Y), N > 1.\n",
+        "loop(X)? lonely(X)?\n",
+    ).parse()
+        .unwrap();
+    let lints = program
+        .lint()
+        .into_iter()
+        .map(|lint| {
+            let span = lint.span.unwrap();
+            (span.line, span.col, lint.is_error, lint.msg)
+        })
+        .collect::<Vec<_>>();
+    let expected = vec![
+        (5, 24, false, "predicate node/1 is used, but has no clauses"),
+        (6, 1, true, "since the variable Z does not appear"),
+        (6, 1, false, "rule for far/2 is not used"),
+        (6, 30, false, "predicate edge/3 is used, but has no clauses"),
+        (6, 30, true, "edge is used with 3 arguments, but elsewhere with 2"),
+        (7, 22, false, "variable Y appears only once"),
+        (8, 1, true, "since the variable Y does not appear"),
+        (8, 1, false, "rule for weird/2 is not used"),
+        (9, 1, false, "rule for bad/1 is not used"),
+        (9, 19, false, "variable Y appears only once"),
+        (9, 23, true, "uses N as a number, but it is a symbol"),
+    ];
+    assert_eq!(lints.len(), expected.len());
+    for (lint, expected) in lints.iter().zip(expected) {
+        let (line, col, is_error, ref msg) = *lint;
+        assert_eq!((line, col, is_error), (expected.0, expected.1, expected.2));
+        assert!(msg.contains(expected.3), "{:?}", lint);
+        assert!(!msg.contains(" at "), "{:?}", lint);
+    }
+
+    // Following the advice for a singleton variable silences it.
+    let program: Program =
+        "edge(a, b). lonely(X) :- edge(X, _).".parse().unwrap();
+    assert_eq!(program.lint(), vec![]);
+
+    // A cycle through negation is reported at the rule that closes it.
+    let program: Program = concat!(
+        "q(a).\n",
+        "p(X) :- q(X), not r(X).\n",
+        "r(X) :- q(X), not p(X).\n",
+    ).parse()
+        .unwrap();
+    let lints = program.lint();
+    assert_eq!(lints.len(), 1);
+    let span = lints[0].span.unwrap();
+    assert_eq!((span.line, span.col, lints[0].is_error), (2, 1, true));
+    assert!(lints[0].msg.contains("cannot be stratified"), "{:?}", lints[0]);
+}
@@ -0,0 +1,366 @@
+//! Magic-sets rewriting, which lets a bottom-up interpreter answer a query
+//! without computing every fact about the queried predicate.
+//!
+//! Each rule is specialized to an *adornment* of its head, recording which
+//! arguments are bound (`b`) and which are free (`f`) when it is used. For
+//! every adorned predicate `p_bf`, a "magic" predicate `magic_p_bf` holds the
+//! bound arguments of the subgoals that need `p_bf` solved, and each rule for
+//! `p_bf` is guarded by it. Bindings are passed through rule bodies from left
+//! to right. If the program already uses one of these names, a numeric suffix
+//! is added until it is unused.
+//!
+//! Negated literals and literals about aggregated predicates are not
+//! rewritten, since they must be solved completely anyway; the original rules
+//! for the predicates they depend on are kept instead. Comparisons are kept as
+//! they are, and bind the variables they assign.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use {Clause, Goal, Literal, Name, Program, Statement, Term, Variable};
+
+/// Rewrites the rules for a query. `facts` should contain every predicate
+/// which has facts. Returns the rewritten rules, which begin with the seed
+/// fact for the query's magic predicate, and the query against the adorned
+/// predicate. If the query is not about a predicate defined by a rule, there
+/// is nothing to rewrite, and `None` is returned; likewise if it is about an
+/// aggregated predicate, whose rules need their bodies solved in full.
+pub fn rewrite<'a, I>(
+    rules: I,
+    facts: &HashSet<(Name, usize)>,
+    query: &Literal,
+) -> Option<(Vec<Clause>, Literal)>
+where
+    I: IntoIterator<Item = &'a Clause>,
+{
+    let rules = rules.into_iter().collect::<Vec<_>>();
+    let idb = rules.iter().map(|r| r.pred()).collect::<HashSet<_>>();
+    let aggregated = rules
+        .iter()
+        .filter(|r| r.is_aggregate())
+        .map(|r| r.pred())
+        .collect::<HashSet<_>>();
+    if !idb.contains(&query.signature())
+        || aggregated.contains(&query.signature())
+    {
+        return None;
+    }
+
+    let mut names = Names::new(&rules, facts, query);
+    let query_adornment = adorn(query, &BTreeSet::new());
+    let seed = names.magic(query, &query_adornment);
+    let mut out = vec![Clause(seed, vec![], vec![], None)];
+    let mut todo = vec![(query.signature(), query_adornment.clone())];
+    let mut done = todo.iter().cloned().collect::<HashSet<_>>();
+    let mut negated = Vec::new();
+    while let Some((pred, adornment)) = todo.pop() {
+        if facts.contains(&pred) {
+            // The facts about the predicate stay in the original relation, so
+            // a rule brings them into the adorned one.
+            let args = (0..pred.1)
+                .map(|i| format!("X{}", i))
+                .map(|v| Term::Var(Variable::new(&v).unwrap(), None))
+                .collect();
+            let head = Literal(pred.0.clone(), args, None);
+            out.push(Clause(
+                names.adorned(&head, &adornment),
+                vec![
+                    Goal::Literal(names.magic(&head, &adornment)),
+                    Goal::Literal(head),
+                ],
+                vec![],
+                None,
+            ));
+        }
+
+        for rule in rules.iter().filter(|r| r.pred() == pred) {
+            let head = rule.head();
+            let mut bound = head.1
+                .iter()
+                .zip(adornment.chars())
+                .filter(|&(_, a)| a == 'b')
+                .filter_map(|(arg, _)| var(arg))
+                .collect::<BTreeSet<_>>();
+
+            let mut body = vec![Goal::Literal(names.magic(head, &adornment))];
+            for goal in rule.body() {
+                let literal = match *goal {
+                    Goal::Literal(ref l) => l,
+                    Goal::Not(ref l) => {
+                        negated.push(l.signature());
+                        body.push(goal.clone());
+                        continue;
+                    }
+                    Goal::Compare(..) => {
+                        bound.extend(goal.vars());
+                        body.push(goal.clone());
+                        continue;
+                    }
+                };
+                if aggregated.contains(&literal.signature()) {
+                    negated.push(literal.signature());
+                    body.push(goal.clone());
+                } else if idb.contains(&literal.signature()) {
+                    let a = adorn(literal, &bound);
+                    let magic = names.magic(literal, &a);
+                    out.push(Clause(magic, body.clone(), vec![], rule.3));
+                    body.push(Goal::Literal(names.adorned(literal, &a)));
+                    if done.insert((literal.signature(), a.clone())) {
+                        todo.push((literal.signature(), a));
+                    }
+                } else {
+                    body.push(goal.clone());
+                }
+                bound.extend(literal.1.iter().filter_map(var));
+            }
+            let head = names.adorned(head, &adornment);
+            out.push(Clause(head, body, vec![], rule.3));
+        }
+    }
+
+    let mut kept = HashSet::new();
+    while let Some(pred) = negated.pop() {
+        if kept.insert(pred.clone()) {
+            for rule in rules.iter().filter(|r| r.pred() == pred) {
+                out.push((*rule).clone());
+                let deps = rule.body().iter().filter_map(Goal::literal);
+                negated.extend(deps.map(Literal::signature));
+            }
+        }
+    }
+
+    Some((out, names.adorned(query, &query_adornment)))
+}
+
+/// Rewrites a program for a query. The declarations and facts in the program
+/// (and retractions of facts) are kept in order, followed by the rewritten
+/// rules and the rewritten query. Rules that are retracted are left out. If
+/// there is nothing to rewrite, the program is returned with the query
+/// appended.
+pub fn rewrite_program(program: &Program, query: &Literal) -> Program {
+    let mut stmts = Vec::new();
+    let mut rules = Vec::new();
+    let mut facts = HashSet::new();
+    for stmt in &program.0 {
+        match *stmt {
+            Statement::Declaration(_) => stmts.push(stmt.clone()),
+            Statement::Assertion(ref c) if c.as_fact().is_none() => {
+                rules.push(c)
+            }
+            Statement::Assertion(ref c) => {
+                facts.insert(c.pred());
+                stmts.push(stmt.clone());
+            }
+            Statement::Retraction(ref c) if c.as_fact().is_some() => {
+                stmts.push(stmt.clone())
+            }
+            Statement::Retraction(ref c) => rules.retain(|r| !c.retracts(r)),
+            Statement::Query(_) => {}
+        }
+    }
+
+    if let Some((rules, query)) = rewrite(rules.iter().cloned(), &facts, query)
+    {
+        stmts.extend(rules.into_iter().map(Statement::Assertion));
+        stmts.push(Statement::Query(vec![Goal::Literal(query)]));
+    } else {
+        stmts.extend(rules.into_iter().cloned().map(Statement::Assertion));
+        stmts.push(Statement::Query(vec![Goal::Literal(query.clone())]));
+    }
+    Program(stmts)
+}
+
+/// Computes the adornment of a literal, given the variables which are bound.
+fn adorn(literal: &Literal, bound: &BTreeSet<Variable>) -> String {
+    literal
+        .1
+        .iter()
+        .map(|arg| match *arg {
+            Term::Value(..) => 'b',
+            Term::Var(ref v, _) if bound.contains(v) => 'b',
+            Term::Var(..) => 'f',
+        })
+        .collect()
+}
+
+/// The names of the adorned and magic predicates of a rewriting, which are
+/// chosen so as not to be used by any other predicate.
+struct Names {
+    used: HashSet<Name>,
+    made: HashMap<(bool, Name, String), Name>,
+}
+
+impl Names {
+    /// Creates the names for a rewriting, avoiding every predicate used by
+    /// the rules, the facts and the query.
+    fn new(
+        rules: &[&Clause],
+        facts: &HashSet<(Name, usize)>,
+        query: &Literal,
+    ) -> Names {
+        let mut used = facts
+            .iter()
+            .map(|pred| pred.0.clone())
+            .collect::<HashSet<_>>();
+        used.insert(query.0.clone());
+        for rule in rules {
+            used.insert(rule.head().0.clone());
+            used.extend(rule.body().iter().filter_map(Goal::literal).map(|l| {
+                l.0.clone()
+            }));
+        }
+        Names {
+            used,
+            made: HashMap::new(),
+        }
+    }
+
+    /// Returns the name for the adorned (or magic) predicate of a predicate,
+    /// making an unused one the first time it is asked for.
+    fn name(&mut self, magic: bool, pred: &Name, adornment: &str) -> Name {
+        let key = (magic, pred.clone(), adornment.to_string());
+        if let Some(name) = self.made.get(&key) {
+            return name.clone();
+        }
+
+        let base = if magic {
+            format!("magic_{}_{}", pred.as_ref(), adornment)
+        } else {
+            format!("{}_{}", pred.as_ref(), adornment)
+        };
+        let mut name = Name::new(&base).unwrap();
+        let mut i = 0;
+        while self.used.contains(&name) {
+            i += 1;
+            name = Name::new(&format!("{}_{}", base, i)).unwrap();
+        }
+        self.used.insert(name.clone());
+        self.made.insert(key, name.clone());
+        name
+    }
+
+    /// Returns the literal, with its predicate renamed to the adorned
+    /// predicate.
+    fn adorned(&mut self, literal: &Literal, adornment: &str) -> Literal {
+        let Literal(ref pred, ref args, span) = *literal;
+        let name = self.name(false, pred, adornment);
+        Literal(name, args.clone(), span)
+    }
+
+    /// Returns the magic literal for the literal: the magic predicate for its
+    /// adornment, applied to its bound arguments.
+    fn magic(&mut self, literal: &Literal, adornment: &str) -> Literal {
+        let Literal(ref pred, ref args, span) = *literal;
+        let name = self.name(true, pred, adornment);
+        let args = args.iter()
+            .zip(adornment.chars())
+            .filter(|&(_, a)| a == 'b')
+            .map(|(arg, _)| arg.clone())
+            .collect();
+        Literal(name, args, span)
+    }
+}
+
+fn var(term: &Term) -> Option<Variable> {
+    match *term {
+        Term::Var(ref v, _) => Some(v.clone()),
+        Term::Value(..) => None,
+    }
+}
+
+#[test]
+fn rewrite_path() {
+    let program: Program = concat!(
+        "edge(a, b).\n",
+        "path(X, Y) :- edge(X, Y).\n",
+        "path(X, Y) :- edge(X, Z), path(Z, Y).\n",
+    ).parse()
+        .unwrap();
+    let query = match "path(a, Y)?".parse().unwrap() {
+        Statement::Query(q) => q[0].literal().unwrap().clone(),
+        _ => unreachable!(),
+    };
+
+    let expected: Program = concat!(
+        "edge(a, b).\n",
+        "magic_path_bf(a).\n",
+        "path_bf(X, Y) :- magic_path_bf(X), edge(X, Y).\n",
+        "magic_path_bf(Z) :- magic_path_bf(X), edge(X, Z).\n",
+        "path_bf(X, Y) :- magic_path_bf(X), edge(X, Z), path_bf(Z, Y).\n",
+        "path_bf(a, Y)?\n",
+    ).parse()
+        .unwrap();
+    assert_eq!(rewrite_program(&program, &query), expected);
+}
+
+#[test]
+fn rewrite_negation() {
+    let program: Program = concat!(
+        "edge(a, b). node(a). node(b).\n",
+        "reach(X, Y) :- edge(X, Y).\n",
+        "unreach(X, Y) :- node(X), node(Y), not reach(X, Y).\n",
+    ).parse()
+        .unwrap();
+    let query = match "unreach(a, Y)?".parse().unwrap() {
+        Statement::Query(q) => q[0].literal().unwrap().clone(),
+        _ => unreachable!(),
+    };
+
+    let expected: Program = concat!(
+        "edge(a, b). node(a). node(b).\n",
+        "magic_unreach_bf(a).\n",
+        "unreach_bf(X, Y) :- magic_unreach_bf(X), node(X), node(Y), ",
+        "not reach(X, Y).\n",
+        "reach(X, Y) :- edge(X, Y).\n",
+        "unreach_bf(a, Y)?\n",
+    ).parse()
+        .unwrap();
+    assert_eq!(rewrite_program(&program, &query), expected);
+}
+
+#[test]
+fn rewrite_avoids_used_names() {
+    let program: Program = concat!(
+        "edge(a, b).\n",
+        "path_bf(a, zzz).\n",
+        "path(X, Y) :- edge(X, Y).\n",
+    ).parse()
+        .unwrap();
+    let query = match "path(a, Y)?".parse().unwrap() {
+        Statement::Query(q) => q[0].literal().unwrap().clone(),
+        _ => unreachable!(),
+    };
+
+    let expected: Program = concat!(
+        "edge(a, b).\n",
+        "path_bf(a, zzz).\n",
+        "magic_path_bf(a).\n",
+        "path_bf_1(X, Y) :- magic_path_bf(X), edge(X, Y).\n",
+        "path_bf_1(a, Y)?\n",
+    ).parse()
+        .unwrap();
+    assert_eq!(rewrite_program(&program, &query), expected);
+}
+
+#[test]
+fn rewrite_retracted_rules() {
+    let program: Program = concat!(
+        "edge(a, b).\n",
+        "path(X, Y) :- edge(X, Y).\n",
+        "path(X, Y) :- edge(X, Z), path(Z, Y).\n",
+        "path(A, B) :- edge(A, C), path(C, B)~\n",
+    ).parse()
+        .unwrap();
+    let query = match "path(a, Y)?".parse().unwrap() {
+        Statement::Query(q) => q[0].literal().unwrap().clone(),
+        _ => unreachable!(),
+    };
+
+    let expected: Program = concat!(
+        "edge(a, b).\n",
+        "magic_path_bf(a).\n",
+        "path_bf(X, Y) :- magic_path_bf(X), edge(X, Y).\n",
+        "path_bf(a, Y)?\n",
+    ).parse()
+        .unwrap();
+    assert_eq!(rewrite_program(&program, &query), expected);
+}
@@ -9,9 +9,11 @@ use std::io::ErrorKind as IoErrorKind;
 use std::process::exit;
 
 use clap::ArgMatches;
-use datalog::{DynamicInterpreter, ErrorKind, Interpeter, NaiveInterpreter,
-              Program, Result, Statement};
-use datalog::styles::{ERROR, PUNCTUATION};
+use datalog::{load_csv, save_csv, Bindings, Database, DynamicInterpreter,
+              ErrorKind, Goal, Interpeter, Name, NaiveInterpreter, Program,
+              Result, SemiNaiveInterpreter, Statement, TabledInterpreter,
+              WamInterpreter};
+use datalog::styles::{ERROR, PUNCTUATION, SUCCESS, WARNING};
 use error_chain::ChainedError;
 use liner::Context;
 use sparkly::{Doc, Sparkly};
@@ -24,17 +26,26 @@ fn main() {
         (@subcommand pretty =>
             (about: "Pretty-prints Datalog code")
             (@arg FILE: +required "Loads the given Datalog file")
+            (@arg MAGIC: --magic +takes_value "Rewrites the program with magic sets for the given query")
+        )
+        (@subcommand check =>
+            (about: "Checks Datalog code for likely mistakes, and prints the type of each predicate")
+            (@arg FILE: +required "Loads the given Datalog file")
         )
         (@subcommand run =>
             (about: "Runs Datalog code")
             (@arg FILE: "Loads the given Datalog file")
-            (@arg INTERPRETER: -i +takes_value "The interpreter to use. One of: naive")
+            (@arg INTERPRETER: -i +takes_value "The interpreter to use. One of: naive, seminaive, tabled, wam")
+            (@arg DB: --db +takes_value "Stores facts and rules in the given directory, loading them first")
+            (@arg LOAD_CSV: --("load-csv") ... +takes_value number_of_values(1) "Loads facts from a CSV file, given as PRED=FILE; files ending in .tsv are tab-separated")
+            (@arg OUTPUT_CSV: --("output-csv") ... +takes_value number_of_values(1) "Saves facts to a CSV file at exit, given as PRED=FILE")
             (@arg STMTS: ... -e +takes_value "A statement to run instead of starting a REPL")
         )
     ).get_matches();
 
     let result = match matches.subcommand() {
         ("pretty", Some(matches)) => pretty(matches),
+        ("check", Some(matches)) => check(matches),
         ("run", Some(matches)) => run(matches),
         _ => {
             eprintln!("{}", matches.usage());
@@ -51,36 +62,171 @@ fn main() {
     }
 }
 
-fn pretty(matches: &ArgMatches) -> Result<()> {
-    let file = matches.value_of("FILE").unwrap();
-    let program = if file == "-" {
-        use std::io::{stdin, Read};
+/// Reads the given file, or standard input if it is `-`.
+fn read_source(file: &str) -> Result<String> {
+    use std::fs::File;
+    use std::io::{stdin, Read};
 
-        let mut buf = String::new();
+    let mut buf = String::new();
+    if file == "-" {
         stdin().read_to_string(&mut buf)?;
-        buf.parse()?
     } else {
-        Program::parse_file(file)?
+        File::open(file)?.read_to_string(&mut buf)?;
+    }
+    Ok(buf)
+}
+
+fn pretty(matches: &ArgMatches) -> Result<()> {
+    let src = read_source(matches.value_of("FILE").unwrap())?;
+    let program: Program = src.parse()?;
+    let program = if let Some(query) = matches.value_of("MAGIC") {
+        match query.parse()? {
+            Statement::Query(ref goals) => match goals.as_slice() {
+                [Goal::Literal(ref query)] => program.magic_sets(query),
+                _ => {
+                    let msg = format!("{} should be a single literal", query);
+                    return Err(msg.into());
+                }
+            },
+            _ => return Err(format!("{} is not a query", query).into()),
+        }
+    } else {
+        program
     };
 
-    program.to_doc().writeln_to_tty().map_err(|e| e.into())
+    // Rendering each statement separately keeps the pretty-printer from
+    // considering every combination of line breaks across the whole program,
+    // which takes time exponential in its length.
+    for stmt in program.0 {
+        stmt.to_doc().writeln_to_tty()?;
+    }
+    Ok(())
+}
+
+fn check(matches: &ArgMatches) -> Result<()> {
+    let file = matches.value_of("FILE").unwrap();
+    let src = read_source(file)?;
+    let program = Program::parse_source(&src, file)?;
+    let lints = program.lint();
+    for lint in &lints {
+        let (kind, style) = if lint.is_error {
+            ("error", ERROR)
+        } else {
+            ("warning", WARNING)
+        };
+        let at = match lint.span {
+            Some(span) => span.to_string(),
+            None => file.to_string(),
+        };
+        let msg = format!("{}: {}: {}", at, kind, lint.msg);
+        Doc::text(msg, style).writeln_to_tty()?;
+    }
+
+    let (types, _) = program.infer_types();
+    let mut types = types.into_iter().collect::<Vec<_>>();
+    types.sort_by(|a, b| (a.0).0.as_ref().cmp((b.0).0.as_ref()));
+    for ((name, _), columns) in types {
+        let columns = columns.iter().map(|ty| match *ty {
+            Some(ty) => ty.to_doc(),
+            None => Doc::from("_"),
+        });
+        name.to_doc()
+            .append(
+                Doc::text(",", PUNCTUATION)
+                    .append(Doc::space())
+                    .join(columns)
+                    .bracket("(", ")"),
+            )
+            .writeln_to_tty()?;
+    }
+
+    let errors = lints.iter().filter(|lint| lint.is_error).count();
+    if errors == 0 {
+        Ok(())
+    } else {
+        let plural = if errors == 1 { "" } else { "s" };
+        Err(format!("{} has {} error{}", file, errors, plural).into())
+    }
 }
 
 fn run(matches: &ArgMatches) -> Result<()> {
     let mut interpreter: DynamicInterpreter =
         match matches.value_of("INTERPRETER") {
             Some("naive") | None => NaiveInterpreter::new().into(),
+            Some("seminaive") => SemiNaiveInterpreter::new().into(),
+            Some("tabled") => TabledInterpreter::new().into(),
+            Some("wam") => WamInterpreter::new().into(),
             Some(_) => {
                 eprintln!("{}", matches.usage());
                 exit(1);
             }
         };
 
+    if let Some(dir) = matches.value_of("DB") {
+        let mut db = Database::open(dir, interpreter)?;
+        run_with(&mut db, matches)?;
+        db.checkpoint()
+    } else {
+        run_with(&mut interpreter, matches)
+    }
+}
+
+fn run_with<I: Interpeter>(
+    interpreter: &mut I,
+    matches: &ArgMatches,
+) -> Result<()> {
     if let Some(path) = matches.value_of("FILE") {
         let program = Program::parse_file(path)?;
-        interpreter.load_program(program)?;
+        interpreter.load_program_with(program, &mut |query, answers| {
+            Doc::text("?-", PUNCTUATION)
+                .append(Doc::space())
+                .append(Statement::Query(query.clone()).to_doc())
+                .writeln_to_tty()?;
+            if is_ground(&query) {
+                print_truth(!answers.is_empty())
+            } else {
+                for binding in &answers {
+                    print_bindings(binding)?;
+                }
+                print_truth(false)
+            }
+        })?;
+    }
+    for spec in matches.values_of("LOAD_CSV").into_iter().flat_map(|v| v) {
+        let (name, path, delimiter) = parse_csv_spec(spec)?;
+        load_csv(interpreter, &name, path, delimiter)?;
+    }
+
+    run_stmts(interpreter, matches)?;
+    for spec in matches.values_of("OUTPUT_CSV").into_iter().flat_map(|v| v) {
+        let (name, path, delimiter) = parse_csv_spec(spec)?;
+        let arity = interpreter.arity(&name).ok_or_else(|| {
+            format!(
+                "{} has no declaration, or clauses of a single arity, to give \
+                 the number of columns",
+                name
+            )
+        })?;
+        save_csv(interpreter, &(name, arity), path, delimiter)?;
     }
+    Ok(())
+}
+
+/// Parses a `PRED=FILE` argument, choosing the delimiter from the file's
+/// extension.
+fn parse_csv_spec(spec: &str) -> Result<(Name, &str, char)> {
+    let err = || format!("{} should have the form PRED=FILE", spec);
+    let eq = spec.find('=').ok_or_else(err)?;
+    let (name, path) = (&spec[..eq], &spec[eq + 1..]);
+    let name = Name::new(name).ok_or_else(err)?;
+    let delimiter = if path.ends_with(".tsv") { '\t' } else { ',' };
+    Ok((name, path, delimiter))
+}
 
+fn run_stmts<I: Interpeter>(
+    interpreter: &mut I,
+    matches: &ArgMatches,
+) -> Result<()> {
     if let Some(stmts) = matches.values_of("STMTS") {
         for stmt in stmts {
             let stmt: Statement = stmt.parse()?;
@@ -88,20 +234,24 @@ fn run(matches: &ArgMatches) -> Result<()> {
                 .append(Doc::space())
                 .append(stmt.to_doc())
                 .writeln_to_tty()?;
-            run_stmt(&mut interpreter, stmt)?;
+            run_stmt(interpreter, stmt)?;
         }
         Ok(())
     } else {
         let mut ctx = Context::new();
         let r = loop {
-            if let Err(err) = run_repl(&mut interpreter, &mut ctx) {
+            if let Err(err) = run_repl(interpreter, &mut ctx) {
                 match err.kind() {
                     &ErrorKind::Io(ref err) => match err.kind() {
                         IoErrorKind::UnexpectedEof => break Ok(()),
                         IoErrorKind::Interrupted => continue,
                         _ => {}
                     },
-                    _ => {}
+                    // A mistake in a statement shouldn't end the session.
+                    _ => {
+                        Doc::text(err.to_string(), ERROR).writeln_to_tty()?;
+                        continue;
+                    }
                 }
                 break Err(err);
             }
@@ -111,35 +261,76 @@ fn run(matches: &ArgMatches) -> Result<()> {
     }
 }
 
-fn run_repl(
-    interpreter: &mut DynamicInterpreter,
+fn run_repl<I: Interpeter>(
+    interpreter: &mut I,
     ctx: &mut Context,
 ) -> Result<()> {
     let line = ctx.read_line("?- ", &mut |_| {})?;
+    ctx.history.push(line.clone().into())?;
 
     let stmt = line.parse()?;
-    run_stmt(interpreter, stmt)?;
-
-    ctx.history.push(line.into()).map_err(|err| err.into())
+    run_stmt(interpreter, stmt)
 }
 
-fn run_stmt(
-    interpreter: &mut DynamicInterpreter,
+fn run_stmt<I: Interpeter>(
+    interpreter: &mut I,
     stmt: Statement,
 ) -> Result<()> {
-    for binding in interpreter.run_stmt(stmt)? {
-        Doc::text(",", PUNCTUATION)
-            .append(Doc::space())
-            .join(binding.iter().map(|(v, n)| {
-                v.to_doc()
-                    .append(Doc::nbsp())
-                    .append(Doc::text("=", PUNCTUATION))
-                    .append(Doc::nbsp())
-                    .append(n.to_doc())
-            }))
-            .append(Doc::text(";", PUNCTUATION))
-            .writeln_to_tty()?;
+    let query = match stmt {
+        Statement::Declaration(decl) => {
+            interpreter.run_declaration(decl)?;
+            return print_truth(true);
+        }
+        Statement::Assertion(clause) => {
+            interpreter.run_assertion(clause)?;
+            return print_truth(true);
+        }
+        Statement::Retraction(clause) => {
+            interpreter.run_retraction(clause)?;
+            return print_truth(true);
+        }
+        Statement::Query(query) => query,
+    };
+
+    // A ground query has no bindings to print, just whether it holds.
+    if is_ground(&query) {
+        return print_truth(interpreter.holds(query)?);
     }
-    Doc::text("false.", ERROR).writeln_to_tty()?;
+
+    for binding in interpreter.run_query_distinct(query) {
+        print_bindings(&binding?)?;
+    }
+    print_truth(false)
+}
+
+/// Returns whether a query has no variables to print bindings for.
+fn is_ground(query: &[Goal]) -> bool {
+    query
+        .iter()
+        .flat_map(Goal::vars)
+        .all(|var| var.is_anonymous())
+}
+
+fn print_truth(holds: bool) -> Result<()> {
+    if holds {
+        Doc::text("true.", SUCCESS).writeln_to_tty()?;
+    } else {
+        Doc::text("false.", ERROR).writeln_to_tty()?;
+    }
+    Ok(())
+}
+
+fn print_bindings(binding: &Bindings) -> Result<()> {
+    Doc::text(",", PUNCTUATION)
+        .append(Doc::space())
+        .join(binding.iter().map(|(v, n)| {
+            v.to_doc()
+                .append(Doc::nbsp())
+                .append(Doc::text("=", PUNCTUATION))
+                .append(Doc::nbsp())
+                .append(n.to_doc())
+        }))
+        .append(Doc::text(";", PUNCTUATION))
+        .writeln_to_tty()?;
     Ok(())
 }
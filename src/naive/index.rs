@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use Value;
+
+/// Hash indexes on the arguments of a relation.
+///
+/// An index is built the first time the relation is queried with a given set
+/// of bound columns, and is kept up to date as facts are added; removing a
+/// fact shifts the positions of the rest, so the indexes are dropped instead.
+#[derive(Debug, Default)]
+pub struct Indexes {
+    indexes: HashMap<Vec<usize>, HashMap<Vec<Value>, Vec<usize>>>,
+}
+
+impl Indexes {
+    /// Returns the positions of the facts whose values in the given columns
+    /// are the given key, building an index on those columns if needed.
+    pub fn lookup(
+        &mut self,
+        facts: &[Vec<Value>],
+        cols: &[usize],
+        key: &[Value],
+    ) -> Vec<usize> {
+        let index = self.indexes.entry(cols.to_vec()).or_insert_with(|| {
+            let mut index = HashMap::new();
+            for (i, fact) in facts.iter().enumerate() {
+                index
+                    .entry(project(fact, cols))
+                    .or_insert_with(Vec::new)
+                    .push(i);
+            }
+            index
+        });
+        index.get(key).cloned().unwrap_or_default()
+    }
+
+    /// Adds the fact at the given position to every index.
+    pub fn insert(&mut self, fact: &[Value], pos: usize) {
+        for (cols, index) in &mut self.indexes {
+            index
+                .entry(project(fact, cols))
+                .or_insert_with(Vec::new)
+                .push(pos);
+        }
+    }
+
+    /// Returns the number of indexes that have been built.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.indexes.len()
+    }
+}
+
+/// Returns the values of a fact in the given columns.
+fn project(fact: &[Value], cols: &[usize]) -> Vec<Value> {
+    cols.iter().map(|&i| fact[i].clone()).collect()
+}
+
+#[test]
+fn lookup() {
+    let fact = |x: &str, y: &str| {
+        vec![Value::new(x).unwrap(), Value::new(y).unwrap()]
+    };
+    let mut facts = vec![fact("a", "b"), fact("a", "c"), fact("b", "c")];
+    let mut indexes = Indexes::default();
+    let a = Value::new("a").unwrap();
+    let c = Value::new("c").unwrap();
+
+    assert_eq!(indexes.lookup(&facts, &[0], &[a.clone()]), vec![0, 1]);
+    assert_eq!(indexes.lookup(&facts, &[1], &[c.clone()]), vec![1, 2]);
+    let key = [c.clone(), a.clone()];
+    assert_eq!(indexes.lookup(&facts, &[0, 1], &key), Vec::<usize>::new());
+    assert_eq!(indexes.len(), 3);
+
+    facts.push(fact("c", "c"));
+    indexes.insert(&facts[3], 3);
+    assert_eq!(indexes.lookup(&facts, &[1], &[c]), vec![1, 2, 3]);
+    assert_eq!(indexes.lookup(&facts, &[0], &[a]), vec![0, 1]);
+}
@@ -1,15 +1,28 @@
+mod index;
+
+use std::cell::RefCell;
 use std::collections::{BTreeMap, HashMap};
-use std::iter::empty;
+use std::iter::{empty, once};
+
+use {Clause, Declaration, Goal, Interpeter, Literal, Name, Result, Term, Value,
+     Variable};
+use schema::Schema;
+use util::{arity, check_assertion, check_declaration, retract};
 
-use {Clause, Interpeter, Literal, Name, Result, Variable};
+use self::index::Indexes;
 
 /// A naive interpreter.
+///
+/// Facts are looked up through hash indexes on their arguments, which are
+/// built lazily for each combination of bound arguments that is queried.
 // An interpreter based on the one in [the first edition of Modern Compiler
 // Design](https://dickgrune.com/Books/MCD_1st_Edition/), page 601.
 #[derive(Debug)]
 pub struct NaiveInterpreter {
-    facts: HashMap<(Name, usize), Vec<Vec<Name>>>,
+    facts: HashMap<(Name, usize), Vec<Vec<Value>>>,
     rules: HashMap<(Name, usize), Vec<Clause>>,
+    indexes: RefCell<HashMap<(Name, usize), Indexes>>,
+    schema: Schema,
 }
 
 impl NaiveInterpreter {
@@ -18,6 +31,8 @@ impl NaiveInterpreter {
         NaiveInterpreter {
             facts: HashMap::new(),
             rules: HashMap::new(),
+            indexes: RefCell::new(HashMap::new()),
+            schema: Schema::default(),
         }
     }
 
@@ -25,31 +40,55 @@ impl NaiveInterpreter {
     pub fn solve<'a>(
         &'a self,
         goal: Literal,
-    ) -> Box<'a + Iterator<Item = BTreeMap<Variable, Name>>> {
+    ) -> Box<'a + Iterator<Item = Result<BTreeMap<Variable, Value>>>> {
         Box::new(self.solve_facts(goal.clone()).chain(self.solve_rules(goal)))
     }
 
-    /// Attempts to solve a goal, only using facts.
+    /// Attempts to solve a goal, only using facts. If any of the goal's
+    /// arguments are bound, only the facts found through an index on those
+    /// arguments are considered.
     pub fn solve_facts<'a>(
         &'a self,
         goal: Literal,
-    ) -> Box<'a + Iterator<Item = BTreeMap<Variable, Name>>> {
-        if let Some(facts) = self.facts.get(&goal.signature()) {
-            Box::new(
+    ) -> Box<'a + Iterator<Item = Result<BTreeMap<Variable, Value>>>> {
+        let facts = match self.facts.get(&goal.signature()) {
+            Some(facts) => facts,
+            None => return Box::new(empty()),
+        };
+        let (mut cols, mut key) = (Vec::new(), Vec::new());
+        for (i, arg) in goal.1.iter().enumerate() {
+            if let Term::Value(ref value, _) = *arg {
+                cols.push(i);
+                key.push(value.clone());
+            }
+        }
+        if cols.is_empty() {
+            return Box::new(
                 facts
                     .iter()
-                    .filter_map(move |fact| goal.try_instantiate_fact(fact)),
-            )
-        } else {
-            Box::new(empty())
+                    .filter_map(move |fact| goal.try_instantiate_fact(fact))
+                    .map(Ok),
+            );
         }
+
+        let matches = self.indexes
+            .borrow_mut()
+            .entry(goal.signature())
+            .or_insert_with(Indexes::default)
+            .lookup(facts, &cols, &key);
+        Box::new(
+            matches
+                .into_iter()
+                .filter_map(move |i| goal.try_instantiate_fact(&facts[i]))
+                .map(Ok),
+        )
     }
 
     /// Attempts to solve a goal, without using facts about the goal.
     pub fn solve_rules<'a>(
         &'a self,
         goal: Literal,
-    ) -> Box<'a + Iterator<Item = BTreeMap<Variable, Name>>> {
+    ) -> Box<'a + Iterator<Item = Result<BTreeMap<Variable, Value>>>> {
         if let Some(rules) = self.rules.get(&goal.signature()) {
             Box::new(
                 rules
@@ -66,31 +105,125 @@ impl NaiveInterpreter {
         &'a self,
         goal: &Literal,
         rule: &'a Clause,
-    ) -> Box<'a + Iterator<Item = BTreeMap<Variable, Name>>> {
-        use styles::ERROR;
-        use sparkly::Doc;
+    ) -> Box<'a + Iterator<Item = Result<BTreeMap<Variable, Value>>>> {
+        if rule.is_aggregate() {
+            return self.solve_aggregate(goal, rule);
+        }
+
+        let (bindings, body) = match rule.try_instantiate(goal.clone()) {
+            Some(instance) => instance,
+            None => return Box::new(empty()),
+        };
+
+        // The body is solved in terms of the rule's variables; the results
+        // are projected back onto the goal's variables by matching the goal
+        // against the instantiated head.
+        let goal = goal.clone();
+        Box::new(self.solve_body(body, bindings).filter_map(move |bindings| {
+            let bindings = match bindings {
+                Ok(bindings) => bindings,
+                Err(err) => return Some(Err(err)),
+            };
+            let head = try_opt!(rule.head().substitute(&bindings).as_ground());
+            goal.try_instantiate_fact(&head).map(Ok)
+        }))
+    }
+
+    /// Attempts to solve a goal, using a rule with aggregates in its head.
+    /// Every solution to the body is needed before the aggregates can be
+    /// computed, so they are all collected first.
+    fn solve_aggregate<'a>(
+        &'a self,
+        goal: &Literal,
+        rule: &'a Clause,
+    ) -> Box<'a + Iterator<Item = Result<BTreeMap<Variable, Value>>>> {
+        let instance = rule.try_instantiate(rule.group_goal(goal));
+        let (bindings, body) = match instance {
+            Some(instance) => instance,
+            None => return Box::new(empty()),
+        };
+        let tuples = self.solve_body(body, bindings)
+            .collect::<Result<Vec<_>>>()
+            .and_then(|solutions| rule.aggregate(solutions));
+        match tuples {
+            Ok(tuples) => {
+                let goal = goal.clone();
+                Box::new(tuples.into_iter().filter_map(move |tuple| {
+                    goal.try_instantiate_fact(&tuple).map(Ok)
+                }))
+            }
+            Err(err) => Box::new(once(Err(err))),
+        }
+    }
 
-        let opt = rule.try_instantiate(goal.clone());
-        if opt.is_none() {
-            return Box::new(empty());
+    /// Attempts to solve each goal of a rule body in turn, from left to
+    /// right, extending the given bindings with the bindings from each. A
+    /// negated goal succeeds, without binding anything, if the literal inside
+    /// it has no solutions; a comparison is evaluated directly.
+    pub fn solve_body<'a>(
+        &'a self,
+        mut body: Vec<Goal>,
+        bindings: BTreeMap<Variable, Value>,
+    ) -> Box<'a + Iterator<Item = Result<BTreeMap<Variable, Value>>>> {
+        if body.is_empty() {
+            return Box::new(once(Ok(bindings)));
         }
-        let body = opt.unwrap();
 
-        eprintln!(
-            "{}",
-            Doc::text("TODO:", ERROR).append(Doc::lines(body)).display()
-        );
-        Box::new(empty())
+        match body.remove(0).substitute(&bindings) {
+            Goal::Literal(goal) => {
+                Box::new(self.solve(goal).flat_map(move |new| match new {
+                    Ok(new) => {
+                        let mut bindings = bindings.clone();
+                        bindings.extend(new);
+                        self.solve_body(body.clone(), bindings)
+                    }
+                    Err(err) => Box::new(once(Err(err))),
+                }))
+            }
+            Goal::Not(goal) => match self.solve(goal).next() {
+                None => self.solve_body(body, bindings),
+                Some(Ok(_)) => Box::new(empty()),
+                Some(Err(err)) => Box::new(once(Err(err))),
+            },
+            Goal::Compare(l, op, r) => match op.eval(&l, &r) {
+                Ok(Some(new)) => {
+                    let mut bindings = bindings;
+                    bindings.extend(new);
+                    self.solve_body(body, bindings)
+                }
+                Ok(None) => Box::new(empty()),
+                Err(err) => Box::new(once(Err(err))),
+            },
+        }
     }
 }
 
 impl Interpeter for NaiveInterpreter {
+    fn run_declaration(&mut self, decl: Declaration) -> Result<()> {
+        let facts = self.facts
+            .iter()
+            .flat_map(|(pred, facts)| facts.iter().map(move |f| (&pred.0, f)));
+        let rules = self.rules.values().flat_map(|r| r.iter());
+        check_declaration(&mut self.schema, facts, rules, decl)
+    }
+
     fn run_assertion(&mut self, clause: Clause) -> Result<()> {
+        let rules = self.rules.values().flat_map(|r| r.iter());
+        let clause = check_assertion(&self.schema, rules, clause)?;
         if let Some(fact) = clause.as_fact() {
-            self.facts
+            // A fact that is already present is found through an index on
+            // every column, so that loading many facts stays linear.
+            let facts =
+                self.facts.entry(clause.pred()).or_insert_with(Vec::new);
+            let indexes = self.indexes
+                .get_mut()
                 .entry(clause.pred())
-                .or_insert_with(Vec::new)
-                .push(fact)
+                .or_insert_with(Indexes::default);
+            let cols = (0..fact.len()).collect::<Vec<_>>();
+            if indexes.lookup(facts, &cols, &fact).is_empty() {
+                indexes.insert(&fact, facts.len());
+                facts.push(fact)
+            }
         } else {
             self.rules
                 .entry(clause.pred())
@@ -101,13 +234,224 @@ impl Interpeter for NaiveInterpreter {
     }
 
     fn run_retraction(&mut self, clause: Clause) -> Result<()> {
-        unimplemented!()
+        // Removing facts moves the others, so their indexes are rebuilt.
+        self.indexes.borrow_mut().remove(&clause.pred());
+        retract(&mut self.facts, &mut self.rules, clause)?;
+        Ok(())
     }
 
     fn run_query<'a>(
         &'a self,
         query: Literal,
-    ) -> Box<'a + Iterator<Item = BTreeMap<Variable, Name>>> {
+    ) -> Box<'a + Iterator<Item = Result<BTreeMap<Variable, Value>>>> {
         self.solve(query)
     }
+
+    fn arity(&self, name: &Name) -> Option<usize> {
+        let preds = self.facts.keys().chain(self.rules.keys());
+        arity(&self.schema, preds, name)
+    }
+}
+
+#[test]
+fn rules() {
+    use Program;
+    use util::{goals, query, set};
+
+    let program: Program = concat!(
+        "red(apple). red(pepper). fruit(apple). vegetable(pepper).\n",
+        "spicy(X) :- red(X), vegetable(X).\n",
+        "same(X, X) :- red(X).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = NaiveInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    assert_eq!(query(&interpreter, "spicy(X)?"), set(&["pepper"]));
+    assert_eq!(query(&interpreter, "spicy(pepper)?"), set(&[""]));
+    assert_eq!(query(&interpreter, "spicy(apple)?"), set(&[]));
+    assert_eq!(query(&interpreter, "same(pepper, X)?"), set(&["pepper"]));
+    assert_eq!(query(&interpreter, "same(apple, pepper)?"), set(&[]));
+
+    assert!(interpreter.holds(goals("spicy(pepper)?")).unwrap());
+    assert!(!interpreter.holds(goals("spicy(apple)?")).unwrap());
+    assert!(interpreter.holds(goals("spicy(X)?")).unwrap());
+}
+
+#[test]
+fn retractions() {
+    use {ErrorKind, Program};
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "edge(a, b). edge(a, c). edge(b, c).\n",
+        "path(X, Y) :- edge(X, Y).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = NaiveInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    let stmt = |interpreter: &mut NaiveInterpreter, s: &str| {
+        interpreter.run_stmt(s.parse().unwrap()).map(|i| i.count())
+    };
+
+    stmt(&mut interpreter, "edge(b, c)~").unwrap();
+    assert_eq!(query(&interpreter, "path(X, Y)?"), set(&["ab", "ac"]));
+    match *stmt(&mut interpreter, "edge(b, c)~").unwrap_err().kind() {
+        ErrorKind::NoSuchClause(_) => {}
+        ref err => panic!("unexpected error {}", err),
+    }
+
+    stmt(&mut interpreter, "edge(a, X)~").unwrap();
+    assert_eq!(query(&interpreter, "edge(X, Y)?"), set(&[]));
+
+    stmt(&mut interpreter, "edge(a, b).").unwrap();
+    stmt(&mut interpreter, "path(A, B) :- edge(A, B)~").unwrap();
+    assert_eq!(query(&interpreter, "path(X, Y)?"), set(&[]));
+}
+
+#[test]
+fn negation() {
+    use {ErrorKind, Program};
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "red(apple). red(pepper). fruit(apple). vegetable(pepper).\n",
+        "spicy(X) :- red(X), vegetable(X).\n",
+        "bland(X) :- red(X), not spicy(X).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = NaiveInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    assert_eq!(query(&interpreter, "bland(X)?"), set(&["apple"]));
+
+    let rule = "spicy(X) :- red(X), \\+ bland(X).".parse().unwrap();
+    let err = interpreter.run_stmt(rule).map(|i| i.count()).unwrap_err();
+    match *err.kind() {
+        ErrorKind::Unstratifiable(..) => {}
+        ref err => panic!("unexpected error {}", err),
+    }
+    assert_eq!(query(&interpreter, "bland(X)?"), set(&["apple"]));
+}
+
+#[test]
+fn typed_values() {
+    use Program;
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "p(42). p(\"42\"). p(4.2). p(true). p(foo).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = NaiveInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    assert_eq!(query(&interpreter, "p(\"42\")?"), set(&[""]));
+    assert_eq!(query(&interpreter, "p(\"foo\")?"), set(&[]));
+    assert_eq!(query(&interpreter, "p(4.20)?"), set(&[""]));
+    assert_eq!(
+        query(&interpreter, "p(X)?"),
+        set(&["42", "\"42\"", "4.2", "true", "foo"])
+    );
+}
+
+#[test]
+fn aggregates() {
+    use {ErrorKind, Program};
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "edge(a, b). edge(a, c). edge(b, c). edge(c, a).\n",
+        "weight(a, 1). weight(b, 2.5). weight(c, 4).\n",
+        "degree(X, count<Y>) :- edge(X, Y).\n",
+        "reach(X, sum<W>, max<W>) :- edge(X, Y), weight(Y, W).\n",
+        "total(sum<W>) :- weight(X, W).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = NaiveInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    assert_eq!(query(&interpreter, "degree(a, N)?"), set(&["2"]));
+    assert_eq!(query(&interpreter, "degree(b, 1)?"), set(&[""]));
+    assert_eq!(query(&interpreter, "degree(b, 2)?"), set(&[]));
+    assert_eq!(query(&interpreter, "reach(a, 6.5, M)?"), set(&["4"]));
+    assert_eq!(query(&interpreter, "total(T)?"), set(&["7.5"]));
+
+    let rule = "edge(X, Y) :- degree(X, Y).".parse().unwrap();
+    let err = interpreter.run_stmt(rule).map(|i| i.count()).unwrap_err();
+    match *err.kind() {
+        ErrorKind::Unstratifiable(..) => {}
+        ref err => panic!("unexpected error {}", err),
+    }
+}
+
+#[test]
+fn indexes() {
+    use Program;
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "edge(a, b). edge(a, c). edge(b, c).\n",
+        "path(X, Y) :- edge(X, Y).\n",
+        "path(X, Y) :- edge(X, Z), path(Z, Y).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = NaiveInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    let stmt = |interpreter: &mut NaiveInterpreter, s: &str| {
+        interpreter.run_stmt(s.parse().unwrap()).unwrap().count()
+    };
+    let edge = (Name::new("edge").unwrap(), 2);
+    let built = |interpreter: &NaiveInterpreter| {
+        interpreter.indexes.borrow().get(&edge).map_or(0, |i| i.len())
+    };
+
+    // Asserting the facts builds an index on every column, to find
+    // duplicates; a query with no bound arguments doesn't need any other.
+    assert_eq!(query(&interpreter, "edge(X, Y)?").len(), 3);
+    assert_eq!(built(&interpreter), 1);
+    assert_eq!(query(&interpreter, "edge(a, X)?"), set(&["b", "c"]));
+    assert_eq!(query(&interpreter, "edge(X, c)?"), set(&["a", "b"]));
+    assert_eq!(query(&interpreter, "edge(b, c)?"), set(&[""]));
+    assert_eq!(built(&interpreter), 3);
+
+    stmt(&mut interpreter, "edge(d, a).");
+    assert_eq!(query(&interpreter, "edge(X, a)?"), set(&["d"]));
+    assert_eq!(query(&interpreter, "path(d, b)?"), set(&[""]));
+    assert_eq!(built(&interpreter), 3);
+
+    stmt(&mut interpreter, "edge(a, b)~");
+    assert_eq!(built(&interpreter), 0);
+    assert_eq!(query(&interpreter, "edge(a, X)?"), set(&["c"]));
+    assert_eq!(query(&interpreter, "path(d, b)?"), set(&[]));
+}
+
+#[test]
+fn set_semantics() {
+    use Program;
+    use util::{goals, query, set};
+
+    let program: Program = concat!(
+        "edge(a, b). edge(a, b). edge(b, c). edge(a, b).\n",
+        "link(X, Y) :- edge(X, Y).\n",
+        "link(a, b).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = NaiveInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    // Duplicate facts are stored once, but an answer can still be found
+    // more than once, here by the rule and by the fact.
+    assert_eq!(interpreter.run_goals(goals("edge(X, Y)?")).count(), 2);
+    assert_eq!(interpreter.run_goals(goals("link(a, b)?")).count(), 2);
+    let distinct = interpreter.run_query_distinct(goals("link(X, Y)?"));
+    assert_eq!(distinct.count(), 2);
+
+    interpreter
+        .run_stmt("edge(a, b)~".parse().unwrap())
+        .unwrap()
+        .count();
+    assert_eq!(query(&interpreter, "edge(a, X)?"), set(&[]));
 }
@@ -0,0 +1,52 @@
+use ast::{Expr, Goal, Statement, Term, Variable};
+
+/// Renames each occurrence of the anonymous variable `_` in a statement to a
+/// fresh variable, so that no two of them are joined.
+pub fn rename_anonymous(stmt: &mut Statement) {
+    let mut terms = Vec::new();
+    match *stmt {
+        Statement::Declaration(_) => {}
+        Statement::Assertion(ref mut clause)
+        | Statement::Retraction(ref mut clause) => {
+            terms.extend(&mut (clause.0).1);
+            for goal in &mut clause.1 {
+                goal_terms(goal, &mut terms);
+            }
+        }
+        Statement::Query(ref mut goals) => for goal in goals {
+            goal_terms(goal, &mut terms);
+        },
+    }
+
+    let mut fresh = (0..).map(Variable::anonymous);
+    for term in terms {
+        if let Term::Var(ref mut var, _) = *term {
+            if var.as_ref() == "_" {
+                *var = fresh.next().unwrap();
+            }
+        }
+    }
+}
+
+fn goal_terms<'a>(goal: &'a mut Goal, terms: &mut Vec<&'a mut Term>) {
+    match *goal {
+        Goal::Literal(ref mut l) | Goal::Not(ref mut l) => {
+            terms.extend(&mut l.1)
+        }
+        Goal::Compare(ref mut l, _, ref mut r) => {
+            expr_terms(l, terms);
+            expr_terms(r, terms);
+        }
+    }
+}
+
+fn expr_terms<'a>(expr: &'a mut Expr, terms: &mut Vec<&'a mut Term>) {
+    match *expr {
+        Expr::Term(ref mut term) => terms.push(term),
+        Expr::Op(ref mut l, _, ref mut r) => {
+            expr_terms(l, terms);
+            expr_terms(r, terms);
+        }
+        Expr::Neg(ref mut e) => expr_terms(e, terms),
+    }
+}
@@ -1,6 +1,7 @@
 use pest::Parser;
 
-use ast::{Clause, Literal, Name, Program, Statement, Term, Variable};
+use ast::{Aggregate, ArithOp, Clause, CmpOp, Declaration, Expr, Goal,
+          Literal, Name, Program, Statement, Term, Type, Value, Variable};
 use parser::{convert, DatalogParser, Rule};
 
 macro_rules! ast_parse_test {
@@ -26,73 +27,179 @@ ast_parse_test! {
             "apple)~\nspicy(apple)?") =>
         Program(vec![
             Statement::Assertion(Clause(
-                Literal(Name::new("red").unwrap(), vec![Term::Name(Name::new("apple").unwrap())]), vec![
-                ])),
+                Literal(Name::new("red").unwrap(), vec![Term::Value(Value::new("apple").unwrap(), None)], None), vec![
+                ], vec![], None)),
             Statement::Assertion(Clause(
-                Literal(Name::new("fruit").unwrap(), vec![Term::Name(Name::new("apple").unwrap())]), vec![
-                ])),
+                Literal(Name::new("fruit").unwrap(), vec![Term::Value(Value::new("apple").unwrap(), None)], None), vec![
+                ], vec![], None)),
             Statement::Assertion(Clause(
-                Literal(Name::new("spicy").unwrap(), vec![Term::Var(Variable::new("X").unwrap())]), vec![
-                    Literal(Name::new("red").unwrap(), vec![Term::Var(Variable::new("X").unwrap())]),
-                    Literal(Name::new("vegetable").unwrap(), vec![Term::Var(Variable::new("X").unwrap())]),
-                ])),
-            Statement::Query(
-                Literal(Name::new("spicy").unwrap(), vec![Term::Var(Variable::new("X").unwrap())])),
+                Literal(Name::new("spicy").unwrap(), vec![Term::Var(Variable::new("X").unwrap(), None)], None), vec![
+                    Goal::Literal(Literal(Name::new("red").unwrap(), vec![Term::Var(Variable::new("X").unwrap(), None)], None)),
+                    Goal::Literal(Literal(Name::new("vegetable").unwrap(), vec![Term::Var(Variable::new("X").unwrap(), None)], None)),
+                ], vec![], None)),
+            Statement::Query(vec![
+                Goal::Literal(Literal(Name::new("spicy").unwrap(), vec![Term::Var(Variable::new("X").unwrap(), None)], None))]),
             Statement::Assertion(Clause(
-                Literal(Name::new("vegetable").unwrap(), vec![Term::Name(Name::new("apple").unwrap())]), vec![
-                ])),
-            Statement::Query(
-                Literal(Name::new("spicy").unwrap(), vec![Term::Name(Name::new("apple").unwrap())])),
+                Literal(Name::new("vegetable").unwrap(), vec![Term::Value(Value::new("apple").unwrap(), None)], None), vec![
+                ], vec![], None)),
+            Statement::Query(vec![
+                Goal::Literal(Literal(Name::new("spicy").unwrap(), vec![Term::Value(Value::new("apple").unwrap(), None)], None))]),
             Statement::Retraction(Clause(
-                Literal(Name::new("vegetable").unwrap(), vec![Term::Name(Name::new("apple").unwrap())]), vec![
-                ])),
-            Statement::Query(
-                Literal(Name::new("spicy").unwrap(), vec![Term::Name(Name::new("apple").unwrap())])),
+                Literal(Name::new("vegetable").unwrap(), vec![Term::Value(Value::new("apple").unwrap(), None)], None), vec![
+                ], vec![], None)),
+            Statement::Query(vec![
+                Goal::Literal(Literal(Name::new("spicy").unwrap(), vec![Term::Value(Value::new("apple").unwrap(), None)], None))]),
         ]);
 
     [convert_statement, stmt] as statement:
         "red(apple)." => Statement::Assertion(Clause(
-            Literal(Name::new("red").unwrap(), vec![Term::Name(Name::new("apple").unwrap())]), vec![
-            ])),
+            Literal(Name::new("red").unwrap(), vec![Term::Value(Value::new("apple").unwrap(), None)], None), vec![
+            ], vec![], None)),
         "fruit(apple)." => Statement::Assertion(Clause(
-            Literal(Name::new("fruit").unwrap(), vec![Term::Name(Name::new("apple").unwrap())]), vec![
-            ])),
+            Literal(Name::new("fruit").unwrap(), vec![Term::Value(Value::new("apple").unwrap(), None)], None), vec![
+            ], vec![], None)),
         "spicy(X) :-\n\tred(X),\n\tvegetable(X)." => Statement::Assertion(Clause(
-            Literal(Name::new("spicy").unwrap(), vec![Term::Var(Variable::new("X").unwrap())]), vec![
-                Literal(Name::new("red").unwrap(), vec![Term::Var(Variable::new("X").unwrap())]),
-                Literal(Name::new("vegetable").unwrap(), vec![Term::Var(Variable::new("X").unwrap())]),
-            ])),
-        "spicy(X)?" => Statement::Query(
-            Literal(Name::new("spicy").unwrap(), vec![Term::Var(Variable::new("X").unwrap())])),
+            Literal(Name::new("spicy").unwrap(), vec![Term::Var(Variable::new("X").unwrap(), None)], None), vec![
+                Goal::Literal(Literal(Name::new("red").unwrap(), vec![Term::Var(Variable::new("X").unwrap(), None)], None)),
+                Goal::Literal(Literal(Name::new("vegetable").unwrap(), vec![Term::Var(Variable::new("X").unwrap(), None)], None)),
+            ], vec![], None)),
+        "spicy(X)?" => Statement::Query(vec![
+            Goal::Literal(Literal(Name::new("spicy").unwrap(), vec![Term::Var(Variable::new("X").unwrap(), None)], None))]),
         "vegetable(apple)." => Statement::Assertion(Clause(
-            Literal(Name::new("vegetable").unwrap(), vec![Term::Name(Name::new("apple").unwrap())]), vec![
-            ])),
-        "spicy(apple)?" => Statement::Query(
-            Literal(Name::new("spicy").unwrap(), vec![Term::Name(Name::new("apple").unwrap())])),
+            Literal(Name::new("vegetable").unwrap(), vec![Term::Value(Value::new("apple").unwrap(), None)], None), vec![
+            ], vec![], None)),
+        "spicy(apple)?" => Statement::Query(vec![
+            Goal::Literal(Literal(Name::new("spicy").unwrap(), vec![Term::Value(Value::new("apple").unwrap(), None)], None))]),
+        "red(X), not fruit(X)?" => Statement::Query(vec![
+            Goal::Literal(Literal(Name::new("red").unwrap(), vec![Term::Var(Variable::new("X").unwrap(), None)], None)),
+            Goal::Not(Literal(Name::new("fruit").unwrap(), vec![Term::Var(Variable::new("X").unwrap(), None)], None)),
+        ]),
         "vegetable(apple)~" => Statement::Retraction(Clause(
-            Literal(Name::new("vegetable").unwrap(), vec![Term::Name(Name::new("apple").unwrap())]), vec![
-            ]));
+            Literal(Name::new("vegetable").unwrap(), vec![Term::Value(Value::new("apple").unwrap(), None)], None), vec![
+            ], vec![], None)),
+        ".decl edge(src: symbol, weight: float)" => Statement::Declaration(Declaration(
+            Name::new("edge").unwrap(), vec![
+                (Name::new("src").unwrap(), Type::Symbol),
+                (Name::new("weight").unwrap(), Type::Float),
+            ], None)),
+        ".decl \"nullary pred\"()" => Statement::Declaration(Declaration(
+            Name::new("nullary pred").unwrap(), vec![], None));
 
     [convert_clause, clause] as clause:
         "red(apple)" => Clause(Literal(Name::new("red").unwrap(), vec![
-            Term::Name(Name::new("apple").unwrap()),
-        ]), vec![]),
+            Term::Value(Value::new("apple").unwrap(), None),
+        ], None), vec![], vec![], None),
         "fruit(apple)" => Clause(Literal(Name::new("fruit").unwrap(), vec![
-            Term::Name(Name::new("apple").unwrap()),
-        ]), vec![]),
+            Term::Value(Value::new("apple").unwrap(), None),
+        ], None), vec![], vec![], None),
         "spicy(X) :-\n\tred(X),\n\tvegetable(X)" =>
-            Clause(Literal(Name::new("spicy").unwrap(), vec![ Term::Var(Variable::new("X").unwrap()) ]), vec![
-                Literal(Name::new("red").unwrap(), vec![ Term::Var(Variable::new("X").unwrap()) ]),
-                Literal(Name::new("vegetable").unwrap(), vec![ Term::Var(Variable::new("X").unwrap()) ]),
-            ]);
+            Clause(Literal(Name::new("spicy").unwrap(), vec![ Term::Var(Variable::new("X").unwrap(), None) ], None), vec![
+                Goal::Literal(Literal(Name::new("red").unwrap(), vec![ Term::Var(Variable::new("X").unwrap(), None) ], None)),
+                Goal::Literal(Literal(Name::new("vegetable").unwrap(), vec![ Term::Var(Variable::new("X").unwrap(), None) ], None)),
+            ], vec![], None),
+        "degree(X, count<Y>) :- edge(X, Y)" =>
+            Clause(Literal(Name::new("degree").unwrap(), vec![
+                Term::Var(Variable::new("X").unwrap(), None),
+                Term::Var(Variable::new("Y").unwrap(), None),
+            ], None), vec![
+                Goal::Literal(Literal(Name::new("edge").unwrap(), vec![
+                    Term::Var(Variable::new("X").unwrap(), None),
+                    Term::Var(Variable::new("Y").unwrap(), None),
+                ], None)),
+            ], vec![(1, Aggregate::Count)], None);
+
+    [convert_goal, goal] as goal:
+        "foo(X)" => Goal::Literal(Literal(Name::new("foo").unwrap(), vec![Term::Var(Variable::new("X").unwrap(), None)], None)),
+        "not foo(X)" => Goal::Not(Literal(Name::new("foo").unwrap(), vec![Term::Var(Variable::new("X").unwrap(), None)], None)),
+        "\\+ foo(X)" => Goal::Not(Literal(Name::new("foo").unwrap(), vec![Term::Var(Variable::new("X").unwrap(), None)], None)),
+        "nothing(X)" => Goal::Literal(Literal(Name::new("nothing").unwrap(), vec![Term::Var(Variable::new("X").unwrap(), None)], None)),
+        "X * 2 + 1 <= Y" => Goal::Compare(
+            Expr::Op(
+                Box::new(Expr::Op(
+                    Box::new(Expr::Term(Term::Var(Variable::new("X").unwrap(), None))),
+                    ArithOp::Mul,
+                    Box::new(Expr::Term(Term::Value(Value::Int(2), None))))),
+                ArithOp::Add,
+                Box::new(Expr::Term(Term::Value(Value::Int(1), None)))),
+            CmpOp::Le,
+            Expr::Term(Term::Var(Variable::new("Y").unwrap(), None))),
+        "X = Y mod (3 - Z)" => Goal::Compare(
+            Expr::Term(Term::Var(Variable::new("X").unwrap(), None)),
+            CmpOp::Eq,
+            Expr::Op(
+                Box::new(Expr::Term(Term::Var(Variable::new("Y").unwrap(), None))),
+                ArithOp::Mod,
+                Box::new(Expr::Op(
+                    Box::new(Expr::Term(Term::Value(Value::Int(3), None))),
+                    ArithOp::Sub,
+                    Box::new(Expr::Term(Term::Var(Variable::new("Z").unwrap(), None))))))),
+        "Y = X-1" => Goal::Compare(
+            Expr::Term(Term::Var(Variable::new("Y").unwrap(), None)),
+            CmpOp::Eq,
+            Expr::Op(
+                Box::new(Expr::Term(Term::Var(Variable::new("X").unwrap(), None))),
+                ArithOp::Sub,
+                Box::new(Expr::Term(Term::Value(Value::Int(1), None))))),
+        "Y = X - 1" => Goal::Compare(
+            Expr::Term(Term::Var(Variable::new("Y").unwrap(), None)),
+            CmpOp::Eq,
+            Expr::Op(
+                Box::new(Expr::Term(Term::Var(Variable::new("X").unwrap(), None))),
+                ArithOp::Sub,
+                Box::new(Expr::Term(Term::Value(Value::Int(1), None))))),
+        "Y = 3-1" => Goal::Compare(
+            Expr::Term(Term::Var(Variable::new("Y").unwrap(), None)),
+            CmpOp::Eq,
+            Expr::Op(
+                Box::new(Expr::Term(Term::Value(Value::Int(3), None))),
+                ArithOp::Sub,
+                Box::new(Expr::Term(Term::Value(Value::Int(1), None))))),
+        "Y = X*2-1" => Goal::Compare(
+            Expr::Term(Term::Var(Variable::new("Y").unwrap(), None)),
+            CmpOp::Eq,
+            Expr::Op(
+                Box::new(Expr::Op(
+                    Box::new(Expr::Term(Term::Var(Variable::new("X").unwrap(), None))),
+                    ArithOp::Mul,
+                    Box::new(Expr::Term(Term::Value(Value::Int(2), None))))),
+                ArithOp::Sub,
+                Box::new(Expr::Term(Term::Value(Value::Int(1), None))))),
+        "Y = -X" => Goal::Compare(
+            Expr::Term(Term::Var(Variable::new("Y").unwrap(), None)),
+            CmpOp::Eq,
+            Expr::Neg(Box::new(Expr::Term(Term::Var(Variable::new("X").unwrap(), None))))),
+        "Y = -(X + 1) * -2" => Goal::Compare(
+            Expr::Term(Term::Var(Variable::new("Y").unwrap(), None)),
+            CmpOp::Eq,
+            Expr::Op(
+                Box::new(Expr::Neg(Box::new(Expr::Op(
+                    Box::new(Expr::Term(Term::Var(Variable::new("X").unwrap(), None))),
+                    ArithOp::Add,
+                    Box::new(Expr::Term(Term::Value(Value::Int(1), None))))))),
+                ArithOp::Mul,
+                Box::new(Expr::Term(Term::Value(Value::Int(-2), None)))));
 
     [convert_literal, literal] as literal:
-        "foo" => Literal(Name::new("foo").unwrap(), vec![]),
-        "bar(X)" => Literal(Name::new("bar").unwrap(), vec![Term::Var(Variable::new("X").unwrap())]);
+        "foo" => Literal(Name::new("foo").unwrap(), vec![], None),
+        "bar(X)" => Literal(Name::new("bar").unwrap(), vec![Term::Var(Variable::new("X").unwrap(), None)], None);
 
     [convert_term, term] as term:
-        "foo" => Term::Name(Name::new("foo").unwrap()),
-        "Bar" => Term::Var(Variable::new("Bar").unwrap());
+        "foo" => Term::Value(Value::new("foo").unwrap(), None),
+        "Bar" => Term::Var(Variable::new("Bar").unwrap(), None);
+
+    [convert_value, value] as value:
+        "foo" => Value::new("foo").unwrap(),
+        "42" => Value::Int(42),
+        "-7" => Value::Int(-7),
+        "1.5" => Value::Float(1.5),
+        "-2.0e10" => Value::Float(-2.0e10),
+        "1e3" => Value::Float(1e3),
+        "true" => Value::Bool(true),
+        "trueish" => Value::new("trueish").unwrap(),
+        "4x4" => Value::new("4x4").unwrap(),
+        "\"42\"" => Value::string("42"),
+        "'42'" => Value::Symbol(Name::new("42").unwrap()),
+        "'hello\\'s world'" => Value::Symbol(Name::new("hello's world").unwrap());
 
     [convert_name, name] as name:
         "foo" => Name::new("foo").unwrap(),
@@ -3,9 +3,24 @@ use std::char::from_u32 as char_from_u32;
 use pest::Error;
 use pest::iterators::{Pair, Pairs};
 
-use ast::{Clause, Literal, Name, Program, Statement, Term, Variable};
+use ast::{Aggregate, ArithOp, Clause, CmpOp, Declaration, Expr, Goal,
+          Literal, Name, Program, Span, Statement, Term, Type, Value,
+          Variable};
 use parser::Rule;
-use parser::utils::{as_amb, as_one, as_one_any};
+use parser::utils::{as_amb, as_one, as_one_any, as_one_token};
+
+/// Returns the byte range of a token. The rest of the span is filled in once
+/// the whole program has been converted, since finding the line and column of
+/// each token separately would take time quadratic in the length of the
+/// source.
+fn span(token: &Pair<Rule>) -> Option<Span> {
+    let span = token.clone().into_span();
+    Some(Span {
+        start: span.start(),
+        end: span.end(),
+        ..Span::default()
+    })
+}
 
 pub fn convert_program<'a>(
     pairs: Pairs<'a, Rule>,
@@ -29,6 +44,9 @@ pub fn convert_statement_one<'a>(
     pairs: Pairs<'a, Rule>,
 ) -> Result<Statement, Error<'a, Rule>> {
     as_one_any(pairs, Rule::stmt, |token| match token.as_rule() {
+        Rule::declaration => {
+            convert_declaration_one(token).map(Statement::Declaration)
+        }
         Rule::assertion => {
             convert_clause(token.into_inner()).map(Statement::Assertion)
         }
@@ -36,51 +54,231 @@ pub fn convert_statement_one<'a>(
             convert_clause(token.into_inner()).map(Statement::Retraction)
         }
         Rule::query => {
-            convert_literal(token.into_inner()).map(Statement::Query)
+            as_one(token.into_inner(), Rule::literal_list, |goals| {
+                goals
+                    .map(Pair::into_inner)
+                    .map(convert_goal_one)
+                    .collect::<Result<_, _>>()
+                    .map(Statement::Query)
+            })
         }
         _ => Err(Error::ParsingError {
-            positives: vec![Rule::assertion, Rule::retraction, Rule::query],
+            positives: vec![
+                Rule::declaration,
+                Rule::assertion,
+                Rule::retraction,
+                Rule::query,
+            ],
             negatives: vec![],
             pos: token.into_span().start_pos(),
         }),
     })
 }
 
+pub fn convert_declaration_one<'a>(
+    token: Pair<'a, Rule>,
+) -> Result<Declaration, Error<'a, Rule>> {
+    let span = span(&token);
+    as_amb(token.into_inner(), Rule::name, Rule::column_list, |pred, cols| {
+        let pred = convert_name_one(pred.into_inner())?;
+        let columns = cols.into_iter().flat_map(Pair::into_inner).map(|column| {
+            let mut pairs = column.into_inner();
+            let name = Name::new(pairs.next().unwrap().as_str()).unwrap();
+            let ty = match pairs.next().unwrap().as_str() {
+                "bool" => Type::Bool,
+                "int" => Type::Int,
+                "float" => Type::Float,
+                "string" => Type::Str,
+                "symbol" => Type::Symbol,
+                s => unreachable!("unrecognized type {}", s),
+            };
+            (name, ty)
+        });
+        Ok(Declaration(pred, columns.collect(), span))
+    })
+}
+
 pub fn convert_clause<'a>(
     pairs: Pairs<'a, Rule>,
 ) -> Result<Clause, Error<'a, Rule>> {
-    as_one(pairs, Rule::clause, |pairs| {
-        as_amb(pairs, Rule::literal, Rule::literal_list, |head, body| {
-            let head = convert_literal_one(head)?;
+    as_one_token(pairs, Rule::clause, |token| {
+        let span = span(&token);
+        let pairs = token.into_inner();
+        as_amb(pairs, Rule::head, Rule::literal_list, |head, body| {
+            let (head, aggregates) = convert_head_one(head)?;
             let body = if let Some(body) = body {
-                body.map(Pair::into_inner)
-                    .map(convert_literal_one)
-                    .collect()
+                let goals = body.into_inner().map(Pair::into_inner);
+                goals.map(convert_goal_one).collect()
             } else {
                 Ok(vec![])
             };
-            Ok(Clause(head, body?))
+            Ok(Clause(head, body?, aggregates, span))
         })
     })
 }
 
+/// Converts the head of a clause, returning the aggregates in it separately.
+/// The aggregated variable is left in the head in place of each aggregate.
+pub fn convert_head_one<'a>(
+    token: Pair<'a, Rule>,
+) -> Result<(Literal, Vec<(usize, Aggregate)>), Error<'a, Rule>> {
+    let head_span = span(&token);
+    as_amb(token.into_inner(), Rule::name, Rule::head_arg_list, |pred, args| {
+        let pred = convert_name_one(pred.into_inner())?;
+        let mut terms = Vec::new();
+        let mut aggregates = Vec::new();
+        let args = args.into_iter().flat_map(Pair::into_inner);
+        for (i, arg) in args.enumerate() {
+            let arg = arg.into_inner().next().unwrap();
+            if arg.as_rule() == Rule::aggregate {
+                let mut pairs = arg.into_inner();
+                let aggregate = match pairs.next().unwrap().as_str() {
+                    "count" => Aggregate::Count,
+                    "sum" => Aggregate::Sum,
+                    "min" => Aggregate::Min,
+                    "max" => Aggregate::Max,
+                    "avg" => Aggregate::Avg,
+                    s => unreachable!("unrecognized aggregate {}", s),
+                };
+                let var = pairs.next().unwrap();
+                let span = span(&var);
+                terms.push(Term::Var(convert_variable_one(var), span));
+                aggregates.push((i, aggregate));
+            } else {
+                terms.push(convert_term_one(arg.into_inner())?);
+            }
+        }
+        Ok((Literal(pred, terms, head_span), aggregates))
+    })
+}
+
+#[cfg(test)]
+pub fn convert_goal<'a>(
+    pairs: Pairs<'a, Rule>,
+) -> Result<Goal, Error<'a, Rule>> {
+    as_one(pairs, Rule::goal, convert_goal_one)
+}
+
+pub fn convert_goal_one<'a>(
+    pairs: Pairs<'a, Rule>,
+) -> Result<Goal, Error<'a, Rule>> {
+    as_one_any(pairs, Rule::goal, |token| match token.as_rule() {
+        Rule::literal => convert_literal_one(token).map(Goal::Literal),
+        Rule::negation => {
+            as_amb(token.into_inner(), Rule::not_op, Rule::literal, |_, l| {
+                convert_literal_one(l.unwrap()).map(Goal::Not)
+            })
+        }
+        Rule::comparison => {
+            let mut pairs = token.into_inner();
+            let l = convert_expr_one(pairs.next().unwrap().into_inner())?;
+            let op = match pairs.next().unwrap().as_str() {
+                "=" => CmpOp::Eq,
+                "!=" => CmpOp::Ne,
+                "<" => CmpOp::Lt,
+                "<=" => CmpOp::Le,
+                ">" => CmpOp::Gt,
+                ">=" => CmpOp::Ge,
+                s => unreachable!("unrecognized comparison operator {}", s),
+            };
+            let r = convert_expr_one(pairs.next().unwrap().into_inner())?;
+            Ok(Goal::Compare(l, op, r))
+        }
+        _ => Err(Error::ParsingError {
+            positives: vec![Rule::literal, Rule::negation, Rule::comparison],
+            negatives: vec![],
+            pos: token.into_span().start_pos(),
+        }),
+    })
+}
+
+/// Converts the operands and operators of an `expr` or `product`, which are
+/// left-associative.
+pub fn convert_expr_one<'a>(
+    mut pairs: Pairs<'a, Rule>,
+) -> Result<Expr, Error<'a, Rule>> {
+    let mut expr = convert_operand(pairs.next().unwrap())?;
+    while let Some(op) = pairs.next() {
+        let op = match op.as_str() {
+            "+" => ArithOp::Add,
+            "-" => ArithOp::Sub,
+            "*" => ArithOp::Mul,
+            "/" => ArithOp::Div,
+            "mod" => ArithOp::Mod,
+            s => unreachable!("unrecognized arithmetic operator {}", s),
+        };
+        let r = convert_operand(pairs.next().unwrap())?;
+        expr = Expr::Op(Box::new(expr), op, Box::new(r));
+    }
+    Ok(expr)
+}
+
+/// Converts a `product` or an `operand`, which is a parenthesized expression,
+/// a term, or a negated operand.
+fn convert_operand<'a>(token: Pair<'a, Rule>) -> Result<Expr, Error<'a, Rule>> {
+    match token.as_rule() {
+        Rule::product => convert_expr_one(token.into_inner()),
+        Rule::operand => {
+            let mut pairs = token.into_inner();
+            let first = pairs.next().unwrap();
+            match first.as_rule() {
+                Rule::expr => convert_expr_one(first.into_inner()),
+                Rule::neg_op => {
+                    let operand = convert_operand(pairs.next().unwrap())?;
+                    Ok(Expr::Neg(Box::new(operand)))
+                }
+                _ => {
+                    let pos = first.clone().into_span().start_pos();
+                    let text = first.as_str();
+                    let term = convert_term_one(first.into_inner())?;
+                    // An identifier may contain hyphens, so `x-1` is a single
+                    // symbol, which is almost certainly not what was meant.
+                    if let Term::Value(Value::Symbol(_), _) = term {
+                        if text.contains('-') && !text.starts_with('\'') {
+                            return Err(Error::CustomErrorPos {
+                                message: format!(
+                                    "a symbol with a hyphen must be quoted in \
+                                     an expression, as '{}'; to subtract, \
+                                     write {}",
+                                    text,
+                                    text.replace('-', " - ")
+                                ),
+                                pos,
+                            });
+                        }
+                    }
+                    Ok(Expr::Term(term))
+                }
+            }
+        }
+        _ => Err(Error::ParsingError {
+            positives: vec![Rule::product, Rule::operand],
+            negatives: vec![],
+            pos: token.into_span().start_pos(),
+        }),
+    }
+}
+
+#[cfg(test)]
 pub fn convert_literal<'a>(
     pairs: Pairs<'a, Rule>,
 ) -> Result<Literal, Error<'a, Rule>> {
-    as_one(pairs, Rule::literal, convert_literal_one)
+    as_one_token(pairs, Rule::literal, convert_literal_one)
 }
 
 pub fn convert_literal_one<'a>(
-    pairs: Pairs<'a, Rule>,
+    token: Pair<'a, Rule>,
 ) -> Result<Literal, Error<'a, Rule>> {
-    as_amb(pairs, Rule::name, Rule::term_list, |pred, args| {
-        let pred = convert_name_one(pred)?;
+    let span = span(&token);
+    as_amb(token.into_inner(), Rule::name, Rule::term_list, |pred, args| {
+        let pred = convert_name_one(pred.into_inner())?;
         let args = if let Some(args) = args {
-            args.map(Pair::into_inner).map(convert_term_one).collect()
+            let terms = args.into_inner().map(Pair::into_inner);
+            terms.map(convert_term_one).collect()
         } else {
             Ok(vec![])
         };
-        Ok(Literal(pred, args?))
+        Ok(Literal(pred, args?, span))
     })
 }
 
@@ -95,16 +293,83 @@ pub fn convert_term_one<'a>(
     pairs: Pairs<'a, Rule>,
 ) -> Result<Term, Error<'a, Rule>> {
     as_one_any(pairs, Rule::term, |token| match token.as_rule() {
-        Rule::name => convert_name_one(token.into_inner()).map(Term::Name),
-        Rule::variable => Ok(Term::Var(convert_variable_one(token))),
+        Rule::value => {
+            let span = span(&token);
+            let value = convert_value_one(token.into_inner())?;
+            Ok(Term::Value(value, span))
+        }
+        Rule::variable => {
+            let span = span(&token);
+            Ok(Term::Var(convert_variable_one(token), span))
+        }
         _ => Err(Error::ParsingError {
-            positives: vec![Rule::name, Rule::variable],
+            positives: vec![Rule::value, Rule::variable],
             negatives: vec![],
             pos: token.into_span().start_pos(),
         }),
     })
 }
 
+#[cfg(test)]
+pub fn convert_value<'a>(
+    pairs: Pairs<'a, Rule>,
+) -> Result<Value, Error<'a, Rule>> {
+    as_one(pairs, Rule::value, convert_value_one)
+}
+
+pub fn convert_value_one<'a>(
+    pairs: Pairs<'a, Rule>,
+) -> Result<Value, Error<'a, Rule>> {
+    as_one_any(pairs, Rule::value, |token| {
+        let pos = token.clone().into_span().start_pos();
+        match token.as_rule() {
+            Rule::int => token.as_str().parse().map(Value::Int).map_err(|_| {
+                Error::CustomErrorPos {
+                    message: "integer literal is out of range".to_string(),
+                    pos,
+                }
+            }),
+            // The grammar only admits valid floats, but they may be too large
+            // to be finite, and infinity can't be written back out.
+            Rule::float => match token.as_str().parse::<f64>().unwrap() {
+                f if f.is_finite() => Ok(Value::float(f)),
+                _ => Err(Error::CustomErrorPos {
+                    message: "float literal is out of range".to_string(),
+                    pos,
+                }),
+            },
+            Rule::boolean => Ok(Value::Bool(token.as_str() == "true")),
+            Rule::string => convert_string(token).map(|s| Value::string(&s)),
+            Rule::symbol => {
+                // Like a name in string syntax, this fails for 'X'.
+                let symbol = convert_string(token)?;
+                Name::new(&symbol).map(Value::Symbol).ok_or_else(|| {
+                    Error::ParsingError {
+                        positives: vec![Rule::symbol],
+                        negatives: vec![],
+                        pos,
+                    }
+                })
+            }
+            Rule::ident => {
+                Ok(Value::Symbol(Name::new(token.as_str()).unwrap()))
+            }
+            _ => Err(Error::ParsingError {
+                positives: vec![
+                    Rule::int,
+                    Rule::float,
+                    Rule::boolean,
+                    Rule::string,
+                    Rule::symbol,
+                    Rule::ident,
+                ],
+                negatives: vec![],
+                pos,
+            }),
+        }
+    })
+}
+
 #[cfg(test)]
 pub fn convert_name<'a>(
     pairs: Pairs<'a, Rule>,
@@ -124,11 +389,7 @@ pub fn convert_name_one<'a>(
             Rule::string => {
                 // This might fail, since "X" is a valid string but an invalid
                 // name.
-                let string = token
-                    .clone()
-                    .into_inner()
-                    .map(convert_char)
-                    .collect::<Result<String, _>>()?;
+                let string = convert_string(token.clone())?;
                 Name::new(&string).ok_or_else(|| Error::ParsingError {
                     positives: vec![Rule::name],
                     negatives: vec![],
@@ -144,11 +405,17 @@ pub fn convert_name_one<'a>(
     })
 }
 
+pub fn convert_string<'a>(
+    token: Pair<'a, Rule>,
+) -> Result<String, Error<'a, Rule>> {
+    token.into_inner().map(convert_char).collect()
+}
+
 pub fn convert_char<'a>(
     token: Pair<'a, Rule>,
 ) -> Result<char, Error<'a, Rule>> {
     match token.as_rule() {
-        Rule::raw_ch => {
+        Rule::raw_ch | Rule::sym_ch => {
             let mut s = token.as_str().chars();
             let ch = s.next().unwrap();
             assert_eq!(s.next(), None);
@@ -173,6 +440,7 @@ pub fn convert_escape<'a>(
 ) -> Result<char, Error<'a, Rule>> {
     match token.as_rule() {
         Rule::hex_esc | Rule::uni4_esc | Rule::uni8_esc => {
+            let pos = token.clone().into_span().start_pos();
             let mut n = 0;
             for token in token.into_inner() {
                 assert_eq!(token.as_rule(), Rule::hex_digit);
@@ -196,11 +464,10 @@ pub fn convert_escape<'a>(
                     s => unimplemented!("error: unrecognized hex digit {}", s),
                 };
             }
-            if let Some(ch) = char_from_u32(n) {
-                Ok(ch)
-            } else {
-                unimplemented!("bad hex escape")
-            }
+            char_from_u32(n).ok_or_else(|| Error::CustomErrorPos {
+                message: format!("invalid character escape {:x}", n),
+                pos,
+            })
         }
         Rule::predef_esc => match token.as_str() {
             "n" => Ok('\n'),
@@ -116,7 +116,7 @@ fn literals() {
                 ]),
                 term_list(2, 5, [
                     term(2, 5, [
-                        name(2, 5, [
+                        value(2, 5, [
                             ident(2, 5),
                         ]),
                     ]),
@@ -135,12 +135,12 @@ fn literals() {
                 ]),
                 term_list(7, 20, [
                     term(7, 11, [
-                        name(7, 11, [
+                        value(7, 11, [
                             ident(7, 11),
                         ]),
                     ]),
                     term(13, 20, [
-                        name(13, 20, [
+                        value(13, 20, [
                             ident(13, 20),
                         ]),
                     ]),
@@ -159,12 +159,12 @@ fn literals() {
                 ]),
                 term_list(5, 19, [
                     term(5, 7, [
-                        name(5, 7, [
-                            ident(5, 7),
+                        value(5, 7, [
+                            int(5, 7),
                         ]),
                     ]),
                     term(9, 19, [
-                        name(9, 19, [
+                        value(9, 19, [
                             string(9, 19, [
                                 esc_ch(10, 12, [
                                     predef_esc(11, 12)
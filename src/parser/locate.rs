@@ -0,0 +1,93 @@
+use std::iter::once;
+
+use symbol::Symbol;
+
+use ast::{Clause, Expr, Goal, Literal, Span, Statement, Term};
+
+/// The start of each line of a source, for filling in the file, line, and
+/// column of the spans in a statement converted from it, which only have
+/// their byte ranges.
+pub struct Lines<'a> {
+    src: &'a str,
+    file: Option<Symbol>,
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    pub fn new(src: &'a str, file: Option<&str>) -> Lines<'a> {
+        let breaks = src.match_indices('\n').map(|(i, _)| i + 1);
+        Lines {
+            src,
+            file: file.map(Symbol::from),
+            starts: once(0).chain(breaks).collect(),
+        }
+    }
+
+    pub fn stmt(&self, stmt: &mut Statement) {
+        match *stmt {
+            Statement::Declaration(ref mut decl) => self.span(&mut decl.2),
+            Statement::Assertion(ref mut clause)
+            | Statement::Retraction(ref mut clause) => self.clause(clause),
+            Statement::Query(ref mut goals) => for goal in goals {
+                self.goal(goal);
+            },
+        }
+    }
+
+    fn clause(&self, clause: &mut Clause) {
+        self.span(&mut clause.3);
+        self.literal(&mut clause.0);
+        for goal in &mut clause.1 {
+            self.goal(goal);
+        }
+    }
+
+    fn goal(&self, goal: &mut Goal) {
+        match *goal {
+            Goal::Literal(ref mut l) | Goal::Not(ref mut l) => self.literal(l),
+            Goal::Compare(ref mut l, _, ref mut r) => {
+                self.expr(l);
+                self.expr(r);
+            }
+        }
+    }
+
+    fn expr(&self, expr: &mut Expr) {
+        match *expr {
+            Expr::Term(ref mut term) => self.term(term),
+            Expr::Op(ref mut l, _, ref mut r) => {
+                self.expr(l);
+                self.expr(r);
+            }
+            Expr::Neg(ref mut e) => self.expr(e),
+        }
+    }
+
+    fn literal(&self, literal: &mut Literal) {
+        self.span(&mut literal.2);
+        for term in &mut literal.1 {
+            self.term(term);
+        }
+    }
+
+    fn term(&self, term: &mut Term) {
+        match *term {
+            Term::Value(_, ref mut span) | Term::Var(_, ref mut span) => {
+                self.span(span)
+            }
+        }
+    }
+
+    fn span(&self, span: &mut Option<Span>) {
+        if let Some(ref mut span) = *span {
+            let line = match self.starts.binary_search(&span.start) {
+                Ok(line) => line,
+                Err(line) => line - 1,
+            };
+            let start = self.starts[line];
+            span.file = self.file;
+            span.line = line + 1;
+            span.col = self.src[start..span.start].chars().count() + 1;
+        }
+    }
+}
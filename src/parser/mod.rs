@@ -1,22 +1,28 @@
+mod anonymous;
 mod convert;
+mod locate;
 mod utils;
 
 #[cfg(test)]
 mod ast_tests;
 #[cfg(test)]
 mod cst_tests;
+#[cfg(test)]
+mod round_trip_tests;
 
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 
-use pest::Parser;
+use pest::{Error as PestError, Parser};
 
 use ast::{Program, Statement};
-use errors::Result;
+use errors::{Error, ErrorKind, Result};
 
+use self::anonymous::rename_anonymous;
 use self::convert::{convert_program, convert_statement};
+use self::locate::Lines;
 
 #[cfg(debug_assertions)]
 const _GRAMMAR: &'static str = include_str!("datalog.pest");
@@ -28,10 +34,14 @@ struct DatalogParser;
 impl Display for Rule {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
         match *self {
-            Rule::ident_ch => fmt.write_str("identifier character"),
+            Rule::ident_ch | Rule::var_ch => {
+                fmt.write_str("identifier character")
+            }
             Rule::ident => fmt.write_str("identifier"),
             Rule::variable => fmt.write_str("variable"),
-            Rule::raw_ch => fmt.write_str("non-escape character"),
+            Rule::raw_ch | Rule::sym_ch => {
+                fmt.write_str("non-escape character")
+            }
             Rule::hex_digit => fmt.write_str("hex digit"),
             Rule::hex_esc => fmt.write_str("\\x escape"),
             Rule::uni4_esc => fmt.write_str("\\u escape"),
@@ -39,15 +49,42 @@ impl Display for Rule {
             Rule::predef_esc => fmt.write_str("predefined escape"),
             Rule::esc_ch => fmt.write_str("escape character"),
             Rule::string => fmt.write_str("string"),
+            Rule::symbol => fmt.write_str("quoted symbol"),
             Rule::name => fmt.write_str("name"),
+            Rule::digits => fmt.write_str("digits"),
+            Rule::exponent => fmt.write_str("exponent"),
+            Rule::int => fmt.write_str("integer"),
+            Rule::float => fmt.write_str("float"),
+            Rule::boolean => fmt.write_str("boolean"),
+            Rule::value => fmt.write_str("value"),
             Rule::literal => fmt.write_str("literal"),
-            Rule::literal_list => fmt.write_str("list of literals"),
+            Rule::not_op => fmt.write_str("negation operator"),
+            Rule::negation => fmt.write_str("negated literal"),
+            Rule::goal => fmt.write_str("goal"),
+            Rule::cmp_op => fmt.write_str("comparison operator"),
+            Rule::comparison => fmt.write_str("comparison"),
+            Rule::add_op | Rule::mul_op => {
+                fmt.write_str("arithmetic operator")
+            }
+            Rule::neg_op => fmt.write_str("negation"),
+            Rule::operand => fmt.write_str("operand"),
+            Rule::product | Rule::expr => fmt.write_str("expression"),
+            Rule::literal_list => fmt.write_str("list of goals"),
             Rule::term => fmt.write_str("term"),
             Rule::term_list => fmt.write_str("list of terms"),
+            Rule::agg_op => fmt.write_str("aggregate function"),
+            Rule::aggregate => fmt.write_str("aggregate"),
+            Rule::head_arg => fmt.write_str("term or aggregate"),
+            Rule::head_arg_list => fmt.write_str("list of terms"),
+            Rule::head => fmt.write_str("head"),
             Rule::clause => fmt.write_str("clause"),
             Rule::assertion => fmt.write_str("assertion"),
             Rule::retraction => fmt.write_str("retraction"),
             Rule::query => fmt.write_str("query"),
+            Rule::type_name => fmt.write_str("type"),
+            Rule::column => fmt.write_str("column"),
+            Rule::column_list => fmt.write_str("list of columns"),
+            Rule::declaration => fmt.write_str("declaration"),
             Rule::stmt => fmt.write_str("statement"),
             Rule::program => fmt.write_str("program"),
             Rule::stmt_all => fmt.write_str("statement"),
@@ -60,22 +97,207 @@ impl Display for Rule {
 
 /// Parses a program from the given file.
 pub fn parse_program_file<P: AsRef<Path>>(path: P) -> Result<Program> {
+    let path = path.as_ref();
     let mut f = File::open(path)?;
     let mut buf = String::new();
     f.read_to_string(&mut buf)?;
-    parse_program_string(&buf)
+    parse_program_source(&buf, Some(&path.display().to_string()))
 }
 
 /// Parses a program from the given string.
 pub fn parse_program_string(src: &str) -> Result<Program> {
-    DatalogParser::parse(Rule::program, src)
+    parse_program_source(src, None)
+}
+
+/// Parses a program from the given string, giving the spans in it the given
+/// file name.
+pub fn parse_program_source(src: &str, file: Option<&str>) -> Result<Program> {
+    let mut program = DatalogParser::parse(Rule::program, src)
         .and_then(convert_program)
-        .map_err(|_err| unimplemented!("Convert parse error")) // TODO
+        .map_err(|err| convert_error(src, err))?;
+    let lines = Lines::new(src, file);
+    for stmt in &mut program.0 {
+        rename_anonymous(stmt);
+        lines.stmt(stmt);
+    }
+    Ok(program)
 }
 
 /// Parses a statement from the given string.
 pub fn parse_stmt(src: &str) -> Result<Statement> {
-    DatalogParser::parse(Rule::stmt, src)
+    let mut stmt = DatalogParser::parse(Rule::stmt, src)
         .and_then(convert_statement)
-        .map_err(|_err| unimplemented!("Convert parse error")) // TODO
+        .map_err(|err| convert_error(src, err))?;
+    rename_anonymous(&mut stmt);
+    Lines::new(src, None).stmt(&mut stmt);
+    Ok(stmt)
+}
+
+/// Converts an error from pest to an `ErrorKind::Parse`, with the line of the
+/// source the error occurred on and a caret pointing to the error.
+fn convert_error(src: &str, err: PestError<Rule>) -> Error {
+    let (pos, msg) = match err {
+        PestError::ParsingError {
+            positives,
+            negatives,
+            pos,
+        } => {
+            let msg = match (positives.is_empty(), negatives.is_empty()) {
+                (false, true) => format!("expected {}", list(&positives)),
+                (true, false) => format!("unexpected {}", list(&negatives)),
+                (false, false) => format!(
+                    "unexpected {}; expected {}",
+                    list(&negatives),
+                    list(&positives)
+                ),
+                (true, true) => "unknown parsing error".to_string(),
+            };
+            (pos, msg)
+        }
+        PestError::CustomErrorPos { message, pos } => (pos, message),
+        PestError::CustomErrorSpan { message, span } => {
+            (span.start_pos(), message)
+        }
+    };
+
+    let (line, col) = pos.line_col();
+    let text = src.lines().nth(line - 1).unwrap_or("");
+    // Tabs are kept in the caret's indentation, so it lines up however wide
+    // the terminal renders them.
+    let indent = text.chars()
+        .take(col - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    let excerpt = format!("{}\n{}^", text, indent);
+    ErrorKind::Parse(line, col, msg, excerpt).into()
+}
+
+/// Lists rules in English, for example "name, variable, or string".
+fn list(rules: &[Rule]) -> String {
+    let mut names = Vec::<String>::new();
+    for rule in rules {
+        let name = rule.to_string();
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    match names.len() {
+        1 => names.remove(0),
+        2 => format!("{} or {}", names[0], names[1]),
+        n => format!("{}, or {}", names[..n - 1].join(", "), names[n - 1]),
+    }
+}
+
+#[test]
+fn parse_errors() {
+    let err = |src: &str| match *parse_program_string(src).unwrap_err().kind()
+    {
+        ErrorKind::Parse(line, col, ref msg, ref excerpt) => {
+            (line, col, msg.clone(), excerpt.clone())
+        }
+        ref err => panic!("unexpected error {}", err),
+    };
+
+    let (line, col, msg, excerpt) = err("edge(a, b).\n\tpath(X, Y) :- .");
+    assert_eq!((line, col), (2, 16));
+    assert_eq!(msg, "expected goal or list of goals");
+    assert_eq!(excerpt, "\tpath(X, Y) :- .\n\t              ^");
+
+    let (line, col, msg, _) = err("edge(a, \"\\UFFFFFFFF\").");
+    assert_eq!((line, col), (1, 11));
+    assert_eq!(msg, "invalid character escape ffffffff");
+
+    let (line, col, msg, _) = err("p(1e999).");
+    assert_eq!((line, col), (1, 3));
+    assert_eq!(msg, "float literal is out of range");
+
+    let (line, col, msg, _) = err("p(Y) :- q(X), Y = x-1.");
+    assert_eq!((line, col), (1, 19));
+    assert_eq!(
+        msg,
+        "a symbol with a hyphen must be quoted in an expression, as 'x-1'; \
+         to subtract, write x - 1"
+    );
+
+    let err = parse_stmt("foo :- .").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        concat!(
+            "Parse error at 1:8: expected goal or list of goals\n",
+            "foo :- .\n       ^",
+        )
+    );
+}
+
+#[test]
+fn anonymous_variables() {
+    use ast::Goal;
+
+    let stmt = parse_stmt("p(_, _0, X) :- q(_, X), _ > 1.").unwrap();
+    assert_eq!(stmt.to_string(), "p(_, _0, X) :- q(_, X), _ > 1.");
+
+    let clause = match stmt {
+        Statement::Assertion(clause) => clause,
+        _ => unreachable!(),
+    };
+    let mut vars = clause
+        .body()
+        .iter()
+        .flat_map(Goal::vars)
+        .collect::<Vec<_>>();
+    vars.extend(Goal::Literal(clause.head().clone()).vars());
+    vars.sort();
+    vars.dedup();
+    let anonymous = vars.iter().filter(|var| var.is_anonymous()).count();
+    assert_eq!((vars.len(), anonymous), (5, 3));
+}
+
+#[test]
+fn spans() {
+    use ast::{Goal, Span};
+
+    let src = concat!(
+        "edge(a, b). edge(b, c).\n",
+        "% comment\n",
+        "\n",
+        "  path(X, \"\u{e9}\") :-\n",
+        "  \"\u{e9}\"(X), not edge(X, _).",
+    );
+    let program = parse_program_source(src, Some("path.dl")).unwrap();
+    let clause = match program.0[2] {
+        Statement::Assertion(ref clause) => clause,
+        ref stmt => panic!("unexpected statement {}", stmt),
+    };
+    let span = |start, end, line, col| {
+        Some(Span {
+            file: Some("path.dl".into()),
+            start,
+            end,
+            line,
+            col,
+        })
+    };
+    assert_eq!(clause.span(), span(37, 79, 4, 3));
+    assert_eq!(clause.span().unwrap().to_string(), "path.dl:4:3");
+    assert_eq!(clause.head().span(), span(37, 50, 4, 3));
+    assert_eq!(clause.head().1[1].span(), span(45, 49, 4, 11));
+    match clause.body()[1] {
+        Goal::Not(ref l) => {
+            assert_eq!(l.span(), span(69, 79, 5, 15));
+            assert_eq!(l.1[1].span(), span(77, 78, 5, 23));
+        }
+        ref goal => panic!("unexpected goal {}", goal),
+    }
+
+    let program = parse_program_string("edge(a, b).\n  loop(X).").unwrap();
+    let err = match program.0[1] {
+        Statement::Assertion(ref clause) => clause.check_safety().unwrap_err(),
+        ref stmt => panic!("unexpected statement {}", stmt),
+    };
+    assert_eq!(
+        err.to_string(),
+        "The clause loop(X) at 2:3 is unsafe, since the variable X does not \
+         appear in a positive literal in its body"
+    );
 }
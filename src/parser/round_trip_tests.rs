@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use proptest::prelude::*;
+
+use ast::{Aggregate, ArithOp, Clause, CmpOp, Declaration, Expr, Goal,
+          Literal, Name, Program, Statement, Term, Type, Value, Variable};
+
+fn name() -> BoxedStrategy<Name> {
+    "[^A-Z_](.|\n){0,8}"
+        .prop_map(|s| Name::new(&s).unwrap())
+        .boxed()
+}
+
+/// Variables are never `_`, since each `_` is read back as a fresh variable.
+fn variable() -> BoxedStrategy<Variable> {
+    "[A-Z_][A-Za-z0-9_]{0,4}"
+        .prop_filter("anonymous", |s| s != "_")
+        .prop_map(|s| Variable::new(&s).unwrap())
+        .boxed()
+}
+
+/// Symbols include ones that look like other values, such as `42` or `true`.
+fn value() -> BoxedStrategy<Value> {
+    let float = prop::num::f64::POSITIVE | prop::num::f64::NEGATIVE
+        | prop::num::f64::NORMAL | prop::num::f64::SUBNORMAL
+        | prop::num::f64::ZERO;
+    let symbol = prop_oneof![
+        name(),
+        "true|false|[a-z0-9][a-z0-9_-]{0,6}"
+            .prop_map(|s| Name::new(&s).unwrap()),
+    ];
+    prop_oneof![
+        prop::bool::ANY.prop_map(Value::Bool),
+        prop::num::i64::ANY.prop_map(Value::Int),
+        float.prop_map(Value::float),
+        "(.|\n){0,8}".prop_map(|s| Value::string(&s)),
+        symbol.prop_map(Value::Symbol),
+    ].boxed()
+}
+
+fn term() -> BoxedStrategy<Term> {
+    prop_oneof![
+        variable().prop_map(|var| Term::Var(var, None)),
+        value().prop_map(|value| Term::Value(value, None)),
+    ].boxed()
+}
+
+prop_compose! {
+    fn literal()(pred in name(), args in prop::collection::vec(term(), 0..3))
+        -> Literal
+    {
+        Literal(pred, args, None)
+    }
+}
+
+fn expr() -> BoxedStrategy<Expr> {
+    let op = prop_oneof![
+        Just(ArithOp::Add),
+        Just(ArithOp::Sub),
+        Just(ArithOp::Mul),
+        Just(ArithOp::Div),
+        Just(ArithOp::Mod),
+    ];
+    term()
+        .prop_map(Expr::Term)
+        .prop_recursive(2, 8, 2, move |expr: Arc<BoxedStrategy<Expr>>| {
+            prop_oneof![
+                (expr.clone(), op.clone(), expr.clone()).prop_map(
+                    |(l, op, r)| Expr::Op(Box::new(l), op, Box::new(r))
+                ),
+                expr.prop_map(|e| Expr::Neg(Box::new(e))),
+            ].boxed()
+        })
+        .boxed()
+}
+
+fn goal() -> BoxedStrategy<Goal> {
+    let op = prop_oneof![
+        Just(CmpOp::Eq),
+        Just(CmpOp::Ne),
+        Just(CmpOp::Lt),
+        Just(CmpOp::Le),
+        Just(CmpOp::Gt),
+        Just(CmpOp::Ge),
+    ];
+    prop_oneof![
+        literal().prop_map(Goal::Literal),
+        literal().prop_map(Goal::Not),
+        (expr(), op, expr()).prop_map(|(l, op, r)| Goal::Compare(l, op, r)),
+    ].boxed()
+}
+
+/// Generates a clause, with an aggregate over some of the variables in its
+/// head.
+fn clause() -> BoxedStrategy<Clause> {
+    let aggregate = prop::option::of(prop_oneof![
+        Just(Aggregate::Count),
+        Just(Aggregate::Sum),
+        Just(Aggregate::Min),
+        Just(Aggregate::Max),
+        Just(Aggregate::Avg),
+    ]);
+    let body = prop::collection::vec(goal(), 0..3);
+    let aggregates = prop::collection::vec(aggregate, 3..4);
+    (literal(), body, aggregates)
+        .prop_map(|(head, body, aggregates)| {
+            let aggregates = head.1
+                .iter()
+                .zip(aggregates)
+                .enumerate()
+                .filter_map(|(i, (arg, aggregate))| match *arg {
+                    Term::Var(..) => aggregate.map(|a| (i, a)),
+                    Term::Value(..) => None,
+                })
+                .collect();
+            Clause(head, body, aggregates, None)
+        })
+        .boxed()
+}
+
+fn declaration() -> BoxedStrategy<Declaration> {
+    let ty = prop_oneof![
+        Just(Type::Bool),
+        Just(Type::Int),
+        Just(Type::Float),
+        Just(Type::Str),
+        Just(Type::Symbol),
+    ];
+    let column = ("[a-z][a-z0-9_-]{0,6}", ty)
+        .prop_map(|(name, ty)| (Name::new(&name).unwrap(), ty));
+    (name(), prop::collection::vec(column, 0..3))
+        .prop_map(|(pred, columns)| Declaration(pred, columns, None))
+        .boxed()
+}
+
+fn statement() -> BoxedStrategy<Statement> {
+    prop_oneof![
+        declaration().prop_map(Statement::Declaration),
+        clause().prop_map(Statement::Assertion),
+        clause().prop_map(Statement::Retraction),
+        prop::collection::vec(goal(), 1..3).prop_map(Statement::Query),
+    ].boxed()
+}
+
+proptest! {
+    #[test]
+    fn round_trip(ref stmts in prop::collection::vec(statement(), 0..4)) {
+        let program = Program(stmts.clone());
+        let src = program.to_string();
+        match src.parse::<Program>() {
+            Ok(parsed) => prop_assert_eq!(parsed, program),
+            Err(err) => prop_assert!(false, "{}\n{}", src, err),
+        }
+    }
+}
+
+#[test]
+fn round_trip_large_floats() {
+    let src = "p(1.7976931348623157e308, -1e308).";
+    let program: Program = src.parse().unwrap();
+    assert_eq!(program.to_string().parse::<Program>().unwrap(), program);
+    assert!("p(1e999).".parse::<Program>().is_err());
+}
@@ -10,7 +10,7 @@ pub fn as_amb<'a, F, T>(
     func: F,
 ) -> Result<T, Error<'a, Rule>>
 where
-    F: Fn(Pairs<'a, Rule>, Option<Pairs<'a, Rule>>) -> Result<T, Error<'a, Rule>>,
+    F: Fn(Pair<'a, Rule>, Option<Pair<'a, Rule>>) -> Result<T, Error<'a, Rule>>,
 {
     as_ht(pairs, a_rule, |a, mut pairs| {
         if let Some(b) = pairs.next() {
@@ -21,7 +21,7 @@ where
                     pos: token.into_span().start_pos(),
                 })
             } else if b.as_rule() == b_rule {
-                func(a, Some(b.into_inner()))
+                func(a, Some(b))
             } else {
                 Err(Error::ParsingError {
                     positives: vec![b_rule],
@@ -41,11 +41,11 @@ fn as_ht<'a, F, T>(
     func: F,
 ) -> Result<T, Error<'a, Rule>>
 where
-    F: Fn(Pairs<'a, Rule>, Pairs<'a, Rule>) -> Result<T, Error<'a, Rule>>,
+    F: Fn(Pair<'a, Rule>, Pairs<'a, Rule>) -> Result<T, Error<'a, Rule>>,
 {
     if let Some(head) = pairs.next() {
         if head.as_rule() == head_rule {
-            func(head.into_inner(), pairs)
+            func(head, pairs)
         } else {
             Err(Error::ParsingError {
                 positives: vec![head_rule],
@@ -88,10 +88,21 @@ pub fn as_one<'a, F, T>(
 ) -> Result<T, Error<'a, Rule>>
 where
     F: Fn(Pairs<'a, Rule>) -> Result<T, Error<'a, Rule>>,
+{
+    as_one_token(pairs, rule, |token| func(token.into_inner()))
+}
+
+pub fn as_one_token<'a, F, T>(
+    pairs: Pairs<'a, Rule>,
+    rule: Rule,
+    func: F,
+) -> Result<T, Error<'a, Rule>>
+where
+    F: Fn(Pair<'a, Rule>) -> Result<T, Error<'a, Rule>>,
 {
     as_one_any(pairs, rule, |token| {
         if token.as_rule() == rule {
-            func(token.into_inner())
+            func(token)
         } else {
             Err(Error::ParsingError {
                 positives: vec![rule],
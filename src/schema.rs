@@ -0,0 +1,483 @@
+//! Checking clauses against the declarations of the predicates they use, and
+//! inferring the types of undeclared predicates.
+
+use std::collections::{BTreeMap, HashMap};
+
+use sparkly::Sparkly;
+
+use {Aggregate, Clause, CmpOp, Declaration, Error, ErrorKind, Expr, Goal,
+     Literal, Name, Program, Result, Span, Statement, Term, Type, Variable};
+
+/// The type of each column of each predicate in a program, by name and
+/// arity, where it is known.
+pub type Types = BTreeMap<(Name, usize), Vec<Option<Type>>>;
+
+/// The declarations made so far, by predicate name. A predicate with no
+/// declaration may be used with any arity and any types.
+#[derive(Clone, Debug, Default)]
+pub struct Schema {
+    decls: HashMap<Name, Declaration>,
+}
+
+impl Schema {
+    /// Adds a declaration. A predicate may be declared again, but only in
+    /// the same way.
+    pub fn declare(&mut self, decl: Declaration) -> Result<()> {
+        if let Some(old) = self.decls.get(&decl.0) {
+            if *old != decl {
+                let err = ErrorKind::Redeclared(old.clone(), decl);
+                return Err(err.into());
+            }
+        }
+        self.decls.insert(decl.0.clone(), decl);
+        Ok(())
+    }
+
+    /// Returns the declaration of a predicate, if it has one.
+    pub fn declaration(&self, name: &Name) -> Option<&Declaration> {
+        self.decls.get(name)
+    }
+
+    /// Checks that every literal in a clause has the arity of its
+    /// predicate's declaration, and a constant of the declared type in each
+    /// column. A variable in the head of a rule must also have the same type
+    /// as each column it appears in in the body.
+    pub fn check(&self, clause: &Clause) -> Result<()> {
+        let literals = clause.body().iter().filter_map(Goal::literal);
+        for literal in Some(clause.head()).into_iter().chain(literals) {
+            let decl = match self.decls.get(&literal.0) {
+                Some(decl) => decl,
+                None => continue,
+            };
+            if decl.1.len() != literal.1.len() {
+                let (decl, clause) = (decl.clone(), clause.clone());
+                return Err(ErrorKind::ArityMismatch(decl, clause).into());
+            }
+            for (i, arg) in literal.1.iter().enumerate() {
+                if let Term::Value(ref value, _) = *arg {
+                    check_column(decl, i, value.ty(), clause)?;
+                }
+            }
+        }
+
+        let decl = match self.decls.get(&clause.head().0) {
+            Some(decl) => decl,
+            None => return Ok(()),
+        };
+        for (i, arg) in clause.head().1.iter().enumerate() {
+            // An aggregate's result need not have the type of its argument.
+            if clause.2.iter().any(|&(j, _)| i == j) {
+                continue;
+            }
+            let var = match *arg {
+                Term::Var(ref var, _) => var,
+                Term::Value(..) => continue,
+            };
+            for goal in clause.body() {
+                let literal = match *goal {
+                    Goal::Literal(ref l) => l,
+                    Goal::Not(_) | Goal::Compare(..) => continue,
+                };
+                for (j, arg) in literal.1.iter().enumerate() {
+                    if *arg != Term::Var(var.clone(), None) {
+                        continue;
+                    }
+                    if let Some(ty) = self.column_type(literal, j) {
+                        check_column(decl, i, ty, clause)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the declared type of a column of a literal's predicate.
+    fn column_type(&self, literal: &Literal, i: usize) -> Option<Type> {
+        let decl = self.decls.get(&literal.0)?;
+        decl.1.get(i).map(|&(_, ty)| ty)
+    }
+}
+
+/// Checks that a value of the given type belongs in a column.
+fn check_column(
+    decl: &Declaration,
+    i: usize,
+    ty: Type,
+    clause: &Clause,
+) -> Result<()> {
+    if decl.1[i].1 == ty {
+        Ok(())
+    } else {
+        let (decl, clause) = (decl.clone(), clause.clone());
+        Err(ErrorKind::TypeMismatch(decl, clause, i, ty).into())
+    }
+}
+
+/// Infers the type of each column of each predicate in a program, from its
+/// declarations, the constants in its clauses, and the variables its rules
+/// pass from one column to another. Also returns an error for each use of a
+/// value as two different types, or of a non-number in arithmetic.
+pub fn infer_types(program: &Program) -> (Types, Vec<Error>) {
+    let (types, errors) = infer(program);
+    (types, errors.into_iter().map(|(_, err)| err).collect())
+}
+
+/// Infers types as `infer_types` does, returning where in the source each
+/// error is along with it.
+pub fn infer(program: &Program) -> (Types, Vec<(Option<Span>, Error)>) {
+    let mut schema = Schema::default();
+    let mut unifier = Unifier::default();
+    let mut errors = Vec::new();
+    for stmt in &program.0 {
+        if let Statement::Declaration(ref decl) = *stmt {
+            if let Err(err) = schema.declare(decl.clone()) {
+                errors.push((decl.2, err));
+                continue;
+            }
+            for (i, &(_, ty)) in decl.1.iter().enumerate() {
+                let column = unifier.slot(Slot::Column(decl.pred(), i));
+                unifier.assign(column, ty).expect("declared twice");
+            }
+        }
+    }
+
+    // Uses of a variable as a number are checked once its type is known.
+    let mut numbers = Vec::new();
+    for (n, stmt) in program.0.iter().enumerate() {
+        let clause = match *stmt {
+            Statement::Assertion(ref clause) => clause,
+            _ => continue,
+        };
+        let mut conflict = |term: &Term, what: String, (expected, found)| {
+            let clause = clause.clone();
+            let err = ErrorKind::TypeConflict(clause, what, expected, found);
+            errors.push((term.span(), err.into()));
+        };
+
+        let literals = clause.body().iter().filter_map(|goal| match *goal {
+            Goal::Literal(ref l) | Goal::Not(ref l) => Some(l),
+            Goal::Compare(..) => None,
+        });
+        let literals = Some(clause.head()).into_iter().chain(literals);
+        for (i, literal) in literals.enumerate() {
+            for (j, arg) in literal.1.iter().enumerate() {
+                let aggregate = match i {
+                    0 => clause.2.iter().find(|&&(k, _)| j == k),
+                    _ => None,
+                };
+                let column = unifier.slot(Slot::Column(literal.signature(), j));
+                match (arg, aggregate) {
+                    (&Term::Var(ref var, _), None) => {
+                        let var = unifier.slot(Slot::Var(n, var.clone()));
+                        if let Err(types) = unifier.union(column, var) {
+                            conflict(arg, arg.to_string(), types);
+                        }
+                    }
+                    (&Term::Value(ref value, _), None) => {
+                        if let Err(ty) = unifier.assign(column, value.ty()) {
+                            conflict(arg, arg.to_string(), (ty, value.ty()));
+                        }
+                    }
+                    (_, Some(&(_, aggregate))) => {
+                        let what = format!(
+                            "{}<{}>",
+                            aggregate.to_doc().display(),
+                            arg
+                        );
+                        let ty = match aggregate {
+                            Aggregate::Count => Type::Int,
+                            Aggregate::Avg => Type::Float,
+                            _ => {
+                                let var = match *arg {
+                                    Term::Var(ref var, _) => var.clone(),
+                                    Term::Value(..) => unreachable!(),
+                                };
+                                let var = unifier.slot(Slot::Var(n, var));
+                                if let Err(types) = unifier.union(column, var)
+                                {
+                                    conflict(arg, what, types);
+                                }
+                                numbers.push((clause, n, arg));
+                                continue;
+                            }
+                        };
+                        if let Err(expected) = unifier.assign(column, ty) {
+                            conflict(arg, what, (expected, ty));
+                        }
+                        if aggregate == Aggregate::Avg {
+                            numbers.push((clause, n, arg));
+                        }
+                    }
+                }
+            }
+        }
+
+        for goal in clause.body() {
+            let (l, op, r) = match *goal {
+                Goal::Compare(ref l, op, ref r) => (l, op, r),
+                Goal::Literal(_) | Goal::Not(_) => continue,
+            };
+            match (l, op, r) {
+                (&Expr::Term(ref l), CmpOp::Eq, &Expr::Term(ref r))
+                | (&Expr::Term(ref l), CmpOp::Ne, &Expr::Term(ref r)) => {
+                    let (term, result) = match (l, r) {
+                        (&Term::Var(ref lv, _), &Term::Var(ref rv, _)) => {
+                            let lv = unifier.slot(Slot::Var(n, lv.clone()));
+                            let rv = unifier.slot(Slot::Var(n, rv.clone()));
+                            (r, unifier.union(lv, rv))
+                        }
+                        (&Term::Var(ref var, _), &Term::Value(ref v, _)) => {
+                            let var = unifier.slot(Slot::Var(n, var.clone()));
+                            let result = unifier
+                                .assign(var, v.ty())
+                                .map_err(|ty| (ty, v.ty()));
+                            (r, result)
+                        }
+                        (&Term::Value(ref v, _), &Term::Var(ref var, _)) => {
+                            let var = unifier.slot(Slot::Var(n, var.clone()));
+                            let result = unifier
+                                .assign(var, v.ty())
+                                .map_err(|ty| (ty, v.ty()));
+                            (l, result)
+                        }
+                        (&Term::Value(..), &Term::Value(..)) => continue,
+                    };
+                    if let Err(types) = result {
+                        conflict(term, term.to_string(), types);
+                    }
+                }
+                _ => {
+                    let terms = l.terms().into_iter().chain(r.terms());
+                    numbers.extend(terms.map(|t| (clause, n, t)));
+                }
+            }
+        }
+    }
+
+    for (clause, n, term) in numbers {
+        let ty = match *term {
+            Term::Var(ref var, _) => {
+                let var = unifier.slot(Slot::Var(n, var.clone()));
+                match unifier.ty(var) {
+                    Some(ty) => ty,
+                    None => continue,
+                }
+            }
+            Term::Value(ref value, _) => value.ty(),
+        };
+        if ty != Type::Int && ty != Type::Float {
+            let (clause, what) = (clause.clone(), term.to_string());
+            let err = ErrorKind::NotNumeric(clause, what, ty);
+            errors.push((term.span(), err.into()));
+        }
+    }
+
+    let mut types = Types::new();
+    let columns = unifier.slots.keys().cloned().collect::<Vec<_>>();
+    for slot in columns {
+        if let Slot::Column(pred, i) = slot.clone() {
+            let ty = unifier.slot(slot);
+            let ty = unifier.ty(ty);
+            let arity = pred.1;
+            types.entry(pred).or_insert_with(|| vec![None; arity])[i] = ty;
+        }
+    }
+    (types, errors)
+}
+
+/// Somewhere a value can be put: a column of a predicate, or a variable in
+/// the nth statement of a program.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum Slot {
+    Column((Name, usize), usize),
+    Var(usize, Variable),
+}
+
+/// Sets of slots that must hold values of the same type, kept with
+/// union-find, along with the type of each set, where it is known.
+#[derive(Debug, Default)]
+struct Unifier {
+    slots: HashMap<Slot, usize>,
+    parents: Vec<usize>,
+    types: Vec<Option<Type>>,
+}
+
+impl Unifier {
+    /// Returns the index of a slot, adding it in a set of its own if it is
+    /// new.
+    fn slot(&mut self, slot: Slot) -> usize {
+        let next = self.parents.len();
+        let i = *self.slots.entry(slot).or_insert(next);
+        if i == next {
+            self.parents.push(i);
+            self.types.push(None);
+        }
+        i
+    }
+
+    /// Returns the index of the set a slot is in.
+    fn find(&mut self, i: usize) -> usize {
+        let parent = self.parents[i];
+        if parent == i {
+            return i;
+        }
+        let root = self.find(parent);
+        self.parents[i] = root;
+        root
+    }
+
+    /// Returns the type of the set a slot is in.
+    fn ty(&mut self, i: usize) -> Option<Type> {
+        let i = self.find(i);
+        self.types[i]
+    }
+
+    /// Gives the set a slot is in a type. If it already has another type,
+    /// returns that type.
+    fn assign(
+        &mut self,
+        i: usize,
+        ty: Type,
+    ) -> ::std::result::Result<(), Type> {
+        let i = self.find(i);
+        match self.types[i] {
+            Some(old) if old != ty => Err(old),
+            _ => {
+                self.types[i] = Some(ty);
+                Ok(())
+            }
+        }
+    }
+
+    /// Merges the sets two slots are in. If they have different types,
+    /// returns them instead, so that one mistake doesn't spread to every
+    /// slot in either set.
+    fn union(
+        &mut self,
+        a: usize,
+        b: usize,
+    ) -> ::std::result::Result<(), (Type, Type)> {
+        let (a, b) = (self.find(a), self.find(b));
+        match (self.types[a], self.types[b]) {
+            (Some(l), Some(r)) if l != r => return Err((l, r)),
+            (None, r) => self.types[a] = r,
+            _ => {}
+        }
+        self.parents[b] = a;
+        Ok(())
+    }
+}
+
+#[test]
+fn check() {
+    use Statement;
+
+    let mut schema = Schema::default();
+    let decls = concat!(
+        ".decl edge(src: symbol, dst: symbol)\n",
+        ".decl weight(src: symbol, w: int)\n",
+        ".decl degree(node: symbol, n: int)\n",
+    );
+    for stmt in decls.parse::<::Program>().unwrap().0 {
+        match stmt {
+            Statement::Declaration(decl) => schema.declare(decl).unwrap(),
+            _ => unreachable!(),
+        }
+    }
+    let check = |src: &str| -> Result<()> {
+        match src.parse().unwrap() {
+            Statement::Assertion(clause) => schema.check(&clause),
+            _ => unreachable!(),
+        }
+    };
+    let kind = |result: Result<()>| match *result.unwrap_err().kind() {
+        ErrorKind::ArityMismatch(..) => "arity",
+        ErrorKind::TypeMismatch(_, _, i, _) => ["0", "1"][i],
+        ref err => panic!("unexpected error {}", err),
+    };
+
+    check("edge(a, b).").unwrap();
+    check("weight(a, 3) :- edge(a, X).").unwrap();
+    check("degree(X, count<Y>) :- edge(X, Y).").unwrap();
+    check("other(X, 1.5) :- weight(X, N).").unwrap();
+    assert_eq!(kind(check("edge(a).")), "arity");
+    assert_eq!(kind(check("p :- edge(a, b, c).")), "arity");
+    assert_eq!(kind(check("edge(a, 1).")), "1");
+    assert_eq!(kind(check("p :- weight(\"a\", N).")), "0");
+    assert_eq!(kind(check("edge(X, N) :- weight(X, N).")), "1");
+    assert_eq!(kind(check("degree(N, X) :- weight(X, N).")), "0");
+
+    let decl = |src: &str| match src.parse().unwrap() {
+        Statement::Declaration(decl) => decl,
+        _ => unreachable!(),
+    };
+    schema.declare(decl(".decl edge(src: symbol, dst: symbol)")).unwrap();
+    match *schema
+        .declare(decl(".decl edge(src: symbol, dst: int)"))
+        .unwrap_err()
+        .kind()
+    {
+        ErrorKind::Redeclared(..) => {}
+        ref err => panic!("unexpected error {}", err),
+    }
+}
+
+#[test]
+fn inference() {
+    let program: Program = concat!(
+        ".decl edge(src: symbol, dst: symbol)\n",
+        "path(X, Y) :- edge(X, Y).\n",
+        "path(X, Y) :- edge(X, Z), path(Z, Y).\n",
+        "weight(a, 3). weight(b, 2.5).\n",
+        "degree(X, count<Y>) :- path(X, Y).\n",
+        "heavy(X) :- weight(X, W), W > 2, not light(W).\n",
+        "bad(N) :- path(X, Y), weight(N, Y).\n",
+        "next(X, Y) :- path(X, Z), Y = Z + 1.\n",
+    ).parse()
+        .unwrap();
+    let (types, errors) = program.infer_types();
+
+    let types = types
+        .into_iter()
+        .map(|((name, _), columns)| {
+            let columns = columns
+                .iter()
+                .map(|ty| ty.map_or("_", Type::name))
+                .collect::<Vec<_>>();
+            format!("{}({})", name, columns.join(", "))
+        })
+        .collect::<::std::collections::BTreeSet<_>>();
+    let expected = vec![
+        "bad(symbol)",
+        "degree(symbol, int)",
+        "edge(symbol, symbol)",
+        "heavy(symbol)",
+        "light(int)",
+        "next(symbol, _)",
+        "path(symbol, symbol)",
+        "weight(symbol, int)",
+    ];
+    let expected = expected.into_iter().map(String::from).collect();
+    assert_eq!(types, expected);
+
+    let errors = errors
+        .iter()
+        .map(|err| match *err.kind() {
+            ErrorKind::TypeConflict(_, ref what, expected, found) => {
+                format!("{}: {} not {}", what, found, expected)
+            }
+            ErrorKind::NotNumeric(_, ref what, found) => {
+                format!("{}: {} not a number", what, found)
+            }
+            ref err => panic!("unexpected error {}", err),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        errors,
+        vec![
+            "2.5: float not int",
+            "Y: symbol not int",
+            "Z: symbol not a number",
+        ]
+    );
+}
@@ -0,0 +1,252 @@
+use std::collections::HashSet;
+
+use {Clause, Goal, Name, Result, Value};
+
+use super::eval::{eval_rule, eval_stratum, merge, Db, Relation, Relations};
+
+/// Updates a model of the given rules after some base facts were added and
+/// others removed, so that it is the model of the rules over `facts`, the
+/// new base facts, without recomputing it from scratch. The added and removed
+/// facts must be those that were not, and were, among the base facts before.
+///
+/// Each stratum is updated with the delete and rederive (DRed) algorithm:
+/// every tuple with a derivation using a removed tuple is deleted, those that
+/// can still be derived another way are put back, and the additions are then
+/// propagated as in semi-naive evaluation. A stratum that uses a changed
+/// relation negatively is recomputed instead, since an addition there can
+/// remove tuples and a removal can add them, as is one with an aggregate.
+///
+/// Other than in recomputed strata, the work done is proportional to the
+/// changes rather than to the size of the model: where the old model is
+/// needed, it is read through the changes made so far.
+pub fn update(
+    model: &mut Relations,
+    facts: &Relations,
+    strata: &[Vec<&Clause>],
+    base_added: &Relations,
+    base_removed: &Relations,
+) -> Result<()> {
+    let derived = strata
+        .iter()
+        .flat_map(|s| s.iter().map(|r| r.pred()))
+        .collect::<HashSet<_>>();
+
+    // The net changes to the model so far, which later strata react to.
+    // Relations with no rules change exactly as their base facts do.
+    let base = |changes: &Relations| -> Relations {
+        changes
+            .iter()
+            .filter(|&(pred, _)| !derived.contains(pred))
+            .map(|(pred, tuples)| (pred.clone(), tuples.clone()))
+            .collect()
+    };
+    let mut added = base(base_added);
+    let mut removed = base(base_removed);
+
+    for stratum in strata {
+        let preds = stratum.iter().map(|r| r.pred()).collect::<HashSet<_>>();
+        let changed = |pred: &(Name, usize)| {
+            added.contains_key(pred) || removed.contains_key(pred)
+        };
+        let affected = preds.iter().any(|p| {
+            base_added.contains_key(p) || base_removed.contains_key(p)
+        }) || stratum.iter().any(|rule| {
+            rule.body()
+                .iter()
+                .filter_map(Goal::literal)
+                .any(|l| changed(&l.signature()))
+        });
+        if !affected {
+            continue;
+        }
+
+        // An aggregate's tuples can't be deleted and rederived one at a
+        // time, so a stratum with an aggregate rule is always recomputed.
+        let nonmonotonic = stratum.iter().any(|rule| {
+            rule.is_aggregate() || rule.body().iter().any(|goal| match *goal {
+                Goal::Not(ref l) => changed(&l.signature()),
+                Goal::Literal(..) | Goal::Compare(..) => false,
+            })
+        });
+        if nonmonotonic {
+            let mut old = Relations::new();
+            for pred in &preds {
+                if let Some(tuples) = model.remove(pred) {
+                    old.insert(pred.clone(), tuples);
+                }
+            }
+            eval_stratum(facts, model, stratum)?;
+            for pred in &preds {
+                let empty = Relation::new();
+                let before = old.get(pred).unwrap_or(&empty);
+                let after = model.get(pred).unwrap_or(&empty);
+                for tuple in before.difference(after) {
+                    insert(&mut removed, pred, tuple.clone());
+                }
+                for tuple in after.difference(before) {
+                    insert(&mut added, pred, tuple.clone());
+                }
+            }
+            continue;
+        }
+
+        // Delete every tuple that has a derivation in the old model using a
+        // removed tuple, or that was itself a removed base fact. This
+        // stratum's relations are still as they were, so the old model is
+        // read as the current one with the earlier changes undone.
+        let mut deleted = Relations::new();
+        let mut delta = removed.clone();
+        for pred in &preds {
+            for tuple in base_removed.get(pred).into_iter().flatten() {
+                insert(&mut deleted, pred, tuple.clone());
+                insert(&mut delta, pred, tuple.clone());
+            }
+        }
+        while !delta.is_empty() {
+            let mut new = Relations::new();
+            for (rule, i) in delta_positions(stratum, &delta) {
+                let old = Db {
+                    undo: Some((&added, &removed)),
+                    ..Db::new(facts, model)
+                };
+                for tuple in eval_rule(rule, old, Some((i, &delta)))? {
+                    let pred = rule.pred();
+                    if contains(model, &pred, &tuple)
+                        && !contains(&deleted, &pred, &tuple)
+                    {
+                        insert(&mut deleted, &pred, tuple.clone());
+                        insert(&mut new, &pred, tuple);
+                    }
+                }
+            }
+            delta = new;
+        }
+        for (pred, tuples) in &deleted {
+            if let Some(relation) = model.get_mut(pred) {
+                for tuple in tuples {
+                    relation.remove(tuple);
+                }
+            }
+        }
+
+        // Put back the deleted tuples that are still base facts, or that
+        // still have a derivation from what remains, then propagate them
+        // along with every addition. A tuple is new to the model if it is
+        // inserted without having been deleted.
+        let mut delta = added.clone();
+        for (pred, tuples) in &deleted {
+            let rules = stratum.iter().filter(|r| r.pred() == *pred);
+            let rules = rules.cloned().collect::<Vec<_>>();
+            for tuple in tuples {
+                if contains(facts, pred, tuple)
+                    || derivable(&rules, Db::new(facts, model), tuple)?
+                {
+                    insert(&mut delta, pred, tuple.clone());
+                }
+            }
+        }
+        for pred in &preds {
+            for tuple in base_added.get(pred).into_iter().flatten() {
+                insert(&mut delta, pred, tuple.clone());
+            }
+        }
+        // The changes to earlier strata are already in the model, as those to
+        // relations without rules are in the facts.
+        let mut inserted = Relations::new();
+        for (pred, tuples) in &delta {
+            if !preds.contains(pred) {
+                continue;
+            }
+            for tuple in tuples {
+                if !contains(model, pred, tuple)
+                    && !contains(&deleted, pred, tuple)
+                {
+                    insert(&mut inserted, pred, tuple.clone());
+                }
+                insert(model, pred, tuple.clone());
+            }
+        }
+        while !delta.is_empty() {
+            let mut new = Relations::new();
+            for (rule, i) in delta_positions(stratum, &delta) {
+                let db = Db::new(facts, model);
+                for tuple in eval_rule(rule, db, Some((i, &delta)))? {
+                    let pred = rule.pred();
+                    if !contains(model, &pred, &tuple) {
+                        insert(&mut new, &pred, tuple);
+                    }
+                }
+            }
+            merge(model, &new);
+            for (pred, tuples) in &new {
+                for tuple in tuples {
+                    if !contains(&deleted, pred, tuple) {
+                        insert(&mut inserted, pred, tuple.clone());
+                    }
+                }
+            }
+            delta = new;
+        }
+
+        for pred in &preds {
+            for tuple in deleted.get(pred).into_iter().flatten() {
+                if !contains(model, pred, tuple) {
+                    insert(&mut removed, pred, tuple.clone());
+                }
+            }
+            for tuple in inserted.get(pred).into_iter().flatten() {
+                insert(&mut added, pred, tuple.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns each rule in the stratum, with the index of each positive body
+/// literal in it whose relation has tuples in the delta.
+fn delta_positions<'a>(
+    stratum: &[&'a Clause],
+    delta: &Relations,
+) -> Vec<(&'a Clause, usize)> {
+    let mut positions = Vec::new();
+    for &rule in stratum {
+        for (i, goal) in rule.body().iter().enumerate() {
+            if let Goal::Literal(ref l) = *goal {
+                if delta.contains_key(&l.signature()) {
+                    positions.push((rule, i));
+                }
+            }
+        }
+    }
+    positions
+}
+
+/// Returns whether any of the rules, none of which aggregates, derives the
+/// tuple from the database.
+fn derivable(rules: &[&Clause], db: Db, tuple: &[Value]) -> Result<bool> {
+    for rule in rules {
+        let bindings = match rule.head().try_instantiate_fact(tuple) {
+            Some(bindings) => bindings,
+            None => continue,
+        };
+        let head = rule.head().substitute(&bindings);
+        let body = rule.body().iter().map(|g| g.substitute(&bindings));
+        let rule = Clause(head, body.collect(), vec![], rule.3);
+        if !eval_rule(&rule, db, None)?.is_empty() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Returns whether a relation in a database has a tuple.
+fn contains(db: &Relations, pred: &(Name, usize), tuple: &[Value]) -> bool {
+    db.get(pred).map_or(false, |r| r.contains(tuple))
+}
+
+/// Adds a tuple to a relation in a database.
+fn insert(db: &mut Relations, pred: &(Name, usize), tuple: Vec<Value>) {
+    db.entry(pred.clone())
+        .or_insert_with(Relation::new)
+        .insert(tuple);
+}
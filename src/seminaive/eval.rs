@@ -0,0 +1,209 @@
+use std::collections::{HashMap, HashSet};
+
+use {Bindings, Clause, Goal, Name, Result, Value};
+
+/// The tuples in a single relation.
+pub type Relation = HashSet<Vec<Value>>;
+
+/// A database of relations, indexed by predicate name and arity.
+pub type Relations = HashMap<(Name, usize), Relation>;
+
+/// The relations that rules are evaluated against. A model holds only the
+/// relations its rules derive, each including the facts about it, and every
+/// other relation is read from the base facts, so that those are not copied
+/// into each model.
+///
+/// While a model is being updated, `undo` holds the tuples added to and
+/// removed from it so far, and the relations are read as they were before
+/// those changes.
+#[derive(Clone, Copy)]
+pub struct Db<'a> {
+    pub facts: &'a Relations,
+    pub model: &'a Relations,
+    pub undo: Option<(&'a Relations, &'a Relations)>,
+}
+
+impl<'a> Db<'a> {
+    /// Creates a `Db` reading a model as it is.
+    pub fn new(facts: &'a Relations, model: &'a Relations) -> Db<'a> {
+        Db {
+            facts,
+            model,
+            undo: None,
+        }
+    }
+
+    /// Returns the tuples of a relation, or `None` if it has never had any.
+    pub fn tuples(&self, pred: &(Name, usize)) -> Option<Vec<&'a Vec<Value>>> {
+        let relation = self.model.get(pred).or_else(|| self.facts.get(pred));
+        let (added, removed) = match self.undo {
+            Some((added, removed)) => (added.get(pred), removed.get(pred)),
+            None => (None, None),
+        };
+        if relation.is_none() && removed.is_none() {
+            return None;
+        }
+        let current = relation
+            .into_iter()
+            .flat_map(|r| r.iter())
+            .filter(|tuple| added.map_or(true, |a| !a.contains(*tuple)));
+        Some(current.chain(removed.into_iter().flatten()).collect())
+    }
+}
+
+/// Computes the least fixpoint of the given rules over the given facts,
+/// returning the relations the rules derive. The rules must be safe, and
+/// split into strata by `stratify`.
+pub fn fixpoint(
+    facts: &Relations,
+    strata: &[Vec<&Clause>],
+) -> Result<Relations> {
+    let mut model = Relations::new();
+    for stratum in strata {
+        eval_stratum(facts, &mut model, stratum)?;
+    }
+    Ok(model)
+}
+
+/// Evaluates a single stratum to its fixpoint, adding the derived tuples to
+/// the model. A relation of the stratum that the model doesn't hold yet
+/// starts out with its facts.
+pub fn eval_stratum(
+    facts: &Relations,
+    model: &mut Relations,
+    rules: &[&Clause],
+) -> Result<()> {
+    let preds = rules.iter().map(|r| r.pred()).collect::<HashSet<_>>();
+    for pred in &preds {
+        if !model.contains_key(pred) {
+            let tuples = facts.get(pred).cloned().unwrap_or_default();
+            model.insert(pred.clone(), tuples);
+        }
+    }
+
+    // The first round has no delta to work from, so every rule is evaluated
+    // against the whole database.
+    let mut delta = Relations::new();
+    for rule in rules {
+        for tuple in eval_rule(rule, Db::new(facts, model), None)? {
+            insert_new(model, &mut delta, rule.pred(), tuple);
+        }
+    }
+
+    while !delta.is_empty() {
+        merge(model, &delta);
+
+        // Only rules with a positive body literal from this stratum can
+        // derive anything new, and only by using a tuple derived last round.
+        let mut new = Relations::new();
+        for rule in rules {
+            for (i, goal) in rule.body().iter().enumerate() {
+                let literal = match *goal {
+                    Goal::Literal(ref l) => l,
+                    Goal::Not(_) | Goal::Compare(..) => continue,
+                };
+                if !preds.contains(&literal.signature()) {
+                    continue;
+                }
+                let db = Db::new(facts, model);
+                for tuple in eval_rule(rule, db, Some((i, &delta)))? {
+                    insert_new(model, &mut new, rule.pred(), tuple);
+                }
+            }
+        }
+        delta = new;
+    }
+    Ok(())
+}
+
+/// Evaluates a rule, returning the head tuples it derives. If `delta` is
+/// given, the body literal at that index is matched against the delta
+/// relations instead of the database. Negated literals are checked against
+/// the database, which must already hold every tuple of their relations, as
+/// must every body literal of a rule with aggregates.
+pub fn eval_rule(
+    rule: &Clause,
+    db: Db,
+    delta: Option<(usize, &Relations)>,
+) -> Result<Vec<Vec<Value>>> {
+    let mut bindings = vec![Bindings::new()];
+    for (i, goal) in rule.body().iter().enumerate() {
+        let literal = match *goal {
+            Goal::Literal(ref l) => l,
+            Goal::Not(ref l) => {
+                let tuples = db.tuples(&l.signature()).unwrap_or_default();
+                bindings.retain(|b| {
+                    let l = l.substitute(b);
+                    !tuples
+                        .iter()
+                        .any(|tuple| l.try_instantiate_fact(tuple).is_some())
+                });
+                continue;
+            }
+            Goal::Compare(ref l, op, ref r) => {
+                let mut next = Vec::new();
+                for mut b in bindings {
+                    let (l, r) = (l.substitute(&b), r.substitute(&b));
+                    if let Some(new) = op.eval(&l, &r)? {
+                        b.extend(new);
+                        next.push(b);
+                    }
+                }
+                bindings = next;
+                continue;
+            }
+        };
+        let tuples = match delta {
+            Some((j, delta)) if i == j => delta
+                .get(&literal.signature())
+                .map(|r| r.iter().collect()),
+            _ => db.tuples(&literal.signature()),
+        };
+        let tuples: Vec<&Vec<Value>> = match tuples {
+            Some(tuples) => tuples,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut next = Vec::new();
+        for b in bindings {
+            let literal = literal.substitute(&b);
+            for &tuple in &tuples {
+                if let Some(new) = literal.try_instantiate_fact(tuple) {
+                    let mut b = b.clone();
+                    b.extend(new);
+                    next.push(b);
+                }
+            }
+        }
+        bindings = next;
+    }
+
+    if rule.is_aggregate() {
+        return rule.aggregate(bindings);
+    }
+    Ok(bindings
+        .into_iter()
+        .filter_map(|b| rule.head().substitute(&b).as_ground())
+        .collect())
+}
+
+/// Inserts the tuple into `new` if it is not already in `model`.
+fn insert_new(
+    model: &Relations,
+    new: &mut Relations,
+    pred: (Name, usize),
+    tuple: Vec<Value>,
+) {
+    if model.get(&pred).map(|r| r.contains(&tuple)) != Some(true) {
+        new.entry(pred).or_insert_with(Relation::new).insert(tuple);
+    }
+}
+
+/// Adds every tuple in `delta` to `db`.
+pub fn merge(db: &mut Relations, delta: &Relations) {
+    for (pred, tuples) in delta {
+        db.entry(pred.clone())
+            .or_insert_with(Relation::new)
+            .extend(tuples.iter().cloned());
+    }
+}
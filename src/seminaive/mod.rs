@@ -0,0 +1,462 @@
+//! A bottom-up interpreter using semi-naive evaluation.
+
+mod dred;
+mod eval;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::iter::once;
+
+use {Bindings, Clause, Declaration, Interpeter, Literal, Name, Result, Term};
+use magic::rewrite;
+use schema::Schema;
+use stratify::stratify;
+use util::{arity, check_assertion, check_declaration, retract};
+
+pub use self::eval::{Relation, Relations};
+use self::dred::update;
+use self::eval::{fixpoint, Db};
+
+/// A bottom-up interpreter, which computes every fact derivable from the
+/// program before answering a query.
+///
+/// The derived facts are computed with semi-naive evaluation: each stratum of
+/// rules is iterated to a fixpoint, but each round only considers derivations
+/// that use a fact derived in the previous round. Unlike the top-down
+/// `NaiveInterpreter`, this terminates on left-recursive rules such as
+/// `edge(X, Y) :- edge(Y, X)`.
+///
+/// Once computed, the model is kept up to date as facts are asserted and
+/// retracted, rather than being recomputed; changing a rule discards it.
+/// Until the model has been computed, a query with a bound argument is
+/// instead answered from the model of the magic-sets rewriting of the program
+/// for it, which is kept and updated in the same way. Only the most recently
+/// used of these are kept.
+///
+/// The models hold only the relations derived by rules; the others are read
+/// from the base facts.
+#[derive(Debug)]
+pub struct SemiNaiveInterpreter {
+    facts: Relations,
+    rules: HashMap<(Name, usize), Vec<Clause>>,
+    strata: Vec<Vec<Clause>>,
+    model: RefCell<Option<Relations>>,
+    magic: RefCell<Vec<MagicModel>>,
+    schema: Schema,
+}
+
+/// The most models of magic-sets rewritings that are kept at once. Each is
+/// updated whenever the base facts change, so the least recently used is
+/// discarded to make room for another.
+const MAGIC_MODELS: usize = 16;
+
+/// The model of the magic-sets rewriting of the program for a query, and the
+/// strata of the rewritten rules. They are found by the seed fact of the
+/// rewriting, which is the same for every query with the same bound
+/// arguments.
+#[derive(Debug)]
+struct MagicModel {
+    seed: Literal,
+    strata: Vec<Vec<Clause>>,
+    model: Relations,
+}
+
+impl Default for SemiNaiveInterpreter {
+    fn default() -> SemiNaiveInterpreter {
+        SemiNaiveInterpreter::new()
+    }
+}
+
+impl SemiNaiveInterpreter {
+    /// Creates a new instance of `SemiNaiveInterpreter`.
+    pub fn new() -> SemiNaiveInterpreter {
+        SemiNaiveInterpreter {
+            facts: HashMap::new(),
+            rules: HashMap::new(),
+            strata: Vec::new(),
+            model: RefCell::new(None),
+            magic: RefCell::new(Vec::new()),
+            schema: Schema::default(),
+        }
+    }
+
+    /// Stratifies the rules again after they change, and discards the
+    /// models.
+    fn rules_changed(&mut self) -> Result<()> {
+        let rules = self.rules.values().flat_map(|r| r.iter());
+        self.strata = owned(stratify(rules)?);
+        *self.model.borrow_mut() = None;
+        self.magic.borrow_mut().clear();
+        Ok(())
+    }
+
+    /// Returns the strata of the rules.
+    fn strata(&self) -> Vec<Vec<&Clause>> {
+        borrowed(&self.strata)
+    }
+
+    /// Computes every fact derivable from the program, if that has not been
+    /// done since the program last changed. The models of magic-sets
+    /// rewritings are no longer needed once it has been.
+    fn compute_model(&self) -> Result<()> {
+        if self.model.borrow().is_none() {
+            let model = fixpoint(&self.facts, &self.strata())?;
+            *self.model.borrow_mut() = Some(model);
+            self.magic.borrow_mut().clear();
+        }
+        Ok(())
+    }
+
+    /// Brings the models that have been computed up to date with a change to
+    /// the base facts. A model that fails to update is discarded, so that the
+    /// error is reported by the next query that needs it instead.
+    fn update_model(&self, added: Relations, removed: Relations) {
+        let mut model = self.model.borrow_mut();
+        let failed = match *model {
+            Some(ref mut model) => {
+                let strata = self.strata();
+                update(model, &self.facts, &strata, &added, &removed).is_err()
+            }
+            None => false,
+        };
+        if failed {
+            *model = None;
+        }
+
+        let mut magic = self.magic.borrow_mut();
+        let kept = magic.drain(..).filter_map(|mut magic| {
+            let strata = borrowed(&magic.strata);
+            let model = &mut magic.model;
+            match update(model, &self.facts, &strata, &added, &removed) {
+                Ok(()) => Some(magic),
+                Err(_) => None,
+            }
+        });
+        *magic = kept.collect();
+    }
+
+    /// Answers a query about the rewritten rules of a magic-sets rewriting,
+    /// computing the model of the rules if it is not already kept.
+    fn magic_answers(
+        &self,
+        rules: Vec<Clause>,
+        query: &Literal,
+    ) -> Result<Vec<Result<Bindings>>> {
+        let seed = rules[0].head();
+        let mut magic = self.magic.borrow_mut();
+        let magic_model = match magic.iter().position(|m| m.seed == *seed) {
+            Some(i) => magic.remove(i),
+            None => {
+                let strata = owned(stratify(&rules)?);
+                let model = fixpoint(&self.facts, &borrowed(&strata))?;
+                if magic.len() == MAGIC_MODELS {
+                    magic.remove(0);
+                }
+                MagicModel {
+                    seed: seed.clone(),
+                    strata,
+                    model,
+                }
+            }
+        };
+        let answers = answers(&self.facts, &magic_model.model, query);
+        magic.push(magic_model);
+        Ok(answers)
+    }
+
+    /// Returns every fact derivable from the program.
+    pub fn model(&self) -> Result<Relations> {
+        self.compute_model()?;
+        let mut model = self.facts.clone();
+        model.extend(self.model.borrow().clone().unwrap());
+        Ok(model)
+    }
+}
+
+impl Interpeter for SemiNaiveInterpreter {
+    fn run_declaration(&mut self, decl: Declaration) -> Result<()> {
+        let facts = self.facts
+            .iter()
+            .flat_map(|(pred, facts)| facts.iter().map(move |f| (&pred.0, f)));
+        let rules = self.rules.values().flat_map(|r| r.iter());
+        check_declaration(&mut self.schema, facts, rules, decl)
+    }
+
+    fn run_assertion(&mut self, clause: Clause) -> Result<()> {
+        let rules = self.rules.values().flat_map(|r| r.iter());
+        let clause = check_assertion(&self.schema, rules, clause)?;
+        if let Some(fact) = clause.as_fact() {
+            // The rewriting of a query depends on which predicates have
+            // facts, so it must be redone when one first has some.
+            if !self.facts.contains_key(&clause.pred()) {
+                self.magic.borrow_mut().clear();
+            }
+            let inserted = self.facts
+                .entry(clause.pred())
+                .or_insert_with(Relation::new)
+                .insert(fact.clone());
+            if inserted {
+                let added = once((clause.pred(), once(fact).collect()));
+                self.update_model(added.collect(), Relations::new());
+            }
+        } else {
+            self.rules
+                .entry(clause.pred())
+                .or_insert_with(Vec::new)
+                .push(clause);
+            self.rules_changed()?;
+        }
+        Ok(())
+    }
+
+    fn run_retraction(&mut self, clause: Clause) -> Result<()> {
+        let pred = clause.pred();
+        let (facts, rules) = retract(&mut self.facts, &mut self.rules, clause)?;
+        if rules > 0 {
+            self.rules_changed()?;
+        } else {
+            let removed = once((pred, facts.into_iter().collect())).collect();
+            self.update_model(Relations::new(), removed);
+        }
+        Ok(())
+    }
+
+    fn run_query<'a>(
+        &'a self,
+        query: Literal,
+    ) -> Box<'a + Iterator<Item = Result<Bindings>>> {
+        // If the query has a bound argument, it's (probably) cheaper to
+        // evaluate the magic-sets rewriting of the program for this query than
+        // to compute the whole model. Its model is kept up to date, so asking
+        // the query again doesn't evaluate it again; once the whole model has
+        // been computed, by a query without bound arguments, that is used
+        // instead.
+        let bound = query.1.iter().any(|arg| match *arg {
+            Term::Value(..) => true,
+            Term::Var(..) => false,
+        });
+        if bound && self.model.borrow().is_none() {
+            let rules = self.rules.values().flat_map(|r| r.iter());
+            let facts = self.facts.keys().cloned().collect();
+            if let Some((rules, query)) = rewrite(rules, &facts, &query) {
+                return match self.magic_answers(rules, &query) {
+                    Ok(answers) => Box::new(answers.into_iter()),
+                    Err(err) => Box::new(once(Err(err))),
+                };
+            }
+        }
+
+        if let Err(err) = self.compute_model() {
+            return Box::new(once(Err(err)));
+        }
+        let model = self.model.borrow();
+        let answers = answers(&self.facts, model.as_ref().unwrap(), &query);
+        Box::new(answers.into_iter())
+    }
+
+    fn arity(&self, name: &Name) -> Option<usize> {
+        let preds = self.facts.keys().chain(self.rules.keys());
+        arity(&self.schema, preds, name)
+    }
+}
+
+/// Returns the answers to a query in a model of rules over the given facts.
+fn answers(
+    facts: &Relations,
+    model: &Relations,
+    query: &Literal,
+) -> Vec<Result<Bindings>> {
+    Db::new(facts, model)
+        .tuples(&query.signature())
+        .into_iter()
+        .flatten()
+        .filter_map(|fact| query.try_instantiate_fact(fact))
+        .map(Ok)
+        .collect()
+}
+
+/// Clones the rules in each stratum.
+fn owned(strata: Vec<Vec<&Clause>>) -> Vec<Vec<Clause>> {
+    strata
+        .into_iter()
+        .map(|stratum| stratum.into_iter().cloned().collect())
+        .collect()
+}
+
+/// Borrows the rules in each stratum.
+fn borrowed(strata: &[Vec<Clause>]) -> Vec<Vec<&Clause>> {
+    strata.iter().map(|s| s.iter().collect()).collect()
+}
+
+#[test]
+fn left_recursion() {
+    use Program;
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "edge(a, b). edge(b, c). edge(d, e).\n",
+        "node(a). node(b). node(c). node(d). node(e).\n",
+        "edge(X, X) :- node(X).\n",
+        "edge(X, Y) :- edge(Y, X).\n",
+        "path(X, Y) :- edge(X, Y).\n",
+        "path(X, Y) :- path(X, Z), edge(Z, Y).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = SemiNaiveInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    assert_eq!(query(&interpreter, "edge(b, X)?"), set(&["a", "b", "c"]));
+    assert_eq!(query(&interpreter, "path(X, a)?"), set(&["a", "b", "c"]));
+    assert_eq!(query(&interpreter, "path(e, X)?"), set(&["d", "e"]));
+    assert_eq!(query(&interpreter, "path(a, e)?"), set(&[]));
+}
+
+#[test]
+fn negation() {
+    use Program;
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "edge(a, b). edge(b, c). edge(d, e).\n",
+        "node(a). node(b). node(c). node(d). node(e).\n",
+        "reach(X, Y) :- edge(X, Y).\n",
+        "reach(X, Y) :- reach(X, Z), edge(Z, Y).\n",
+        "unreach(X, Y) :- node(X), node(Y), not reach(X, Y).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = SemiNaiveInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    assert_eq!(query(&interpreter, "unreach(a, X)?"), set(&["a", "d", "e"]));
+    assert_eq!(query(&interpreter, "unreach(X, c)?"), set(&["c", "d", "e"]));
+    assert_eq!(query(&interpreter, "unreach(a, c)?"), set(&[]));
+}
+
+#[test]
+fn aggregates() {
+    use Program;
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "edge(a, b). edge(b, c). edge(c, a). edge(c, d).\n",
+        "path(X, Y) :- edge(X, Y).\n",
+        "path(X, Y) :- path(X, Z), edge(Z, Y).\n",
+        "reachable(X, count<Y>) :- path(X, Y).\n",
+        "most(max<N>) :- reachable(X, N).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = SemiNaiveInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    assert_eq!(query(&interpreter, "reachable(a, N)?"), set(&["4"]));
+    assert_eq!(query(&interpreter, "reachable(X, 4)?"), set(&["a", "b", "c"]));
+    assert_eq!(query(&interpreter, "reachable(d, N)?"), set(&[]));
+    assert_eq!(query(&interpreter, "most(N)?"), set(&["4"]));
+}
+
+#[test]
+fn incremental() {
+    use Program;
+
+    let program: Program = concat!(
+        "edge(a, b). edge(b, c). edge(c, a). edge(c, d).\n",
+        "node(a). node(b). node(c). node(d). node(e).\n",
+        "edge(X, Y) :- link(Y, X).\n",
+        "path(X, Y) :- edge(X, Y).\n",
+        "path(X, Y) :- path(X, Z), edge(Z, Y).\n",
+        "unreach(X, Y) :- node(X), node(Y), not path(X, Y).\n",
+        "reachable(X, count<Y>) :- path(X, Y).\n",
+        "degree(X, count<Y>) :- edge(X, Y).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = SemiNaiveInterpreter::new();
+    interpreter.load_program(program).unwrap();
+    interpreter.model().unwrap();
+
+    let nonempty = |model: Relations| -> Relations {
+        model.into_iter().filter(|&(_, ref r)| !r.is_empty()).collect()
+    };
+    let stmts = vec![
+        "degree(c, 2).",
+        "degree(c, 2)~",
+        "edge(d, e).",
+        "edge(c, a)~",
+        "link(e, a).",
+        "edge(a, b)~",
+        "edge(X, d)~",
+        "link(e, a)~",
+        "edge(c, a).",
+        "edge(a, b).",
+    ];
+    for stmt in stmts {
+        interpreter.run_stmt(stmt.parse().unwrap()).unwrap().count();
+        assert!(interpreter.model.borrow().is_some());
+        let rules = interpreter.rules.values().flat_map(|r| r.iter());
+        let strata = stratify(rules).unwrap();
+        let expected = fixpoint(&interpreter.facts, &strata).unwrap();
+        let model = interpreter.model.borrow().clone().unwrap();
+        assert_eq!(nonempty(model), nonempty(expected), "after {}", stmt);
+    }
+}
+
+#[test]
+fn incremental_magic() {
+    use std::collections::BTreeSet;
+
+    use Program;
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "edge(a, b). edge(b, c). edge(d, e).\n",
+        "path(X, Y) :- edge(X, Y).\n",
+        "path(X, Y) :- path(X, Z), edge(Z, Y).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = SemiNaiveInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    let mut answers = |stmt: &str, q: &str| -> BTreeSet<String> {
+        interpreter.run_stmt(stmt.parse().unwrap()).unwrap().count();
+        let answers = query(&interpreter, q);
+        assert!(interpreter.model.borrow().is_none());
+        assert_eq!(interpreter.magic.borrow().len(), 1);
+        answers
+    };
+
+    assert_eq!(answers("edge(e, f).", "path(a, X)?"), set(&["b", "c"]));
+    assert_eq!(
+        answers("edge(c, d).", "path(a, Y)?"),
+        set(&["b", "c", "d", "e", "f"])
+    );
+    assert_eq!(answers("edge(b, c)~", "path(a, X)?"), set(&["b"]));
+}
+
+#[test]
+fn magic_models_bounded() {
+    use Program;
+    use util::{query, set};
+
+    let mut src = String::from("path(X, Y) :- edge(X, Y).\n");
+    src += "path(X, Y) :- path(X, Z), edge(Z, Y).\n";
+    for i in 0..2 * MAGIC_MODELS {
+        src += &format!("edge(n{}, n{}).\n", i, i + 1);
+    }
+    let program: Program = src.parse().unwrap();
+    let mut interpreter = SemiNaiveInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    for i in 0..2 * MAGIC_MODELS {
+        let answers = query(&interpreter, &format!("path(X, n{})?", i + 1));
+        assert_eq!(answers.len(), i + 1);
+    }
+    let magic = interpreter.magic.borrow();
+    assert_eq!(magic.len(), MAGIC_MODELS);
+    let seed = format!("magic_path_fb(n{})", 2 * MAGIC_MODELS);
+    assert_eq!(magic.last().unwrap().seed.to_string(), seed);
+
+    // The facts about edge are read from the base facts, not copied.
+    let edge = (Name::new("edge").unwrap(), 2);
+    assert!(magic.iter().all(|m| !m.model.contains_key(&edge)));
+    drop(magic);
+    assert_eq!(query(&interpreter, "path(n0, n1)?"), set(&[""]));
+}
@@ -0,0 +1,288 @@
+//! Stratification of rules, which orders them so that every predicate is
+//! fully computed before it is used negatively, or aggregated over.
+
+use std::collections::{HashMap, VecDeque};
+
+use {Aggregate, Clause, ErrorKind, Name, Result};
+
+/// How a rule's head depends on a predicate in its body.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Dependency {
+    /// The predicate is used positively.
+    Positive,
+
+    /// The predicate is used negatively.
+    Negated,
+
+    /// The predicate is aggregated over, by the given function.
+    Aggregated(Aggregate),
+}
+
+impl Dependency {
+    /// Returns whether the predicate must be fully computed first.
+    pub fn is_negative(&self) -> bool {
+        *self != Dependency::Positive
+    }
+}
+
+/// Splits a set of rules into strata, such that the rules in each stratum
+/// only depend on predicates defined in that stratum or in earlier ones, and
+/// only depend negatively on predicates defined in earlier ones.
+///
+/// Each stratum is a strongly connected component of the predicate
+/// dependency graph; the strata are found with Tarjan's algorithm, which
+/// conveniently produces them in dependency order. A rule with an aggregate
+/// in its head depends negatively on every predicate in its body, since it
+/// needs them to be complete. If a predicate depends negatively on a
+/// predicate in its own component, the rules cannot be stratified, and the
+/// error names the offending cycle.
+pub fn stratify<'a, I>(rules: I) -> Result<Vec<Vec<&'a Clause>>>
+where
+    I: IntoIterator<Item = &'a Clause>,
+{
+    let mut graph = Graph::default();
+    for rule in rules {
+        let pred = graph.node(rule.pred());
+        for goal in rule.body() {
+            if let Some(literal) = goal.literal() {
+                let dep = graph.node(literal.signature());
+                let kind = if goal.is_negated() {
+                    Dependency::Negated
+                } else if let Some(&(_, op)) = rule.2.first() {
+                    Dependency::Aggregated(op)
+                } else {
+                    Dependency::Positive
+                };
+                graph.edges[pred].push((dep, kind));
+            }
+        }
+        graph.rules[pred].push(rule);
+    }
+
+    let mut tarjan = Tarjan {
+        graph: &graph,
+        index: 0,
+        indices: vec![None; graph.edges.len()],
+        lowlinks: vec![0; graph.edges.len()],
+        stack: Vec::new(),
+        on_stack: vec![false; graph.edges.len()],
+        sccs: Vec::new(),
+    };
+    for node in 0..graph.edges.len() {
+        if tarjan.indices[node].is_none() {
+            tarjan.visit(node);
+        }
+    }
+
+    let mut components = vec![0; graph.edges.len()];
+    for (i, scc) in tarjan.sccs.iter().enumerate() {
+        for &node in scc {
+            components[node] = i;
+        }
+    }
+    for (node, edges) in graph.edges.iter().enumerate() {
+        for &(dep, kind) in edges {
+            if kind.is_negative() && components[node] == components[dep] {
+                let (cycle, kinds) = graph.cycle(node, dep, kind, &components);
+                return Err(ErrorKind::Unstratifiable(cycle, kinds).into());
+            }
+        }
+    }
+
+    Ok(tarjan
+        .sccs
+        .into_iter()
+        .map(|scc| {
+            scc.into_iter()
+                .flat_map(|node| graph.rules[node].iter().cloned())
+                .collect::<Vec<_>>()
+        })
+        .filter(|stratum| !stratum.is_empty())
+        .collect())
+}
+
+/// The predicate dependency graph.
+#[derive(Default)]
+struct Graph<'a> {
+    nodes: HashMap<(Name, usize), usize>,
+    preds: Vec<(Name, usize)>,
+    edges: Vec<Vec<(usize, Dependency)>>,
+    rules: Vec<Vec<&'a Clause>>,
+}
+
+impl<'a> Graph<'a> {
+    fn node(&mut self, pred: (Name, usize)) -> usize {
+        let preds = &mut self.preds;
+        let edges = &mut self.edges;
+        let rules = &mut self.rules;
+        *self.nodes.entry(pred.clone()).or_insert_with(|| {
+            preds.push(pred);
+            edges.push(Vec::new());
+            rules.push(Vec::new());
+            edges.len() - 1
+        })
+    }
+
+    /// Returns the predicates on a cycle that starts with the edge of the
+    /// given kind from `from` to `to` and returns to `from` within their
+    /// component, starting and ending with `from`, along with the kind of
+    /// each edge on it.
+    fn cycle(
+        &self,
+        from: usize,
+        to: usize,
+        kind: Dependency,
+        components: &[usize],
+    ) -> (Vec<(Name, usize)>, Vec<Dependency>) {
+        let mut parents = HashMap::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(to);
+        parents.insert(to, (to, kind));
+        while let Some(node) = queue.pop_front() {
+            if node == from {
+                break;
+            }
+            for &(dep, kind) in &self.edges[node] {
+                if components[dep] == components[from]
+                    && !parents.contains_key(&dep)
+                {
+                    parents.insert(dep, (node, kind));
+                    queue.push_back(dep);
+                }
+            }
+        }
+
+        let mut path = vec![from];
+        let mut kinds = Vec::new();
+        let mut node = from;
+        while node != to {
+            let (parent, kind) = parents[&node];
+            node = parent;
+            path.push(node);
+            kinds.push(kind);
+        }
+        path.push(from);
+        kinds.push(kind);
+        path.reverse();
+        kinds.reverse();
+        let path = path.into_iter().map(|n| self.preds[n].clone()).collect();
+        (path, kinds)
+    }
+}
+
+struct Tarjan<'a: 'b, 'b> {
+    graph: &'b Graph<'a>,
+    index: usize,
+    indices: Vec<Option<usize>>,
+    lowlinks: Vec<usize>,
+    stack: Vec<usize>,
+    on_stack: Vec<bool>,
+    sccs: Vec<Vec<usize>>,
+}
+
+impl<'a, 'b> Tarjan<'a, 'b> {
+    fn visit(&mut self, node: usize) {
+        self.indices[node] = Some(self.index);
+        self.lowlinks[node] = self.index;
+        self.index += 1;
+        self.stack.push(node);
+        self.on_stack[node] = true;
+
+        for &(dep, _) in &self.graph.edges[node] {
+            if let Some(index) = self.indices[dep] {
+                if self.on_stack[dep] {
+                    self.lowlinks[node] = self.lowlinks[node].min(index);
+                }
+            } else {
+                self.visit(dep);
+                self.lowlinks[node] =
+                    self.lowlinks[node].min(self.lowlinks[dep]);
+            }
+        }
+
+        if Some(self.lowlinks[node]) == self.indices[node] {
+            let mut scc = Vec::new();
+            loop {
+                let top = self.stack.pop().unwrap();
+                self.on_stack[top] = false;
+                scc.push(top);
+                if top == node {
+                    break;
+                }
+            }
+            self.sccs.push(scc);
+        }
+    }
+}
+
+#[test]
+fn negative_cycle() {
+    use {Error, Program, Statement};
+
+    let rules = |s: &str| -> Vec<Clause> {
+        let program: Program = s.parse().unwrap();
+        program
+            .0
+            .into_iter()
+            .map(|stmt| match stmt {
+                Statement::Assertion(c) => c,
+                _ => unreachable!(),
+            })
+            .collect()
+    };
+    let cycle = |err: Error| match *err.kind() {
+        ErrorKind::Unstratifiable(ref cycle, _) => cycle
+            .iter()
+            .map(|&(ref n, _)| n.to_string())
+            .collect::<Vec<_>>(),
+        ref err => panic!("unexpected error {}", err),
+    };
+
+    let ok = rules(concat!(
+        "reach(X, Y) :- edge(X, Y).\n",
+        "reach(X, Y) :- edge(X, Z), reach(Z, Y).\n",
+        "unreach(X, Y) :- node(X), node(Y), not reach(X, Y).\n",
+    ));
+    assert_eq!(stratify(&ok).unwrap().len(), 2);
+
+    let bad = rules(concat!(
+        "win(X) :- move(X, Y), not lose(Y).\n",
+        "lose(X) :- win(X).\n",
+    ));
+    let err = stratify(&bad).unwrap_err();
+    assert_eq!(cycle(err), vec!["win", "lose", "win"]);
+
+    let bad = rules("p(X) :- q(X), \\+ p(X).");
+    let err = stratify(&bad).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        concat!(
+            "The program cannot be stratified, since it has a cycle through ",
+            "negation: p/1 -> not p/1",
+        )
+    );
+
+    let bad = rules("p(count<X>) :- p(X).");
+    let err = stratify(&bad).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        concat!(
+            "The program cannot be stratified, since it has a cycle through ",
+            "an aggregate: p/1 -> count<p/1>",
+        )
+    );
+
+    let bad = rules(concat!(
+        "p(X) :- q(X).\n",
+        "q(X) :- r(X), not p(X).\n",
+        "r(sum<X>) :- q(X).\n",
+    ));
+    let err = stratify(&bad).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        concat!(
+            "The program cannot be stratified, since it has a cycle through ",
+            "negation: q/1 -> not p/1 -> q/1",
+        )
+    );
+}
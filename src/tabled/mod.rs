@@ -0,0 +1,445 @@
+//! A top-down interpreter that tables the answers to subgoals.
+
+use std::collections::{HashMap, HashSet};
+use std::iter::once;
+
+use {Bindings, Clause, Declaration, Goal, Interpeter, Literal, Name, Result,
+     Term, Value, Variable};
+use schema::Schema;
+use util::{arity, check_assertion, check_declaration, retract};
+
+/// A top-down interpreter which memoizes the answers to each subgoal it
+/// encounters, in the style of SLG resolution.
+///
+/// Rather than recursing into a subgoal it has already seen (which is what
+/// sends the `NaiveInterpreter` into an infinite loop on left-recursive
+/// rules), it consumes the answers found so far for that subgoal, and
+/// re-evaluates the subgoals until no table gains a new answer. Only
+/// subgoals reachable from the query are ever evaluated.
+///
+/// A negated subgoal is solved with a separate set of tables, which is
+/// complete before it is consulted; stratification guarantees that it does
+/// not depend on the subgoal being evaluated. The same goes for the subgoals
+/// in the body of a rule with aggregates. Complete tables are kept for the
+/// rest of the query, and used in place of evaluating their subgoals again.
+#[derive(Debug)]
+pub struct TabledInterpreter {
+    facts: HashMap<(Name, usize), HashSet<Vec<Value>>>,
+    rules: HashMap<(Name, usize), Vec<Clause>>,
+    schema: Schema,
+}
+
+impl Default for TabledInterpreter {
+    fn default() -> TabledInterpreter {
+        TabledInterpreter::new()
+    }
+}
+
+impl TabledInterpreter {
+    /// Creates a new instance of `TabledInterpreter`.
+    pub fn new() -> TabledInterpreter {
+        TabledInterpreter {
+            facts: HashMap::new(),
+            rules: HashMap::new(),
+            schema: Schema::default(),
+        }
+    }
+
+    /// Computes the tables for a goal and every subgoal it depends on.
+    ///
+    /// Each table is keyed by its subgoal with its variables renamed
+    /// canonically, so that variant subgoals such as `path(a, X)` and
+    /// `path(a, Y)` share a table. The answers in a table are the ground
+    /// argument tuples of the subgoal.
+    pub fn tables(&self, goal: &Literal) -> Result<Tables> {
+        let mut complete = Tables::new();
+        let mut tables = self.solve(goal, &mut complete)?;
+        tables.extend(complete);
+        Ok(tables)
+    }
+
+    /// Computes the tables for a goal and the subgoals it depends on, other
+    /// than those already in `complete`, which are used as they are.
+    ///
+    /// Each call is evaluated in full the first time, and after that, in the
+    /// style of semi-naive evaluation, only for the derivations that use an
+    /// answer added in the previous round.
+    fn solve(&self, goal: &Literal, complete: &mut Tables) -> Result<Tables> {
+        let mut tables = Tables::new();
+        let mut calls = vec![variant_key(goal)];
+        tables.insert(calls[0].clone(), HashSet::new());
+
+        let mut delta = Tables::new();
+        let mut evaluated = 0;
+        loop {
+            let mut new = Tables::new();
+            let mut i = 0;
+            while i < calls.len() {
+                let old = if i < evaluated { Some(&delta) } else { None };
+                let (answers, new_calls) =
+                    self.eval_call(&calls[i], &tables, old, complete)?;
+                for call in new_calls {
+                    if !tables.contains_key(&call)
+                        && !complete.contains_key(&call)
+                    {
+                        tables.insert(call.clone(), HashSet::new());
+                        calls.push(call);
+                    }
+                }
+
+                let table = tables.get_mut(&calls[i]).unwrap();
+                for answer in answers {
+                    if table.insert(answer.clone()) {
+                        new.entry(calls[i].clone())
+                            .or_insert_with(HashSet::new)
+                            .insert(answer);
+                    }
+                }
+                i += 1;
+            }
+
+            if new.is_empty() {
+                return Ok(tables);
+            }
+            evaluated = calls.len();
+            delta = new;
+        }
+    }
+
+    /// Solves a goal completely, adding the tables for it and the subgoals
+    /// it depends on to `complete`, unless it already has one there.
+    fn complete(&self, goal: &Literal, complete: &mut Tables) -> Result<()> {
+        if !complete.contains_key(&variant_key(goal)) {
+            let tables = self.solve(goal, complete)?;
+            complete.extend(tables);
+        }
+        Ok(())
+    }
+
+    /// Evaluates a call once, using the answers currently in the tables for
+    /// its subgoals, or in the complete tables for those that have one. If
+    /// `delta` is given, only the derivations using one of its answers for a
+    /// positive subgoal are made. Returns the answers found, and any subgoals
+    /// that do not yet have tables.
+    fn eval_call(
+        &self,
+        call: &Literal,
+        tables: &Tables,
+        delta: Option<&Tables>,
+        complete: &mut Tables,
+    ) -> Result<(Vec<Vec<Value>>, Vec<Literal>)> {
+        let mut answers = Vec::new();
+        let mut new_calls = Vec::new();
+
+        let facts = match delta {
+            Some(_) => None,
+            None => self.facts.get(&call.signature()),
+        };
+        if let Some(facts) = facts {
+            answers.extend(
+                facts
+                    .iter()
+                    .filter(|fact| call.try_instantiate_fact(fact).is_some())
+                    .cloned(),
+            );
+        }
+
+        let rules = self.rules.get(&call.signature());
+        for rule in rules.into_iter().flat_map(|rules| rules.iter()) {
+            let instance = if rule.is_aggregate() {
+                rule.try_instantiate(rule.group_goal(call))
+            } else {
+                rule.try_instantiate(call.clone())
+            };
+            let (bindings, body) = match instance {
+                Some(instance) => instance,
+                None => continue,
+            };
+
+            // The subgoals of a rule with aggregates are solved completely,
+            // so it has nothing more to derive once it has been evaluated.
+            let positions = match delta {
+                None => vec![None],
+                Some(_) if rule.is_aggregate() => continue,
+                Some(_) => body.iter()
+                    .enumerate()
+                    .filter_map(|(i, goal)| match *goal {
+                        Goal::Literal(_) => Some(Some(i)),
+                        Goal::Not(_) | Goal::Compare(..) => None,
+                    })
+                    .collect(),
+            };
+            for position in positions {
+                let mut solutions = vec![bindings.clone()];
+                for (i, goal) in body.iter().enumerate() {
+                    let literal = match *goal {
+                        Goal::Literal(ref l) => l,
+                        Goal::Not(ref l) => {
+                            let mut next = Vec::new();
+                            for bindings in solutions {
+                                let l = l.substitute(&bindings);
+                                self.complete(&l, complete)?;
+                                let answers = &complete[&variant_key(&l)];
+                                if !answers.iter().any(|answer| {
+                                    l.try_instantiate_fact(answer).is_some()
+                                }) {
+                                    next.push(bindings);
+                                }
+                            }
+                            solutions = next;
+                            continue;
+                        }
+                        Goal::Compare(ref l, op, ref r) => {
+                            let mut next = Vec::new();
+                            for mut bindings in solutions {
+                                let l = l.substitute(&bindings);
+                                let r = r.substitute(&bindings);
+                                if let Some(new) = op.eval(&l, &r)? {
+                                    bindings.extend(new);
+                                    next.push(bindings);
+                                }
+                            }
+                            solutions = next;
+                            continue;
+                        }
+                    };
+                    let mut next = Vec::new();
+                    for bindings in solutions {
+                        let literal = literal.substitute(&bindings);
+                        let key = variant_key(&literal);
+                        if rule.is_aggregate() {
+                            self.complete(&literal, complete)?;
+                        }
+                        let table = complete
+                            .get(&key)
+                            .or_else(|| tables.get(&key));
+                        let table = match (table, delta) {
+                            (None, _) => {
+                                new_calls.push(key);
+                                continue;
+                            }
+                            (Some(_), Some(delta)) if position == Some(i) => {
+                                match delta.get(&key) {
+                                    Some(table) => table,
+                                    None => continue,
+                                }
+                            }
+                            (Some(table), _) => table,
+                        };
+                        for answer in table {
+                            if let Some(new) =
+                                literal.try_instantiate_fact(answer)
+                            {
+                                let mut bindings = bindings.clone();
+                                bindings.extend(new);
+                                next.push(bindings);
+                            }
+                        }
+                    }
+                    solutions = next;
+                }
+
+                if rule.is_aggregate() {
+                    let tuples = rule.aggregate(solutions)?.into_iter();
+                    answers.extend(tuples.filter(|tuple| {
+                        call.try_instantiate_fact(tuple).is_some()
+                    }));
+                    continue;
+                }
+                for bindings in solutions {
+                    let head = rule.head().substitute(&bindings);
+                    answers.extend(head.as_ground());
+                }
+            }
+        }
+
+        Ok((answers, new_calls))
+    }
+}
+
+/// The answer tables built while solving a goal.
+pub type Tables = HashMap<Literal, HashSet<Vec<Value>>>;
+
+/// Renames the variables of a literal to `_0`, `_1`, and so on, in order of
+/// their first appearance.
+fn variant_key(literal: &Literal) -> Literal {
+    let mut renaming = HashMap::new();
+    let Literal(ref pred, ref args, _) = *literal;
+    let args = args.iter()
+        .map(|arg| match *arg {
+            Term::Var(ref v, _) => {
+                let n = renaming.len();
+                let var = renaming.entry(v.clone()).or_insert_with(|| {
+                    Variable::new(&format!("_{}", n)).unwrap()
+                });
+                Term::Var(var.clone(), None)
+            }
+            Term::Value(ref v, _) => Term::Value(v.clone(), None),
+        })
+        .collect();
+    Literal(pred.clone(), args, None)
+}
+
+impl Interpeter for TabledInterpreter {
+    fn run_declaration(&mut self, decl: Declaration) -> Result<()> {
+        let facts = self.facts
+            .iter()
+            .flat_map(|(pred, facts)| facts.iter().map(move |f| (&pred.0, f)));
+        let rules = self.rules.values().flat_map(|r| r.iter());
+        check_declaration(&mut self.schema, facts, rules, decl)
+    }
+
+    fn run_assertion(&mut self, clause: Clause) -> Result<()> {
+        let rules = self.rules.values().flat_map(|r| r.iter());
+        let clause = check_assertion(&self.schema, rules, clause)?;
+        if let Some(fact) = clause.as_fact() {
+            self.facts
+                .entry(clause.pred())
+                .or_insert_with(HashSet::new)
+                .insert(fact);
+        } else {
+            self.rules
+                .entry(clause.pred())
+                .or_insert_with(Vec::new)
+                .push(clause)
+        }
+        Ok(())
+    }
+
+    fn run_retraction(&mut self, clause: Clause) -> Result<()> {
+        retract(&mut self.facts, &mut self.rules, clause)?;
+        Ok(())
+    }
+
+    fn run_query<'a>(
+        &'a self,
+        query: Literal,
+    ) -> Box<'a + Iterator<Item = Result<Bindings>>> {
+        let mut tables = match self.tables(&query) {
+            Ok(tables) => tables,
+            Err(err) => return Box::new(once(Err(err))),
+        };
+        let answers = tables.remove(&variant_key(&query)).unwrap();
+        Box::new(answers.into_iter().filter_map(move |answer| {
+            query.try_instantiate_fact(&answer[..]).map(Ok)
+        }))
+    }
+
+    fn arity(&self, name: &Name) -> Option<usize> {
+        let preds = self.facts.keys().chain(self.rules.keys());
+        arity(&self.schema, preds, name)
+    }
+}
+
+#[test]
+fn left_recursion() {
+    use Program;
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "edge(a, b). edge(b, c). edge(c, a). edge(d, e).\n",
+        "path(X, Y) :- path(X, Z), edge(Z, Y).\n",
+        "path(X, Y) :- edge(X, Y).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = TabledInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    assert_eq!(query(&interpreter, "path(a, Y)?"), set(&["a", "b", "c"]));
+
+    // Nothing about `d` or `e` is relevant to the query.
+    let path = match "path(a, Y)?".parse().unwrap() {
+        ::Statement::Query(q) => q[0].literal().unwrap().clone(),
+        _ => unreachable!(),
+    };
+    let (d, e) = (Value::new("d").unwrap(), Value::new("e").unwrap());
+    let tables = interpreter.tables(&path).unwrap();
+    assert!(tables.values().flat_map(|t| t.iter()).all(|answer| {
+        answer.iter().all(|v| *v != d && *v != e)
+    }));
+}
+
+#[test]
+fn negation() {
+    use Program;
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "edge(a, b). edge(b, c). edge(d, e).\n",
+        "node(a). node(b). node(c). node(d). node(e).\n",
+        "reach(X, Y) :- edge(X, Y).\n",
+        "reach(X, Y) :- reach(X, Z), edge(Z, Y).\n",
+        "unreach(X, Y) :- node(X), node(Y), not reach(X, Y).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = TabledInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    assert_eq!(query(&interpreter, "unreach(a, X)?"), set(&["a", "d", "e"]));
+    assert_eq!(query(&interpreter, "unreach(X, c)?"), set(&["c", "d", "e"]));
+    assert_eq!(query(&interpreter, "unreach(a, c)?"), set(&[]));
+}
+
+#[test]
+fn mutual_recursion() {
+    use Program;
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "edge(a, b). edge(b, c). edge(c, d). edge(e, f). edge(f, e).\n",
+        "odd(X, Y) :- edge(X, Y).\n",
+        "odd(X, Y) :- even(X, Z), edge(Z, Y).\n",
+        "even(X, Y) :- odd(X, Z), edge(Z, Y).\n",
+        "only_odd(X, Y) :- odd(X, Y), not even(X, Y).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = TabledInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    assert_eq!(query(&interpreter, "odd(a, Y)?"), set(&["b", "d"]));
+    assert_eq!(query(&interpreter, "even(X, c)?"), set(&["a"]));
+    assert_eq!(query(&interpreter, "even(e, Y)?"), set(&["e"]));
+    assert_eq!(query(&interpreter, "only_odd(a, Y)?"), set(&["b", "d"]));
+    assert_eq!(query(&interpreter, "only_odd(e, Y)?"), set(&["f"]));
+
+    // The complete tables for the negated subgoals are kept along with the
+    // others.
+    let only_odd = match "only_odd(a, Y)?".parse().unwrap() {
+        ::Statement::Query(q) => q[0].literal().unwrap().clone(),
+        _ => unreachable!(),
+    };
+    let tables = interpreter.tables(&only_odd).unwrap();
+    for y in &["b", "d"] {
+        let even = Literal(
+            Name::new("even").unwrap(),
+            vec![
+                Term::Value(Value::new("a").unwrap(), None),
+                Term::Value(Value::new(y).unwrap(), None),
+            ],
+            None,
+        );
+        assert_eq!(tables[&even], HashSet::new());
+    }
+}
+
+#[test]
+fn aggregates() {
+    use Program;
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "edge(a, b). edge(b, c). edge(c, a). edge(d, e).\n",
+        "path(X, Y) :- path(X, Z), edge(Z, Y).\n",
+        "path(X, Y) :- edge(X, Y).\n",
+        "reachable(X, count<Y>) :- path(X, Y).\n",
+        "cost(X, Y, 2) :- edge(X, Y).\n",
+        "spent(X, sum<C>, avg<C>) :- cost(X, Y, C).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = TabledInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    assert_eq!(query(&interpreter, "reachable(a, N)?"), set(&["3"]));
+    assert_eq!(query(&interpreter, "reachable(X, 1)?"), set(&["d"]));
+    assert_eq!(query(&interpreter, "spent(a, 2, A)?"), set(&["2.0"]));
+}
@@ -1,7 +1,17 @@
 //! Utility functions.
 
+#[cfg(test)]
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::iter::FromIterator;
+
+use {Clause, Declaration, ErrorKind, Goal, Literal, Name, Result, Term,
+     Value};
+#[cfg(test)]
+use {Interpeter, Statement};
+use schema::Schema;
+use stratify::stratify;
 
 /// Applies a function to an entry.
 pub fn entry_fn<F, K, V>(map: &mut HashMap<K, V>, key: K, f: F)
@@ -14,3 +24,162 @@ where
         map.insert(key, new);
     }
 }
+
+/// Removes the items in an entry that match a predicate, removing the entry
+/// too if none are left. Returns the items removed.
+pub fn remove_where<F, K, V, T>(map: &mut HashMap<K, V>, key: K, f: F) -> Vec<T>
+where
+    F: Fn(&T) -> bool,
+    K: Eq + Hash,
+    V: IntoIterator<Item = T> + FromIterator<T>,
+{
+    let mut removed = Vec::new();
+    entry_fn(map, key, |_, items| {
+        let (gone, kept): (Vec<_>, Vec<_>) =
+            try_opt!(items).into_iter().partition(|item| f(item));
+        removed = gone;
+        if kept.is_empty() {
+            None
+        } else {
+            Some(kept.into_iter().collect())
+        }
+    });
+    removed
+}
+
+/// Checks a clause that is about to be asserted: it must be safe and agree
+/// with the declarations in the schema, and if it is a rule, the program must
+/// still be stratifiable once it is added to the given rules. Returns the
+/// clause with its body scheduled.
+pub fn check_assertion<'a, I>(
+    schema: &Schema,
+    rules: I,
+    clause: Clause,
+) -> Result<Clause>
+where
+    I: IntoIterator<Item = &'a Clause>,
+{
+    clause.check_safety()?;
+    schema.check(&clause)?;
+    let clause = clause.schedule();
+    if !clause.body().is_empty() {
+        let mut rules: Vec<&Clause> = rules.into_iter().collect();
+        rules.push(&clause);
+        stratify(rules)?;
+    }
+    Ok(clause)
+}
+
+/// Adds a declaration to the schema, after checking that the facts and rules
+/// already stored agree with it. Each fact is given with the name of its
+/// predicate; those and the rules that use the declared name, with any
+/// arity, are checked.
+pub fn check_declaration<'a, F, R>(
+    schema: &mut Schema,
+    facts: F,
+    rules: R,
+    decl: Declaration,
+) -> Result<()>
+where
+    F: IntoIterator<Item = (&'a Name, &'a Vec<Value>)>,
+    R: IntoIterator<Item = &'a Clause>,
+{
+    let mut new = schema.clone();
+    new.declare(decl.clone())?;
+    for (name, fact) in facts {
+        if *name == decl.0 {
+            let args = fact.iter().map(|v| Term::Value(v.clone(), None));
+            let head = Literal(name.clone(), args.collect(), None);
+            new.check(&Clause(head, Vec::new(), Vec::new(), None))?;
+        }
+    }
+    for rule in rules {
+        let body = rule.body().iter().filter_map(Goal::literal);
+        let mut literals = Some(rule.head()).into_iter().chain(body);
+        if literals.any(|literal| literal.0 == decl.0) {
+            new.check(rule)?;
+        }
+    }
+    *schema = new;
+    Ok(())
+}
+
+/// Returns the arity of a predicate, as `Interpeter::arity` does, given the
+/// name and arity of each predicate with stored clauses.
+pub fn arity<'a, I>(schema: &Schema, preds: I, name: &Name) -> Option<usize>
+where
+    I: IntoIterator<Item = &'a (Name, usize)>,
+{
+    if let Some(decl) = schema.declaration(name) {
+        return Some(decl.1.len());
+    }
+    let mut arities = preds.into_iter().filter(|p| p.0 == *name).map(|p| p.1);
+    let arity = try_opt!(arities.next());
+    if arities.all(|a| a == arity) {
+        Some(arity)
+    } else {
+        None
+    }
+}
+
+/// Retracts a clause from the facts and rules of an interpreter, as
+/// described by `Clause::retracts`. Returns the facts removed and the number
+/// of rules removed, or an error if nothing was removed.
+pub fn retract<V>(
+    facts: &mut HashMap<(Name, usize), V>,
+    rules: &mut HashMap<(Name, usize), Vec<Clause>>,
+    clause: Clause,
+) -> Result<(Vec<Vec<Value>>, usize)>
+where
+    V: IntoIterator<Item = Vec<Value>> + FromIterator<Vec<Value>>,
+{
+    let clause = clause.schedule();
+    let removed_facts = if clause.body().is_empty() {
+        remove_where(facts, clause.pred(), |f| clause.retracts_fact(f))
+    } else {
+        Vec::new()
+    };
+    let removed_rules = remove_where(rules, clause.pred(), |r| {
+        clause.retracts(r)
+    }).len();
+
+    if removed_facts.is_empty() && removed_rules == 0 {
+        Err(ErrorKind::NoSuchClause(clause).into())
+    } else {
+        Ok((removed_facts, removed_rules))
+    }
+}
+
+/// Parses the goals of a query given in source form.
+#[cfg(test)]
+pub fn goals(query: &str) -> Vec<Goal> {
+    match query.parse().unwrap() {
+        Statement::Query(goals) => goals,
+        stmt => panic!("{} is not a query", stmt),
+    }
+}
+
+/// Runs a query, given in source form, returning each answer as the values
+/// of its variables written one after another.
+#[cfg(test)]
+pub fn try_query<I: Interpeter>(
+    interpreter: &I,
+    query: &str,
+) -> Result<BTreeSet<String>> {
+    interpreter
+        .run_goals(goals(query))
+        .map(|b| Ok(b?.values().map(|v| v.to_string()).collect()))
+        .collect()
+}
+
+/// Like `try_query`, but panics if the query fails.
+#[cfg(test)]
+pub fn query<I: Interpeter>(interpreter: &I, query: &str) -> BTreeSet<String> {
+    try_query(interpreter, query).unwrap()
+}
+
+/// Collects the expected answers to a query, for comparison with `query`.
+#[cfg(test)]
+pub fn set(answers: &[&str]) -> BTreeSet<String> {
+    answers.iter().map(|s| s.to_string()).collect()
+}
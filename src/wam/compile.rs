@@ -0,0 +1,210 @@
+use std::collections::{HashMap, HashSet};
+
+use {Clause, Goal, Literal, Term, Variable};
+use wam::{Instruction, Reg};
+
+/// Compiles a single clause, without any choice instructions.
+pub fn compile_clause(clause: &Clause) -> Vec<Instruction> {
+    if clause.is_aggregate() {
+        return vec![Instruction::Aggregate(clause.clone())];
+    }
+
+    let head = clause.head();
+    let body = clause.body();
+    let mut regs = allocate_registers(head, body);
+    let mut code = Vec::new();
+
+    let allocated = body.len() > 1;
+    if allocated {
+        let perms = regs.perms();
+        code.push(Instruction::Allocate(perms));
+    }
+
+    for (i, arg) in head.1.iter().enumerate() {
+        code.push(match *arg {
+            Term::Value(ref v, _) => Instruction::GetConstant(v.clone(), i),
+            Term::Var(ref v, _) => match regs.first_use(v) {
+                (true, reg) => Instruction::GetVariable(reg, i),
+                (false, reg) => Instruction::GetValue(reg, i),
+            },
+        });
+    }
+
+    for (i, goal) in body.iter().enumerate() {
+        match *goal {
+            Goal::Literal(ref l) => {
+                put_args(l, &mut regs, &mut code);
+                if i + 1 < body.len() {
+                    code.push(Instruction::Call(l.signature()));
+                } else {
+                    if allocated {
+                        code.push(Instruction::Deallocate);
+                    }
+                    code.push(Instruction::Execute(l.signature()));
+                }
+            }
+            Goal::Not(ref l) => {
+                put_args(l, &mut regs, &mut code);
+                code.push(Instruction::CallNot(l.signature()));
+            }
+            Goal::Compare(ref l, op, ref r) => {
+                let vars = goal.vars()
+                    .into_iter()
+                    .map(|v| {
+                        let (first, reg) = regs.first_use(&v);
+                        (v, reg, first)
+                    })
+                    .collect();
+                let (l, r) = (l.clone(), r.clone());
+                code.push(Instruction::Compare(l, op, r, vars));
+            }
+        }
+    }
+
+    // If the body doesn't end with a literal to jump to, the clause has to
+    // return by itself.
+    match body.last() {
+        Some(&Goal::Literal(_)) => {}
+        _ => {
+            if allocated {
+                code.push(Instruction::Deallocate);
+            }
+            code.push(Instruction::Proceed);
+        }
+    }
+    code
+}
+
+/// Compiles a query. Every variable in the query is kept in a permanent
+/// register, so the answer can be read out of the environment by the `Halt`
+/// instruction; the variables are returned in register order.
+pub fn compile_query(query: &Literal) -> (Vec<Instruction>, Vec<Variable>) {
+    let mut vars = Vec::new();
+    for arg in &query.1 {
+        if let Term::Var(ref v, _) = *arg {
+            if !vars.contains(v) {
+                vars.push(v.clone());
+            }
+        }
+    }
+
+    let mut regs = Registers {
+        regs: vars.iter()
+            .enumerate()
+            .map(|(i, v)| (v.clone(), Reg::Y(i)))
+            .collect(),
+        seen: HashSet::new(),
+    };
+    let mut code = vec![Instruction::Allocate(vars.len())];
+    put_args(query, &mut regs, &mut code);
+    code.push(Instruction::Call(query.signature()));
+    code.push(Instruction::Halt);
+    (code, vars)
+}
+
+/// Emits the instructions to load the arguments of a body literal into the
+/// argument registers.
+fn put_args(
+    literal: &Literal,
+    regs: &mut Registers,
+    code: &mut Vec<Instruction>,
+) {
+    for (i, arg) in literal.1.iter().enumerate() {
+        code.push(match *arg {
+            Term::Value(ref v, _) => Instruction::PutConstant(v.clone(), i),
+            Term::Var(ref v, _) => match regs.first_use(v) {
+                (true, reg) => Instruction::PutVariable(reg, i),
+                (false, reg) => Instruction::PutValue(reg, i),
+            },
+        });
+    }
+}
+
+/// The registers assigned to the variables of a clause.
+struct Registers {
+    regs: HashMap<Variable, Reg>,
+    seen: HashSet<Variable>,
+}
+
+impl Registers {
+    /// Returns the number of permanent registers.
+    fn perms(&self) -> usize {
+        self.regs
+            .values()
+            .filter(|r| match **r {
+                Reg::Y(_) => true,
+                Reg::X(_) => false,
+            })
+            .count()
+    }
+
+    /// Returns whether this is the first use of the variable, and the
+    /// register assigned to it.
+    fn first_use(&mut self, var: &Variable) -> (bool, Reg) {
+        (self.seen.insert(var.clone()), self.regs[var])
+    }
+}
+
+/// Assigns registers to the variables of a clause.
+///
+/// The head and the first body goal form the first "chunk" of the clause, and
+/// each later body goal forms a chunk of its own. A variable that occurs in
+/// more than one chunk must survive a call, so it is given a permanent
+/// register; every other variable is given a temporary register above the
+/// argument registers used by the clause.
+fn allocate_registers(head: &Literal, body: &[Goal]) -> Registers {
+    let mut chunks = vec![head.1.iter().collect::<Vec<_>>()];
+    for (i, goal) in body.iter().enumerate() {
+        let terms = match *goal {
+            Goal::Literal(ref l) | Goal::Not(ref l) => l.1.iter().collect(),
+            Goal::Compare(ref l, _, ref r) => {
+                l.terms().into_iter().chain(r.terms()).collect()
+            }
+        };
+        if i == 0 {
+            chunks[0].extend(terms);
+        } else {
+            chunks.push(terms);
+        }
+    }
+
+    let mut order = Vec::new();
+    let mut chunk_counts = HashMap::new();
+    for chunk in &chunks {
+        let mut vars = HashSet::new();
+        for arg in chunk {
+            if let Term::Var(ref v, _) = **arg {
+                if !order.contains(v) {
+                    order.push(v.clone());
+                }
+                vars.insert(v.clone());
+            }
+        }
+        for v in vars {
+            *chunk_counts.entry(v).or_insert(0) += 1;
+        }
+    }
+
+    let max_arity = Some(head)
+        .into_iter()
+        .chain(body.iter().filter_map(Goal::literal))
+        .map(|l| l.1.len())
+        .max()
+        .unwrap_or(0);
+    let (mut x, mut y) = (max_arity, 0);
+    let mut regs = HashMap::new();
+    for v in order {
+        let reg = if chunk_counts[&v] > 1 {
+            y += 1;
+            Reg::Y(y - 1)
+        } else {
+            x += 1;
+            Reg::X(x - 1)
+        };
+        regs.insert(v, reg);
+    }
+    Registers {
+        regs,
+        seen: HashSet::new(),
+    }
+}
@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+
+use {Bindings, Clause, Goal, Literal, Name, Result, Term, Value, Variable};
+use wam::{Instruction, Procedure, Reg};
+use wam::compile::compile_query;
+
+/// A value in a register or heap cell.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Cell {
+    /// A reference to a heap cell. A cell that refers to itself is an unbound
+    /// variable.
+    Ref(usize),
+
+    /// A constant.
+    Con(Value),
+}
+
+/// An address in the code of a procedure, or in the code of the query if the
+/// procedure is `None`.
+#[derive(Clone, Copy, Debug)]
+struct Addr<'a> {
+    code: Option<&'a [Instruction]>,
+    offset: usize,
+}
+
+/// An environment, holding the permanent registers of a clause.
+#[derive(Debug)]
+struct Env<'a> {
+    prev: Option<usize>,
+    cp: Addr<'a>,
+    ys: Vec<Cell>,
+}
+
+/// A choice point, holding the state to restore when backtracking. A choice
+/// point left by an aggregate holds the tuples it has yet to try, in reverse
+/// order.
+#[derive(Debug)]
+struct Choice<'a> {
+    args: Vec<Cell>,
+    tuples: Vec<Vec<Value>>,
+    e: Option<usize>,
+    cp: Addr<'a>,
+    alt: Addr<'a>,
+    trail_len: usize,
+    heap_len: usize,
+    envs_len: usize,
+}
+
+/// The abstract machine, running a single query. Each call to `next` runs
+/// until the query's `Halt` instruction is reached, then returns the
+/// resulting answer; the next call resumes by backtracking.
+#[derive(Debug)]
+pub struct Machine<'a> {
+    procs: &'a HashMap<(Name, usize), Procedure>,
+    query: Vec<Instruction>,
+    vars: Vec<Variable>,
+
+    p: Addr<'a>,
+    cp: Addr<'a>,
+    e: Option<usize>,
+    num_args: usize,
+    regs: Vec<Cell>,
+    heap: Vec<Cell>,
+    trail: Vec<usize>,
+    envs: Vec<Env<'a>>,
+    choices: Vec<Choice<'a>>,
+
+    started: bool,
+}
+
+impl<'a> Machine<'a> {
+    /// Creates a machine to run the given query.
+    pub fn new(
+        procs: &'a HashMap<(Name, usize), Procedure>,
+        query: &Literal,
+    ) -> Machine<'a> {
+        let (query, vars) = compile_query(query);
+        let start = Addr {
+            code: None,
+            offset: 0,
+        };
+        Machine {
+            procs,
+            query,
+            vars,
+            p: start,
+            cp: start,
+            e: None,
+            num_args: 0,
+            regs: Vec::new(),
+            heap: Vec::new(),
+            trail: Vec::new(),
+            envs: Vec::new(),
+            choices: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// Runs until the query succeeds, returning true, or until every choice
+    /// point is exhausted, returning false.
+    fn run(&mut self) -> Result<bool> {
+        loop {
+            let instr = match self.p.code {
+                Some(code) => code[self.p.offset].clone(),
+                None => self.query[self.p.offset].clone(),
+            };
+            self.p.offset += 1;
+
+            let ok = match instr {
+                Instruction::GetVariable(reg, i) => {
+                    let arg = self.arg(i);
+                    self.set(reg, arg);
+                    true
+                }
+                Instruction::GetValue(reg, i) => {
+                    let (value, arg) = (self.get(reg), self.arg(i));
+                    self.unify(value, arg)
+                }
+                Instruction::GetConstant(name, i) => {
+                    let arg = self.arg(i);
+                    self.unify(arg, Cell::Con(name))
+                }
+                Instruction::PutVariable(reg, i) => {
+                    let var = self.new_var();
+                    self.set(reg, var.clone());
+                    self.set(Reg::X(i), var);
+                    true
+                }
+                Instruction::PutValue(reg, i) => {
+                    let value = self.get(reg);
+                    self.set(Reg::X(i), value);
+                    true
+                }
+                Instruction::PutConstant(name, i) => {
+                    self.set(Reg::X(i), Cell::Con(name));
+                    true
+                }
+                Instruction::Call(pred) => {
+                    self.cp = self.p;
+                    self.jump(pred)
+                }
+                Instruction::Execute(pred) => self.jump(pred),
+                Instruction::CallNot(pred) => {
+                    let goal = self.goal(pred);
+                    match Machine::new(self.procs, &goal).next() {
+                        None => true,
+                        Some(Ok(_)) => false,
+                        Some(Err(err)) => return Err(err),
+                    }
+                }
+                Instruction::Compare(l, op, r, vars) => {
+                    let mut bindings = Bindings::new();
+                    for &(ref v, reg, first) in &vars {
+                        if let (false, Cell::Con(n)) =
+                            (first, self.deref(self.get(reg)))
+                        {
+                            bindings.insert(v.clone(), n);
+                        }
+                    }
+                    let l = l.substitute(&bindings);
+                    let r = r.substitute(&bindings);
+                    match op.eval(&l, &r)? {
+                        Some(new) => {
+                            for (v, reg, first) in vars {
+                                if let Some(n) = new.get(&v) {
+                                    let con = Cell::Con(n.clone());
+                                    if first {
+                                        self.set(reg, con);
+                                    } else {
+                                        let value = self.get(reg);
+                                        self.unify(value, con);
+                                    }
+                                }
+                            }
+                            true
+                        }
+                        None => false,
+                    }
+                }
+                Instruction::Aggregate(rule) => {
+                    let goal = self.goal(rule.pred());
+                    let mut tuples = self.aggregate(&rule, &goal)?;
+                    tuples.reverse();
+                    match tuples.pop() {
+                        Some(tuple) => {
+                            if !tuples.is_empty() {
+                                self.choices.push(Choice {
+                                    args: self.regs[..self.num_args].to_vec(),
+                                    tuples,
+                                    e: self.e,
+                                    cp: self.cp,
+                                    alt: self.cp,
+                                    trail_len: self.trail.len(),
+                                    heap_len: self.heap.len(),
+                                    envs_len: self.envs.len(),
+                                });
+                            }
+                            self.p = self.cp;
+                            self.unify_args(tuple)
+                        }
+                        None => false,
+                    }
+                }
+                Instruction::Proceed => {
+                    self.p = self.cp;
+                    true
+                }
+                Instruction::Allocate(n) => {
+                    let ys = (0..n).map(|_| self.new_var()).collect();
+                    self.envs.push(Env {
+                        prev: self.e,
+                        cp: self.cp,
+                        ys,
+                    });
+                    self.e = Some(self.envs.len() - 1);
+                    true
+                }
+                Instruction::Deallocate => {
+                    let e = self.e.unwrap();
+                    self.cp = self.envs[e].cp;
+                    self.e = self.envs[e].prev;
+                    true
+                }
+                Instruction::TryMeElse(label) => {
+                    self.choices.push(Choice {
+                        args: self.regs[..self.num_args].to_vec(),
+                        tuples: Vec::new(),
+                        e: self.e,
+                        cp: self.cp,
+                        alt: Addr {
+                            code: self.p.code,
+                            offset: label,
+                        },
+                        trail_len: self.trail.len(),
+                        heap_len: self.heap.len(),
+                        envs_len: self.envs.len(),
+                    });
+                    true
+                }
+                Instruction::RetryMeElse(label) => {
+                    self.restore();
+                    self.choices.last_mut().unwrap().alt.offset = label;
+                    true
+                }
+                Instruction::TrustMe => {
+                    self.restore();
+                    self.choices.pop();
+                    true
+                }
+                Instruction::Halt => return Ok(true),
+            };
+
+            if !ok && !self.backtrack() {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Resumes from the most recent choice point, returning false if there
+    /// are none.
+    fn backtrack(&mut self) -> bool {
+        loop {
+            let tuple = match self.choices.last_mut() {
+                Some(choice) => {
+                    self.p = choice.alt;
+                    match choice.tuples.pop() {
+                        Some(tuple) => tuple,
+                        None => return true,
+                    }
+                }
+                None => return false,
+            };
+
+            // The choice point was left by an aggregate, so the next tuple is
+            // tried directly.
+            self.restore();
+            if self.choices.last().unwrap().tuples.is_empty() {
+                self.choices.pop();
+            }
+            if self.unify_args(tuple) {
+                return true;
+            }
+        }
+    }
+
+    /// Computes the tuples an aggregate clause derives for a goal.
+    fn aggregate(
+        &self,
+        rule: &Clause,
+        goal: &Literal,
+    ) -> Result<Vec<Vec<Value>>> {
+        let (bindings, body) = match rule.try_instantiate(rule.group_goal(goal))
+        {
+            Some(instance) => instance,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut solutions = vec![bindings];
+        for goal in body {
+            let mut next = Vec::new();
+            for mut bindings in solutions {
+                match goal.substitute(&bindings) {
+                    Goal::Literal(l) => {
+                        for new in Machine::new(self.procs, &l) {
+                            let mut bindings = bindings.clone();
+                            bindings.extend(new?);
+                            next.push(bindings);
+                        }
+                    }
+                    Goal::Not(l) => match Machine::new(self.procs, &l).next() {
+                        None => next.push(bindings),
+                        Some(Ok(_)) => {}
+                        Some(Err(err)) => return Err(err),
+                    },
+                    Goal::Compare(l, op, r) => {
+                        if let Some(new) = op.eval(&l, &r)? {
+                            bindings.extend(new);
+                            next.push(bindings);
+                        }
+                    }
+                }
+            }
+            solutions = next;
+        }
+
+        let tuples = rule.aggregate(solutions)?;
+        Ok(tuples
+            .into_iter()
+            .filter(|tuple| goal.try_instantiate_fact(tuple).is_some())
+            .collect())
+    }
+
+    /// Unifies the arguments with a tuple.
+    fn unify_args(&mut self, tuple: Vec<Value>) -> bool {
+        tuple.into_iter().enumerate().all(|(i, value)| {
+            let arg = self.arg(i);
+            self.unify(arg, Cell::Con(value))
+        })
+    }
+
+    /// Restores the state saved in the most recent choice point.
+    fn restore(&mut self) {
+        let choice = self.choices.last().unwrap();
+        self.num_args = choice.args.len();
+        self.regs[..choice.args.len()].clone_from_slice(&choice.args);
+        self.e = choice.e;
+        self.cp = choice.cp;
+        for addr in self.trail.drain(choice.trail_len..) {
+            self.heap[addr] = Cell::Ref(addr);
+        }
+        self.heap.truncate(choice.heap_len);
+        self.envs.truncate(choice.envs_len);
+    }
+
+    /// Jumps to the code for a predicate, returning false if it has none.
+    fn jump(&mut self, pred: (Name, usize)) -> bool {
+        self.num_args = pred.1;
+        if let Some(procedure) = self.procs.get(&pred) {
+            self.p = Addr {
+                code: Some(&procedure.code),
+                offset: 0,
+            };
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Reads the current arguments back into a literal for the predicate,
+    /// with a variable for each distinct unbound argument.
+    fn goal(&self, (name, arity): (Name, usize)) -> Literal {
+        let mut vars = HashMap::new();
+        let args = (0..arity)
+            .map(|i| match self.deref(self.arg(i)) {
+                Cell::Con(value) => Term::Value(value, None),
+                Cell::Ref(addr) => {
+                    let n = vars.len();
+                    let var = vars.entry(addr).or_insert_with(|| {
+                        Variable::new(&format!("_{}", n)).unwrap()
+                    });
+                    Term::Var(var.clone(), None)
+                }
+            })
+            .collect();
+        Literal(name, args, None)
+    }
+
+    fn arg(&self, i: usize) -> Cell {
+        self.get(Reg::X(i))
+    }
+
+    fn get(&self, reg: Reg) -> Cell {
+        match reg {
+            Reg::X(i) => self.regs[i].clone(),
+            Reg::Y(i) => self.envs[self.e.unwrap()].ys[i].clone(),
+        }
+    }
+
+    fn set(&mut self, reg: Reg, cell: Cell) {
+        match reg {
+            Reg::X(i) => {
+                if self.regs.len() <= i {
+                    self.regs.resize(i + 1, Cell::Ref(0));
+                }
+                self.regs[i] = cell;
+            }
+            Reg::Y(i) => self.envs[self.e.unwrap()].ys[i] = cell,
+        }
+    }
+
+    fn new_var(&mut self) -> Cell {
+        let addr = self.heap.len();
+        self.heap.push(Cell::Ref(addr));
+        Cell::Ref(addr)
+    }
+
+    fn deref(&self, mut cell: Cell) -> Cell {
+        while let Cell::Ref(addr) = cell {
+            if self.heap[addr] == Cell::Ref(addr) {
+                break;
+            }
+            cell = self.heap[addr].clone();
+        }
+        cell
+    }
+
+    fn bind(&mut self, addr: usize, cell: Cell) {
+        self.heap[addr] = cell;
+        self.trail.push(addr);
+    }
+
+    fn unify(&mut self, l: Cell, r: Cell) -> bool {
+        match (self.deref(l), self.deref(r)) {
+            (Cell::Ref(l), Cell::Ref(r)) => {
+                if l != r {
+                    self.bind(l, Cell::Ref(r));
+                }
+                true
+            }
+            (Cell::Ref(addr), con) | (con, Cell::Ref(addr)) => {
+                self.bind(addr, con);
+                true
+            }
+            (Cell::Con(l), Cell::Con(r)) => l == r,
+        }
+    }
+}
+
+impl<'a> Iterator for Machine<'a> {
+    type Item = Result<Bindings>;
+
+    fn next(&mut self) -> Option<Result<Bindings>> {
+        if self.started && !self.backtrack() {
+            return None;
+        }
+        self.started = true;
+        match self.run() {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(err) => {
+                // An error ends the query, rather than being retried.
+                self.choices.clear();
+                return Some(Err(err));
+            }
+        }
+
+        let e = self.e.unwrap();
+        let mut bindings = Bindings::new();
+        for (i, var) in self.vars.iter().enumerate() {
+            let cell = self.envs[e].ys[i].clone();
+            if let Cell::Con(name) = self.deref(cell) {
+                bindings.insert(var.clone(), name);
+            }
+        }
+        Some(Ok(bindings))
+    }
+}
@@ -1 +1,473 @@
+//! An interpreter based on the Warren Abstract Machine.
+//!
+//! Clauses are compiled to instructions for a simplified WAM, following
+//! [Warren's Abstract Machine: A Tutorial
+//! Reconstruction](http://wambook.sourceforge.net/). Since Datalog has no
+//! compound terms, there are no structure or list cells on the heap, and the
+//! `get_*` instructions perform all of the unification that the `unify_*`
+//! instructions would handle for arguments of a structure.
 
+mod compile;
+mod machine;
+mod print;
+
+use std::collections::{HashMap, HashSet};
+use std::iter::{empty, FromIterator};
+
+use sparkly::{Doc, Sparkly};
+
+use {Bindings, Clause, CmpOp, Declaration, ErrorKind, Expr, Interpeter,
+     Literal, Name, Result, Value, Variable};
+use schema::Schema;
+use styles::PUNCTUATION;
+use util::{arity, check_assertion, check_declaration, remove_where};
+
+use self::compile::compile_clause;
+use self::machine::Machine;
+
+/// An interpreter that compiles clauses to bytecode for a Warren Abstract
+/// Machine, then runs queries on it.
+///
+/// Like Prolog, clauses are tried in the order they were asserted, with
+/// depth-first search; as with the `NaiveInterpreter`, left-recursive rules
+/// will not terminate.
+#[derive(Debug)]
+pub struct WamInterpreter {
+    procs: HashMap<(Name, usize), Procedure>,
+    schema: Schema,
+}
+
+impl Default for WamInterpreter {
+    fn default() -> WamInterpreter {
+        WamInterpreter::new()
+    }
+}
+
+impl WamInterpreter {
+    /// Creates a new instance of `WamInterpreter`.
+    pub fn new() -> WamInterpreter {
+        WamInterpreter {
+            procs: HashMap::new(),
+            schema: Schema::default(),
+        }
+    }
+
+    /// Returns a listing of the code compiled for the given predicate, or
+    /// `None` if no clauses exist for it.
+    pub fn disassemble(&self, pred: &(Name, usize)) -> Option<Doc> {
+        let procedure = try_opt!(self.procs.get(pred));
+        let (ref name, arity) = *pred;
+        let label = name.to_doc()
+            .append(Doc::text("/", PUNCTUATION))
+            .append(Doc::from(arity.to_string()))
+            .append(Doc::text(":", PUNCTUATION));
+        let width = (procedure.code.len() - 1).to_string().len();
+        let code = procedure.code.iter().enumerate().map(|(i, instr)| {
+            Doc::from(format!("{:>1$}", i, width))
+                .append(Doc::text(":", PUNCTUATION))
+                .append(Doc::nbsp())
+                .append(instr.to_doc())
+        });
+        Some(label.append(Doc::line().append(Doc::lines(code)).nest(4)))
+    }
+}
+
+/// The clauses for a single predicate, and the code compiled from them.
+#[derive(Debug)]
+pub struct Procedure {
+    clauses: Vec<Clause>,
+    facts: HashSet<Vec<Value>>,
+    code: Vec<Instruction>,
+}
+
+impl Procedure {
+    /// Creates a procedure with no clauses.
+    fn new() -> Procedure {
+        Procedure {
+            clauses: Vec::new(),
+            facts: HashSet::new(),
+            code: Vec::new(),
+        }
+    }
+
+    /// Adds a clause to the end of the procedure.
+    ///
+    /// Rather than recompiling the whole procedure, this patches the choice
+    /// instruction of the last clause, so loading many facts for one
+    /// predicate stays linear.
+    fn push(&mut self, clause: Clause) {
+        self.facts.extend(clause.as_fact());
+        let mut code = compile_clause(&clause);
+        match self.clauses.len() {
+            0 => {}
+            1 => {
+                self.code.insert(0, Instruction::TryMeElse(0));
+                let label = self.code.len();
+                self.code[0] = Instruction::TryMeElse(label);
+                self.code.push(Instruction::TrustMe);
+            }
+            _ => {
+                let label = self.code.len();
+                let last = self.code
+                    .iter()
+                    .rposition(|i| *i == Instruction::TrustMe)
+                    .unwrap();
+                self.code[last] = Instruction::RetryMeElse(label);
+                self.code.push(Instruction::TrustMe);
+            }
+        }
+        self.code.append(&mut code);
+        self.clauses.push(clause);
+    }
+}
+
+impl IntoIterator for Procedure {
+    type Item = Clause;
+    type IntoIter = ::std::vec::IntoIter<Clause>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.clauses.into_iter()
+    }
+}
+
+impl FromIterator<Clause> for Procedure {
+    fn from_iter<I: IntoIterator<Item = Clause>>(clauses: I) -> Procedure {
+        let mut procedure = Procedure::new();
+        for clause in clauses {
+            procedure.push(clause);
+        }
+        procedure
+    }
+}
+
+/// A register.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Reg {
+    /// A temporary register, which doubles as an argument register.
+    X(usize),
+
+    /// A permanent register, stored in the current environment.
+    Y(usize),
+}
+
+/// A WAM instruction. Arguments are passed in the argument registers, which
+/// are the low-numbered `X` registers; the `usize` operands of the `get_*`
+/// and `put_*` instructions are argument register numbers.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Instruction {
+    /// Stores an argument in a register, for the first occurrence of a
+    /// variable in a clause head.
+    GetVariable(Reg, usize),
+
+    /// Unifies an argument with a register, for later occurrences of a
+    /// variable in a clause head.
+    GetValue(Reg, usize),
+
+    /// Unifies an argument with a constant.
+    GetConstant(Value, usize),
+
+    /// Stores a fresh variable in both a register and an argument, for the
+    /// first occurrence of a variable in a body literal.
+    PutVariable(Reg, usize),
+
+    /// Copies a register to an argument.
+    PutValue(Reg, usize),
+
+    /// Stores a constant in an argument.
+    PutConstant(Value, usize),
+
+    /// Calls a predicate, returning to the next instruction.
+    Call((Name, usize)),
+
+    /// Jumps to a predicate, for the last literal of a body.
+    Execute((Name, usize)),
+
+    /// Runs a separate query for a predicate on the current arguments,
+    /// failing if it has any answers and continuing otherwise. This
+    /// implements negation as failure.
+    CallNot((Name, usize)),
+
+    /// Evaluates a comparison on the variables in the given registers,
+    /// failing if it does not hold. The flag for each variable is whether
+    /// this is its first use, in which case the register is uninitialized
+    /// and an equality stores the variable's value in it.
+    Compare(Expr, CmpOp, Expr, Vec<(Variable, Reg, bool)>),
+
+    /// Solves the body of a clause with aggregates in its head, running a
+    /// separate query for each body literal, then unifies the arguments with
+    /// each of the tuples it computes in turn. A clause with aggregates is
+    /// compiled to this single instruction.
+    Aggregate(Clause),
+
+    /// Returns to the continuation.
+    Proceed,
+
+    /// Pushes an environment with the given number of permanent registers.
+    Allocate(usize),
+
+    /// Pops the current environment.
+    Deallocate,
+
+    /// Pushes a choice point which resumes at the given label.
+    TryMeElse(usize),
+
+    /// Updates the current choice point to resume at the given label.
+    RetryMeElse(usize),
+
+    /// Pops the current choice point.
+    TrustMe,
+
+    /// Reports the bindings of the query's variables as an answer.
+    Halt,
+}
+
+impl Interpeter for WamInterpreter {
+    fn run_declaration(&mut self, decl: Declaration) -> Result<()> {
+        // Facts are stored as clauses, like rules.
+        let clauses = self.procs.values().flat_map(|p| p.clauses.iter());
+        check_declaration(&mut self.schema, empty(), clauses, decl)
+    }
+
+    fn run_assertion(&mut self, clause: Clause) -> Result<()> {
+        let clauses = self.procs.values().flat_map(|p| p.clauses.iter());
+        let clause = check_assertion(&self.schema, clauses, clause)?;
+        let procedure = self.procs
+            .entry(clause.pred())
+            .or_insert_with(Procedure::new);
+        let duplicate = clause
+            .as_fact()
+            .map_or(false, |fact| procedure.facts.contains(&fact));
+        if !duplicate {
+            procedure.push(clause);
+        }
+        Ok(())
+    }
+
+    fn run_retraction(&mut self, clause: Clause) -> Result<()> {
+        // The kept clauses are recompiled into a new procedure.
+        let clause = clause.schedule();
+        let removed = remove_where(&mut self.procs, clause.pred(), |c| {
+            clause.retracts(c)
+        });
+        if removed.is_empty() {
+            Err(ErrorKind::NoSuchClause(clause).into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn run_query<'a>(
+        &'a self,
+        query: Literal,
+    ) -> Box<'a + Iterator<Item = Result<Bindings>>> {
+        Box::new(Machine::new(&self.procs, &query))
+    }
+
+    fn arity(&self, name: &Name) -> Option<usize> {
+        arity(&self.schema, self.procs.keys(), name)
+    }
+}
+
+#[test]
+fn queries() {
+    use Program;
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "edge(a, b). edge(b, c). edge(c, d).\n",
+        "path(X, Y) :- edge(X, Y).\n",
+        "path(X, Y) :- edge(X, Z), path(Z, Y).\n",
+        "loop(X) :- edge(X, X).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = WamInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    assert_eq!(query(&interpreter, "path(a, X)?"), set(&["b", "c", "d"]));
+    assert_eq!(query(&interpreter, "path(X, d)?"), set(&["a", "b", "c"]));
+    assert_eq!(query(&interpreter, "path(a, d)?"), set(&[""]));
+    assert_eq!(query(&interpreter, "path(d, a)?"), set(&[]));
+    assert_eq!(query(&interpreter, "loop(X)?"), set(&[]));
+    assert_eq!(query(&interpreter, "nonexistent(X)?"), set(&[]));
+}
+
+#[test]
+fn negation() {
+    use Program;
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "edge(a, b). edge(b, c). edge(d, e).\n",
+        "node(a). node(b). node(c). node(d). node(e).\n",
+        "reach(X, Y) :- edge(X, Y).\n",
+        "reach(X, Y) :- edge(X, Z), reach(Z, Y).\n",
+        "unreach(X, Y) :- node(X), node(Y), not reach(X, Y).\n",
+        "entered(X) :- edge(Y, X).\n",
+        "source(X) :- node(X), not entered(X).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = WamInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    assert_eq!(query(&interpreter, "unreach(a, X)?"), set(&["a", "d", "e"]));
+    assert_eq!(query(&interpreter, "unreach(a, c)?"), set(&[]));
+    assert_eq!(query(&interpreter, "source(X)?"), set(&["a", "d"]));
+
+    let source = interpreter
+        .disassemble(&(Name::new("source").unwrap(), 1))
+        .unwrap();
+    assert_eq!(
+        source.display_opts(80, false).to_string(),
+        concat!(
+            "source/1:\n",
+            "    0: allocate 1\n",
+            "    1: get_variable Y0, A0\n",
+            "    2: put_value Y0, A0\n",
+            "    3: call node/1\n",
+            "    4: put_value Y0, A0\n",
+            "    5: call_not entered/1\n",
+            "    6: deallocate\n",
+            "    7: proceed",
+        )
+    );
+}
+
+#[test]
+fn disassemble() {
+    use Program;
+
+    let program: Program = concat!(
+        "edge(a, b). edge(b, c). edge(a, b).\n",
+        "path(X, Y) :- edge(X, Z), path(Z, Y).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = WamInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    let disassemble = |name: &str, arity| {
+        let pred = (Name::new(name).unwrap(), arity);
+        interpreter
+            .disassemble(&pred)
+            .map(|doc| doc.display_opts(80, false).to_string())
+    };
+
+    assert_eq!(
+        disassemble("edge", 2).unwrap(),
+        concat!(
+            "edge/2:\n",
+            "    0: try_me_else 4\n",
+            "    1: get_constant a, A0\n",
+            "    2: get_constant b, A1\n",
+            "    3: proceed\n",
+            "    4: trust_me\n",
+            "    5: get_constant b, A0\n",
+            "    6: get_constant c, A1\n",
+            "    7: proceed",
+        )
+    );
+    assert_eq!(
+        disassemble("path", 2).unwrap(),
+        concat!(
+            "path/2:\n",
+            "    0: allocate 2\n",
+            "    1: get_variable X2, A0\n",
+            "    2: get_variable Y0, A1\n",
+            "    3: put_value X2, A0\n",
+            "    4: put_variable Y1, A1\n",
+            "    5: call edge/2\n",
+            "    6: put_value Y1, A0\n",
+            "    7: put_value Y0, A1\n",
+            "    8: deallocate\n",
+            "    9: execute path/2",
+        )
+    );
+    assert_eq!(disassemble("nonexistent", 0), None);
+}
+
+#[test]
+fn arithmetic() {
+    use Program;
+    use util::{set, try_query};
+
+    let program: Program = concat!(
+        "num(1). num(2). num(3). word(x).\n",
+        "next(X, Y) :- num(X), X < 3, Y = X + 1.\n",
+        "double(X, Y) :- num(X), num(Y), X * 2 = Y.\n",
+        "next_word(X, Y) :- word(X), Y = X + 1.\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = WamInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    let query = |s: &str| try_query(&interpreter, s);
+
+    assert_eq!(query("next(1, X)?").unwrap(), set(&["2"]));
+    assert_eq!(query("next(X, 3)?").unwrap(), set(&["2"]));
+    assert_eq!(query("next(3, X)?").unwrap(), set(&[]));
+    assert_eq!(query("double(X, 2)?").unwrap(), set(&["1"]));
+    assert_eq!(query("double(2, Y)?").unwrap(), set(&[]));
+    match *query("next_word(X, Y)?").unwrap_err().kind() {
+        ErrorKind::NotANumber(ref value) => {
+            assert_eq!(value, &Value::new("x").unwrap())
+        }
+        ref err => panic!("unexpected error {}", err),
+    }
+
+    let next = interpreter
+        .disassemble(&(Name::new("next").unwrap(), 2))
+        .unwrap();
+    assert_eq!(
+        next.display_opts(80, false).to_string(),
+        concat!(
+            "next/2:\n",
+            "    0: allocate 2\n",
+            "    1: get_variable Y0, A0\n",
+            "    2: get_variable Y1, A1\n",
+            "    3: put_value Y0, A0\n",
+            "    4: call num/1\n",
+            "    5: compare X < 3, X: Y0\n",
+            "    6: compare Y = X + 1, Y: Y1, X: Y0\n",
+            "    7: deallocate\n",
+            "    8: proceed",
+        )
+    );
+}
+
+#[test]
+fn aggregates() {
+    use Program;
+    use util::{query, set};
+
+    let program: Program = concat!(
+        "edge(a, b). edge(a, c). edge(b, c). edge(d, a).\n",
+        "degree(X, count<Y>) :- edge(X, Y).\n",
+        "degree(c, 0).\n",
+        "busy(X) :- degree(X, N), N > 1.\n",
+        "lowest(min<N>) :- degree(X, N).\n",
+    ).parse()
+        .unwrap();
+    let mut interpreter = WamInterpreter::new();
+    interpreter.load_program(program).unwrap();
+
+    assert_eq!(query(&interpreter, "degree(a, N)?"), set(&["2"]));
+    assert_eq!(query(&interpreter, "degree(X, 1)?"), set(&["b", "d"]));
+    assert_eq!(query(&interpreter, "degree(c, N)?"), set(&["0"]));
+    assert_eq!(query(&interpreter, "busy(X)?"), set(&["a"]));
+    assert_eq!(query(&interpreter, "lowest(N)?"), set(&["0"]));
+
+    let degree = interpreter
+        .disassemble(&(Name::new("degree").unwrap(), 2))
+        .unwrap();
+    assert_eq!(
+        degree.display_opts(80, false).to_string(),
+        concat!(
+            "degree/2:\n",
+            "    0: try_me_else 2\n",
+            "    1: aggregate degree(X, count<Y>) :- edge(X, Y)\n",
+            "    2: trust_me\n",
+            "    3: get_constant c, A0\n",
+            "    4: get_constant 0, A1\n",
+            "    5: proceed",
+        )
+    );
+}
@@ -0,0 +1,88 @@
+use sparkly::{Doc, Sparkly};
+
+use Name;
+use styles::PUNCTUATION;
+use wam::{Instruction, Reg};
+
+impl_Display_for_Sparkly!(Reg);
+impl Sparkly for Reg {
+    fn to_doc(&self) -> Doc {
+        match *self {
+            Reg::X(i) => Doc::from(format!("X{}", i)),
+            Reg::Y(i) => Doc::from(format!("Y{}", i)),
+        }
+    }
+}
+
+impl_Display_for_Sparkly!(Instruction);
+impl Sparkly for Instruction {
+    fn to_doc(&self) -> Doc {
+        fn op(name: &'static str, args: Vec<Doc>) -> Doc {
+            let args = Doc::text(",", PUNCTUATION)
+                .append(Doc::nbsp())
+                .join(args);
+            Doc::from(name).append(Doc::nbsp()).append(args)
+        }
+        fn arg(i: usize) -> Doc {
+            Doc::from(format!("A{}", i))
+        }
+        fn pred(&(ref name, arity): &(Name, usize)) -> Doc {
+            name.to_doc()
+                .append(Doc::text("/", PUNCTUATION))
+                .append(Doc::from(arity.to_string()))
+        }
+        fn label(l: usize) -> Doc {
+            Doc::from(l.to_string())
+        }
+
+        match *self {
+            Instruction::GetVariable(r, i) => {
+                op("get_variable", vec![r.to_doc(), arg(i)])
+            }
+            Instruction::GetValue(r, i) => {
+                op("get_value", vec![r.to_doc(), arg(i)])
+            }
+            Instruction::GetConstant(ref n, i) => {
+                op("get_constant", vec![n.to_doc(), arg(i)])
+            }
+            Instruction::PutVariable(r, i) => {
+                op("put_variable", vec![r.to_doc(), arg(i)])
+            }
+            Instruction::PutValue(r, i) => {
+                op("put_value", vec![r.to_doc(), arg(i)])
+            }
+            Instruction::PutConstant(ref n, i) => {
+                op("put_constant", vec![n.to_doc(), arg(i)])
+            }
+            Instruction::Call(ref p) => op("call", vec![pred(p)]),
+            Instruction::Execute(ref p) => op("execute", vec![pred(p)]),
+            Instruction::CallNot(ref p) => op("call_not", vec![pred(p)]),
+            Instruction::Compare(ref l, cmp, ref r, ref vars) => {
+                let goal = l.to_doc()
+                    .append(Doc::nbsp())
+                    .append(cmp.to_doc())
+                    .append(Doc::nbsp())
+                    .append(r.to_doc());
+                let vars = vars.iter().map(|&(ref v, r, _)| {
+                    v.to_doc()
+                        .append(Doc::text(":", PUNCTUATION))
+                        .append(Doc::nbsp())
+                        .append(r.to_doc())
+                });
+                op("compare", Some(goal).into_iter().chain(vars).collect())
+            }
+            Instruction::Aggregate(ref c) => op("aggregate", vec![c.to_doc()]),
+            Instruction::Proceed => Doc::from("proceed"),
+            Instruction::Allocate(n) => {
+                op("allocate", vec![Doc::from(n.to_string())])
+            }
+            Instruction::Deallocate => Doc::from("deallocate"),
+            Instruction::TryMeElse(l) => op("try_me_else", vec![label(l)]),
+            Instruction::RetryMeElse(l) => {
+                op("retry_me_else", vec![label(l)])
+            }
+            Instruction::TrustMe => Doc::from("trust_me"),
+            Instruction::Halt => Doc::from("halt"),
+        }
+    }
+}